@@ -0,0 +1,286 @@
+use crate::{
+    clock::{Clock, Gates, NoteEvent},
+    waveform::WhiteNoise,
+};
+
+///
+/// Keys held at once beyond this are ignored, so that holding them never
+/// allocates.
+///
+pub const MAX_HELD: usize = 16;
+
+pub const MAX_OCTAVES: usize = 4;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ArpMode {
+    #[default]
+    Up,
+    Down,
+    /// Up then back down, without repeating the top and bottom notes.
+    UpDown,
+    Random,
+    /// Every held note at once, climbing an octave a step through the range.
+    Chord,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ArpSettings {
+    pub enabled: bool,
+    pub mode: ArpMode,
+    /// Octaves the pattern climbs through, from `1` to `MAX_OCTAVES`.
+    pub octaves: usize,
+    /// Steps per quarter-note beat, so `4.0` plays sixteenths.
+    pub division: f64,
+    /// Fraction of the step each note is held for, up to `1.0`.
+    pub gate: f64,
+    /// Fraction of a step every second step is delayed by, up to `0.5`.
+    pub swing: f64,
+}
+
+///
+/// Replays the keys held as a pattern at the tempo, starting the moment the
+/// first key goes down.
+///
+#[derive(Clone, Debug)]
+pub struct Arpeggiator {
+    pub settings: ArpSettings,
+    /// Notes held with their velocities, lowest first.
+    held: Vec<(u8, u8)>,
+    clock: Clock,
+    gates: Gates,
+    random: WhiteNoise,
+    /// Steps played since the first key went down.
+    position: usize,
+}
+
+impl Default for ArpSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: ArpMode::default(),
+            octaves: 1,
+            division: 4.0,
+            gate: 0.5,
+            swing: 0.0,
+        }
+    }
+}
+
+impl Arpeggiator {
+    pub fn new(settings: ArpSettings, sample_rate: f64) -> Self {
+        Self {
+            settings,
+            held: Vec::with_capacity(MAX_HELD),
+            clock: Clock::new(sample_rate),
+            gates: Gates::new(),
+            random: WhiteNoise::default(),
+            position: 0,
+        }
+    }
+
+    ///
+    /// Notes held with their velocities, lowest first.
+    ///
+    pub fn held(&self) -> &[(u8, u8)] {
+        &self.held
+    }
+
+    ///
+    /// A velocity of zero is a note-off, as in MIDI.
+    ///
+    pub fn note_on(&mut self, note: u8, velocity: u8) {
+        if velocity == 0 {
+            return self.note_off(note);
+        }
+
+        if self.held.is_empty() {
+            self.clock.reset();
+            self.position = 0;
+        }
+
+        match self.held.binary_search_by_key(&note, |(held, _)| *held) {
+            Ok(index) => self.held[index].1 = velocity,
+            Err(index) if self.held.len() < MAX_HELD => self.held.insert(index, (note, velocity)),
+            Err(_) => {}
+        }
+    }
+
+    pub fn note_off(&mut self, note: u8) {
+        self.held.retain(|(held, _)| *held != note);
+    }
+
+    pub fn release_all(&mut self) {
+        self.held.clear();
+    }
+
+    pub fn set_tempo(&mut self, tempo: f64) {
+        self.clock.set_tempo(tempo);
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.clock.set_sample_rate(sample_rate);
+    }
+
+    ///
+    /// Moves on a sample, passing any notes starting or ending on it to
+    /// `play`. Notes still sounding when the last key is released play out
+    /// their gate.
+    ///
+    pub fn tick(&mut self, play: &mut impl FnMut(NoteEvent)) {
+        let ArpSettings {
+            enabled,
+            mode,
+            division,
+            gate,
+            swing,
+            ..
+        } = self.settings;
+
+        if !enabled {
+            self.gates.close_all(play);
+            return;
+        }
+
+        self.gates.tick(play);
+
+        if self.held.is_empty() || self.clock.tick(division, swing).is_none() {
+            return;
+        }
+
+        let samples = (gate.clamp(0.0, 1.0) * self.clock.step_length(division)).round() as usize;
+        let octaves = self.settings.octaves.clamp(1, MAX_OCTAVES);
+
+        if mode == ArpMode::Chord {
+            let octave = self.position % octaves;
+
+            for (note, velocity) in &self.held {
+                if let Some(note) = transpose(*note, octave) {
+                    self.gates.open(note, *velocity, samples, play);
+                }
+            }
+        } else {
+            let count = self.held.len() * octaves;
+            let index = match mode {
+                ArpMode::Up => self.position % count,
+                ArpMode::Down => count - 1 - self.position % count,
+                ArpMode::UpDown if count > 1 => {
+                    let cycle = self.position % (2 * count - 2);
+
+                    match cycle < count {
+                        true => cycle,
+                        false => 2 * count - 2 - cycle,
+                    }
+                }
+                ArpMode::Random => {
+                    let chance = (self.random.next_sample() + 1.0) / 2.0;
+
+                    ((chance * count as f64) as usize).min(count - 1)
+                }
+                _ => 0,
+            };
+
+            let (note, velocity) = self.held[index % self.held.len()];
+
+            if let Some(note) = transpose(note, index / self.held.len()) {
+                self.gates.open(note, velocity, samples, play);
+            }
+        }
+
+        self.position += 1;
+    }
+}
+
+///
+/// `note` up `octaves` octaves, if that is still a MIDI note.
+///
+fn transpose(note: u8, octaves: usize) -> Option<u8> {
+    let note = note as usize + 12 * octaves;
+
+    (note <= 127).then_some(note as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///
+    /// Notes started over the first `steps` steps, in order.
+    ///
+    fn arpeggiate(settings: ArpSettings, chord: &[u8], steps: usize) -> Vec<u8> {
+        let mut arpeggiator = Arpeggiator::new(settings, 1_000.0);
+        let mut notes = Vec::new();
+
+        for note in chord {
+            arpeggiator.note_on(*note, 100);
+        }
+
+        // Sixteenths at 120 BPM are 125 samples at 1 kHz.
+        for _ in 0..steps * 125 {
+            arpeggiator.tick(&mut |event| {
+                if let NoteEvent::On { note, .. } = event {
+                    notes.push(note);
+                }
+            });
+        }
+
+        notes
+    }
+
+    fn settings(mode: ArpMode, octaves: usize) -> ArpSettings {
+        ArpSettings {
+            enabled: true,
+            mode,
+            octaves,
+            ..ArpSettings::default()
+        }
+    }
+
+    #[test]
+    fn walks_held_notes_across_octaves() {
+        let chord = [64, 60, 67];
+
+        assert!(arpeggiate(settings(ArpMode::Up, 2), &chord, 7) == [60, 64, 67, 72, 76, 79, 60]);
+        assert!(arpeggiate(settings(ArpMode::Down, 1), &chord, 4) == [67, 64, 60, 67]);
+        assert!(arpeggiate(settings(ArpMode::UpDown, 1), &chord, 6) == [60, 64, 67, 64, 60, 64]);
+        assert!(arpeggiate(settings(ArpMode::Chord, 2), &chord, 2) == [60, 64, 67, 72, 76, 79]);
+    }
+
+    #[test]
+    fn random_mode_stays_in_range() {
+        let notes = arpeggiate(settings(ArpMode::Random, 2), &[60, 64], 200);
+
+        assert!(notes.len() == 200);
+        assert!(notes.iter().all(|note| [60, 64, 72, 76].contains(note)));
+        assert!([60, 64, 72, 76].iter().all(|note| notes.contains(note)));
+    }
+
+    #[test]
+    fn stops_when_keys_are_released() {
+        let mut arpeggiator = Arpeggiator::new(settings(ArpMode::Up, 1), 1_000.0);
+        let mut events = Vec::new();
+
+        arpeggiator.note_on(60, 100);
+
+        for n in 0..300 {
+            if n == 10 {
+                arpeggiator.note_off(60);
+            }
+
+            arpeggiator.tick(&mut |event| events.push((n, event)));
+        }
+
+        assert!(
+            events
+                == [
+                    (
+                        0,
+                        NoteEvent::On {
+                            note: 60,
+                            velocity: 100
+                        }
+                    ),
+                    (63, NoteEvent::Off { note: 60 }),
+                ]
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use std::f64::consts::{FRAC_PI_4, SQRT_2};
+
+///
+/// What a device channel plays from the engine's stereo bus.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bus {
+    Left,
+    Right,
+    /// Both sides mixed, for mono and centre speakers.
+    Mid,
+    Silent,
+}
+
+///
+/// Maps the engine's stereo output onto however many channels the device
+/// has, one `Bus` per channel in device order.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChannelLayout {
+    channels: Vec<Bus>,
+}
+
+///
+/// Constant-power pan law, from `-1.0` (hard left) to `1.0` (hard right).
+/// The gains are scaled by √2 so that a centred voice keeps unity gain on
+/// both sides, matching the mono engine.
+///
+pub fn pan_gains(pan: f64) -> [f64; 2] {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * FRAC_PI_4;
+
+    [angle.cos() * SQRT_2, angle.sin() * SQRT_2]
+}
+
+///
+/// Scales the side (difference) signal of a stereo frame, from `0.0`
+/// (mono) through `1.0` (unchanged) to `2.0` (extra wide).
+///
+pub fn widen([left, right]: [f64; 2], width: f64) -> [f64; 2] {
+    let mid = (left + right) / 2.0;
+    let side = (left - right) / 2.0 * width.clamp(0.0, 2.0);
+
+    [mid + side, mid - side]
+}
+
+impl ChannelLayout {
+    pub fn new(channels: Vec<Bus>) -> Self {
+        Self { channels }
+    }
+
+    ///
+    /// The usual speaker order for common channel counts (mono, stereo,
+    /// LCR, quad, 5.1 and 7.1). Rear and side pairs repeat the front pair,
+    /// centres get the mid signal, the LFE is left silent, and channels
+    /// beyond a known layout are silent too.
+    ///
+    pub fn for_channels(count: usize) -> Self {
+        use Bus::*;
+
+        let channels = match count {
+            0 => vec![],
+            1 => vec![Mid],
+            2 => vec![Left, Right],
+            3 => vec![Left, Right, Mid],
+            4 => vec![Left, Right, Left, Right],
+            6 => vec![Left, Right, Mid, Silent, Left, Right],
+            8 => vec![Left, Right, Mid, Silent, Left, Right, Left, Right],
+            count => [Left, Right]
+                .into_iter()
+                .chain(std::iter::repeat(Silent))
+                .take(count)
+                .collect(),
+        };
+
+        Self { channels }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    ///
+    /// One stereo frame spread over every device channel.
+    ///
+    pub fn route(&self, [left, right]: [f32; 2]) -> impl Iterator<Item = f32> + '_ {
+        self.channels.iter().map(move |bus| match bus {
+            Bus::Left => left,
+            Bus::Right => right,
+            Bus::Mid => (left + right) / 2.0,
+            Bus::Silent => 0.0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pan_keeps_constant_power() {
+        assert!(pan_gains(-1.0)[1].abs() < 1e-12);
+        assert!(pan_gains(1.0)[0].abs() < 1e-12);
+        assert!(pan_gains(0.0).iter().all(|gain| (gain - 1.0).abs() < 1e-12));
+
+        for i in -10..=10 {
+            let [left, right] = pan_gains(i as f64 / 10.0);
+
+            assert!((left * left + right * right - 2.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn width_scales_side_only() {
+        let frame = [1.0, 0.5];
+
+        assert!(widen(frame, 1.0) == frame);
+        assert!(widen(frame, 0.0) == [0.75, 0.75]);
+        assert!(widen(frame, 2.0) == [1.25, 0.25]);
+    }
+
+    #[test]
+    fn routes_buses_to_device_channels() {
+        let route = |count, frame| {
+            ChannelLayout::for_channels(count)
+                .route(frame)
+                .collect::<Vec<f32>>()
+        };
+
+        assert!(route(1, [1.0, 0.0]) == [0.5]);
+        assert!(route(2, [1.0, 0.0]) == [1.0, 0.0]);
+        assert!(route(6, [1.0, 0.0]) == [1.0, 0.0, 0.5, 0.0, 1.0, 0.0]);
+        assert!(route(5, [1.0, 0.5]) == [1.0, 0.5, 0.0, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,245 @@
+use std::f64::consts::TAU;
+
+use crate::{
+    delay::DelayLine,
+    effects::Effect,
+    smoothing::{Ramp, Smoothed},
+};
+
+///
+/// Longest delay the sweep can reach, in seconds.
+///
+const MAX_CHORUS_DELAY: f64 = 0.05;
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ChorusSettings {
+    /// Sweep rate in Hz.
+    pub rate: f64,
+    /// How far the delay sweeps either side of `delay`, in seconds.
+    pub depth: f64,
+    /// Centre of the sweep, in seconds.
+    pub delay: f64,
+    /// Fed back from the output, from `-1.0` to `1.0`. Short delays with
+    /// plenty of feedback give a flanger.
+    pub feedback: f64,
+}
+
+impl Default for ChorusSettings {
+    fn default() -> Self {
+        Self {
+            rate: 0.8,
+            depth: 0.003,
+            delay: 0.015,
+            feedback: 0.0,
+        }
+    }
+}
+
+impl ChorusSettings {
+    pub fn flanger() -> Self {
+        Self {
+            rate: 0.25,
+            depth: 0.0015,
+            delay: 0.002,
+            feedback: 0.7,
+        }
+    }
+}
+
+///
+/// A delay swept by a sine, a quarter cycle apart on each side so that the
+/// copies spread across the stereo field.
+///
+#[derive(Clone, Debug)]
+pub struct Chorus {
+    pub settings: ChorusSettings,
+    sample_rate: f64,
+    lines: [DelayLine; 2],
+    /// Position in the sweep, from `0.0` to `1.0`.
+    phase: f64,
+    /// Rate, depth, delay and feedback, gliding so that moving the read
+    /// head never jumps it.
+    smoothed: [Smoothed; 4],
+}
+
+impl Chorus {
+    pub fn new(settings: ChorusSettings, sample_rate: f64) -> Self {
+        Self {
+            settings,
+            sample_rate,
+            lines: std::array::from_fn(|_| DelayLine::new(Self::length(sample_rate))),
+            phase: 0.0,
+            smoothed: Self::targets(settings).map(|value| Smoothed::new(value, sample_rate)),
+        }
+    }
+
+    ///
+    /// How changes to the settings are smoothed.
+    ///
+    pub fn set_smoothing(&mut self, time: f64, ramp: Ramp) {
+        for smoothed in &mut self.smoothed {
+            smoothed.configure(time, ramp);
+        }
+    }
+
+    fn targets(settings: ChorusSettings) -> [f64; 4] {
+        let ChorusSettings {
+            rate,
+            depth,
+            delay,
+            feedback,
+        } = settings;
+
+        [rate, depth, delay, feedback.clamp(-0.95, 0.95)]
+    }
+
+    fn length(sample_rate: f64) -> usize {
+        (MAX_CHORUS_DELAY * sample_rate).ceil() as usize + 2
+    }
+}
+
+impl Effect for Chorus {
+    fn process(&mut self, block: &mut [[f64; 2]]) {
+        for (smoothed, target) in self.smoothed.iter_mut().zip(Self::targets(self.settings)) {
+            smoothed.set_target(target);
+        }
+
+        for frame in block.iter_mut() {
+            let [rate, depth, delay, feedback] =
+                self.smoothed.each_mut().map(Smoothed::next_sample);
+
+            for (side, (line, sample)) in self.lines.iter_mut().zip(frame.iter_mut()).enumerate() {
+                let sweep = (TAU * (self.phase + side as f64 * 0.25)).sin();
+                let wet = line.read((delay + depth * sweep) * self.sample_rate);
+
+                line.write(*sample + wet * feedback);
+                *sample = wet;
+            }
+
+            self.phase = (self.phase + rate / self.sample_rate).fract();
+        }
+    }
+
+    fn reset(&mut self) {
+        for line in &mut self.lines {
+            line.clear();
+        }
+
+        self.phase = 0.0;
+
+        for (smoothed, target) in self.smoothed.iter_mut().zip(Self::targets(self.settings)) {
+            smoothed.reset(target);
+        }
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.lines = std::array::from_fn(|_| DelayLine::new(Self::length(sample_rate)));
+
+        for smoothed in &mut self.smoothed {
+            smoothed.set_sample_rate(sample_rate);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 48_000.0;
+
+    ///
+    /// Where an impulse fed in at `time` seconds comes out on each side,
+    /// in samples after it went in.
+    ///
+    fn delay_at(settings: ChorusSettings, time: f64) -> [usize; 2] {
+        let mut chorus = Chorus::new(settings, SAMPLE_RATE);
+        let start = (time * SAMPLE_RATE) as usize;
+        let mut block = vec![[0.0; 2]; start + 4_800];
+
+        block[start] = [1.0; 2];
+        chorus.process(&mut block);
+
+        std::array::from_fn(|side| {
+            let (peak, _) = block[start..]
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a[side].total_cmp(&b[side]))
+                .unwrap();
+
+            peak
+        })
+    }
+
+    #[test]
+    fn sweeps_delay_in_quadrature() {
+        let settings = ChorusSettings {
+            rate: 1.0,
+            feedback: 0.0,
+            ..ChorusSettings::default()
+        };
+
+        // The sweep centres on 720 samples and reaches 144 either side. At
+        // the start the left side sits at the centre and the right at the
+        // top, and a quarter cycle later the left has caught up. The sweep
+        // keeps moving while the impulse is in flight, hence the slack.
+        let [left, right] = delay_at(settings, 0.0);
+
+        assert!(left.abs_diff(720) < 20 && right.abs_diff(864) < 5);
+
+        let [left, right] = delay_at(settings, 0.25);
+
+        assert!(left.abs_diff(864) < 5 && right.abs_diff(720) < 20);
+    }
+
+    #[test]
+    fn flanger_feedback_repeats() {
+        let mut chorus = Chorus::new(
+            ChorusSettings {
+                depth: 0.0,
+                ..ChorusSettings::flanger()
+            },
+            SAMPLE_RATE,
+        );
+        let mut block = vec![[0.0; 2]; 400];
+
+        block[0] = [1.0; 2];
+        chorus.process(&mut block);
+
+        assert!(block[96][0] == 1.0);
+        assert!((block[192][0] - 0.7).abs() < 1e-12);
+        assert!((block[288][0] - 0.49).abs() < 1e-12);
+    }
+
+    #[test]
+    fn glides_to_new_delays() {
+        let mut chorus = Chorus::new(
+            ChorusSettings {
+                rate: 0.0,
+                depth: 0.0,
+                ..ChorusSettings::default()
+            },
+            SAMPLE_RATE,
+        );
+        let ramp = |chorus: &mut Chorus, start: usize| {
+            let mut block: Vec<_> = (start..start + 2_400).map(|n| [n as f64; 2]).collect();
+
+            chorus.process(&mut block);
+            block
+        };
+
+        ramp(&mut chorus, 0);
+        chorus.settings.delay = 0.002;
+
+        // A rising ramp read back through the line: jumping the read head
+        // 624 samples forward would leap the output by as much.
+        let block = ramp(&mut chorus, 2_400);
+        let leap = block
+            .windows(2)
+            .map(|pair| (pair[1][0] - pair[0][0]).abs())
+            .fold(0.0, f64::max);
+
+        assert!(leap < 20.0);
+        assert!((block[2_399][0] - (4_799.0 - 96.0)).abs() < 1e-6);
+    }
+}
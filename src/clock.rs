@@ -0,0 +1,247 @@
+///
+/// Most notes a note source can hold open at once. Further notes are
+/// dropped, so that tracking gates never allocates.
+///
+pub const MAX_GATES: usize = 32;
+
+///
+/// Swing past half a step would put the off-beat closer to the following
+/// step than to its own.
+///
+const MAX_SWING: f64 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoteEvent {
+    On { note: u8, velocity: u8 },
+    Off { note: u8 },
+}
+
+///
+/// Counts steps at a tempo, a sample at a time, so that whatever it drives
+/// starts notes on the exact sample.
+///
+#[derive(Clone, Debug)]
+pub struct Clock {
+    sample_rate: f64,
+    tempo: f64,
+    /// Samples into the current pair of steps, counted whole so that steps
+    /// at a steady tempo never drift. Swing only ever moves the second of
+    /// the pair.
+    elapsed: f64,
+    /// The step last started, or `None` until the first tick.
+    step: Option<u64>,
+}
+
+///
+/// Notes held open by a note source, each closed after its own number of
+/// samples.
+///
+#[derive(Clone, Debug)]
+pub struct Gates {
+    open: Vec<(u8, usize)>,
+}
+
+impl Clock {
+    pub fn new(sample_rate: f64) -> Self {
+        Self {
+            sample_rate,
+            tempo: 120.0,
+            elapsed: 0.0,
+            step: None,
+        }
+    }
+
+    ///
+    /// Beats per minute, where a beat is a quarter note.
+    ///
+    pub fn set_tempo(&mut self, tempo: f64) {
+        let tempo = tempo.max(1.0);
+
+        // Keeps the same place in the pair.
+        self.elapsed *= self.tempo / tempo;
+        self.tempo = tempo;
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.elapsed *= sample_rate / self.sample_rate;
+        self.sample_rate = sample_rate;
+    }
+
+    ///
+    /// Samples in a step, with `division` steps to the beat.
+    ///
+    pub fn step_length(&self, division: f64) -> f64 {
+        60.0 * self.sample_rate / (self.tempo * division.max(f64::EPSILON))
+    }
+
+    ///
+    /// Starts over, so that step `0` begins on the next tick.
+    ///
+    pub fn reset(&mut self) {
+        self.elapsed = 0.0;
+        self.step = None;
+    }
+
+    ///
+    /// Moves on a sample, returning the index of the step beginning on it.
+    /// `swing` delays every second step by that fraction of a step, up to
+    /// `0.5`.
+    ///
+    pub fn tick(&mut self, division: f64, swing: f64) -> Option<u64> {
+        let Some(step) = self.step else {
+            self.step = Some(0);
+            return self.step;
+        };
+
+        let length = self.step_length(division);
+        let swung = (1.0 + swing.clamp(0.0, MAX_SWING)) * length;
+        let before = self.elapsed;
+
+        self.elapsed += 1.0;
+
+        let started = if before < swung && self.elapsed >= swung {
+            true
+        } else if self.elapsed >= 2.0 * length {
+            self.elapsed -= 2.0 * length;
+            true
+        } else {
+            false
+        };
+
+        self.step = Some(step + started as u64);
+        started.then_some(step + 1)
+    }
+}
+
+impl Default for Gates {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Gates {
+    pub fn new() -> Self {
+        Self {
+            open: Vec::with_capacity(MAX_GATES),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.open.is_empty()
+    }
+
+    ///
+    /// Plays `note` for `samples`, restarting it if it is already open.
+    ///
+    pub fn open(
+        &mut self,
+        note: u8,
+        velocity: u8,
+        samples: usize,
+        play: &mut impl FnMut(NoteEvent),
+    ) {
+        if let Some(index) = self.open.iter().position(|(open, _)| *open == note) {
+            self.open.swap_remove(index);
+            play(NoteEvent::Off { note });
+        }
+
+        if self.open.len() == MAX_GATES {
+            return;
+        }
+
+        self.open.push((note, samples.max(1)));
+        play(NoteEvent::On { note, velocity });
+    }
+
+    ///
+    /// Counts down a sample, closing every note whose time is up.
+    ///
+    pub fn tick(&mut self, play: &mut impl FnMut(NoteEvent)) {
+        for (_, samples) in &mut self.open {
+            *samples -= 1;
+        }
+
+        self.open.retain(|(note, samples)| {
+            if *samples == 0 {
+                play(NoteEvent::Off { note: *note });
+            }
+
+            *samples > 0
+        });
+    }
+
+    pub fn close_all(&mut self, play: &mut impl FnMut(NoteEvent)) {
+        for (note, _) in self.open.drain(..) {
+            play(NoteEvent::Off { note });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///
+    /// Samples at which the first `count` steps start.
+    ///
+    fn starts(clock: &mut Clock, division: f64, swing: f64, count: usize) -> Vec<usize> {
+        (0..)
+            .filter(|_| clock.tick(division, swing).is_some())
+            .take(count)
+            .collect()
+    }
+
+    #[test]
+    fn counts_steps_at_tempo() {
+        // Sixteenths at 120 BPM are 125 ms, or 125 samples at 1 kHz.
+        let mut clock = Clock::new(1_000.0);
+
+        assert!(starts(&mut clock, 4.0, 0.0, 4) == [0, 125, 250, 375]);
+
+        clock.reset();
+        clock.set_tempo(60.0);
+        assert!(starts(&mut clock, 4.0, 0.0, 3) == [0, 250, 500]);
+    }
+
+    #[test]
+    fn swings_every_second_step() {
+        let mut clock = Clock::new(1_000.0);
+
+        assert!(starts(&mut clock, 4.0, 0.2, 5) == [0, 150, 250, 400, 500]);
+    }
+
+    #[test]
+    fn gates_close_on_time() {
+        let mut gates = Gates::new();
+        let mut events = Vec::new();
+        let mut play = |event| events.push(event);
+
+        gates.open(60, 100, 2, &mut play);
+        gates.open(64, 100, 3, &mut play);
+        gates.tick(&mut play);
+        gates.tick(&mut play);
+        gates.open(64, 90, 1, &mut play);
+        gates.tick(&mut play);
+
+        assert!(
+            events
+                == [
+                    NoteEvent::On {
+                        note: 60,
+                        velocity: 100
+                    },
+                    NoteEvent::On {
+                        note: 64,
+                        velocity: 100
+                    },
+                    NoteEvent::Off { note: 60 },
+                    NoteEvent::Off { note: 64 },
+                    NoteEvent::On {
+                        note: 64,
+                        velocity: 90
+                    },
+                    NoteEvent::Off { note: 64 },
+                ]
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use crate::{
+    effects::Effect,
+    smoothing::{Ramp, Smoothed},
+};
+
+///
+/// Longest echo in seconds, which sets how much buffer the delay holds.
+///
+pub const MAX_DELAY: f64 = 4.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum DelayTime {
+    Seconds(f64),
+    /// Quarter-note beats, following the tempo.
+    Beats(f64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DelaySettings {
+    pub time: DelayTime,
+    /// How much of each echo is fed back, from `0.0` (a single echo)
+    /// to just under `1.0`.
+    pub feedback: f64,
+    /// Sends a mono sum into the left side and bounces each echo across to
+    /// the other.
+    pub ping_pong: bool,
+}
+
+impl Default for DelaySettings {
+    fn default() -> Self {
+        Self {
+            time: DelayTime::Beats(0.75),
+            feedback: 0.4,
+            ping_pong: false,
+        }
+    }
+}
+
+///
+/// A circular buffer that can be read any fractional number of samples
+/// into the past.
+///
+#[derive(Clone, Debug)]
+pub struct DelayLine {
+    buffer: Vec<f64>,
+    /// Where the next sample goes.
+    position: usize,
+}
+
+impl DelayLine {
+    ///
+    /// Room for delays of up to `length - 2` samples.
+    ///
+    pub fn new(length: usize) -> Self {
+        Self {
+            buffer: vec![0.0; length.max(3)],
+            position: 0,
+        }
+    }
+
+    ///
+    /// The input from `delay` samples before the next write, interpolated
+    /// linearly between whole samples.
+    ///
+    pub fn read(&self, delay: f64) -> f64 {
+        let length = self.buffer.len();
+        let delay = delay.clamp(1.0, (length - 2) as f64);
+        let whole = delay as usize;
+        let fraction = delay - whole as f64;
+
+        let newer = self.buffer[(self.position + length - whole) % length];
+        let older = self.buffer[(self.position + length - whole - 1) % length];
+
+        newer + (older - newer) * fraction
+    }
+
+    pub fn write(&mut self, sample: f64) {
+        self.buffer[self.position] = sample;
+        self.position = (self.position + 1) % self.buffer.len();
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.fill(0.0);
+    }
+}
+
+///
+/// A stereo echo, free-running or locked to the tempo.
+///
+#[derive(Clone, Debug)]
+pub struct Delay {
+    pub settings: DelaySettings,
+    sample_rate: f64,
+    tempo: f64,
+    lines: [DelayLine; 2],
+    /// In seconds, so that retiming glides rather than jumps.
+    time: Smoothed,
+    feedback: Smoothed,
+}
+
+impl Delay {
+    pub fn new(settings: DelaySettings, sample_rate: f64) -> Self {
+        let mut delay = Self {
+            settings,
+            sample_rate,
+            tempo: 120.0,
+            lines: std::array::from_fn(|_| DelayLine::new(Self::length(sample_rate))),
+            time: Smoothed::new(0.0, sample_rate),
+            feedback: Smoothed::new(settings.feedback, sample_rate),
+        };
+
+        delay.time.reset(delay.seconds());
+        delay
+    }
+
+    ///
+    /// Beats per minute used by `DelayTime::Beats`.
+    ///
+    pub fn set_tempo(&mut self, tempo: f64) {
+        self.tempo = tempo;
+    }
+
+    ///
+    /// The echo time the settings ask for at the current tempo.
+    ///
+    pub fn seconds(&self) -> f64 {
+        let seconds = match self.settings.time {
+            DelayTime::Seconds(seconds) => seconds,
+            DelayTime::Beats(beats) => beats * 60.0 / self.tempo,
+        };
+
+        seconds.clamp(0.0, MAX_DELAY)
+    }
+
+    ///
+    /// How changes to the echo time and feedback are smoothed.
+    ///
+    pub fn set_smoothing(&mut self, time: f64, ramp: Ramp) {
+        self.time.configure(time, ramp);
+        self.feedback.configure(time, ramp);
+    }
+
+    fn length(sample_rate: f64) -> usize {
+        (MAX_DELAY * sample_rate).ceil() as usize + 2
+    }
+}
+
+impl Effect for Delay {
+    fn process(&mut self, block: &mut [[f64; 2]]) {
+        self.time.set_target(self.seconds());
+        self.feedback
+            .set_target(self.settings.feedback.clamp(0.0, 0.99));
+
+        let [left_line, right_line] = &mut self.lines;
+
+        for frame in block.iter_mut() {
+            let delay = self.time.next_sample() * self.sample_rate;
+            let feedback = self.feedback.next_sample();
+            let echoes = [left_line.read(delay), right_line.read(delay)];
+            let [left, right] = *frame;
+
+            if self.settings.ping_pong {
+                left_line.write((left + right) / 2.0 + echoes[1] * feedback);
+                right_line.write(echoes[0] * feedback);
+            } else {
+                left_line.write(left + echoes[0] * feedback);
+                right_line.write(right + echoes[1] * feedback);
+            }
+
+            *frame = echoes;
+        }
+    }
+
+    fn reset(&mut self) {
+        for line in &mut self.lines {
+            line.clear();
+        }
+
+        self.time.reset(self.seconds());
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.lines = std::array::from_fn(|_| DelayLine::new(Self::length(sample_rate)));
+        self.time.set_sample_rate(sample_rate);
+        self.feedback.set_sample_rate(sample_rate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 1_000.0;
+
+    fn impulse_response(delay: &mut Delay, length: usize) -> Vec<[f64; 2]> {
+        let mut block = vec![[0.0; 2]; length];
+
+        block[0] = [1.0, 0.5];
+        delay.process(&mut block);
+        block
+    }
+
+    fn peaks(response: &[[f64; 2]], side: usize) -> Vec<(usize, f64)> {
+        response
+            .iter()
+            .enumerate()
+            .filter(|(_, frame)| frame[side].abs() > 1e-9)
+            .map(|(n, frame)| (n, frame[side]))
+            .collect()
+    }
+
+    #[test]
+    fn reads_fractional_delays() {
+        let mut line = DelayLine::new(8);
+
+        for sample in [1.0, 2.0, 3.0] {
+            line.write(sample);
+        }
+
+        assert!(line.read(1.0) == 3.0);
+        assert!(line.read(2.5) == 1.5);
+    }
+
+    #[test]
+    fn echoes_with_decaying_feedback() {
+        let mut delay = Delay::new(
+            DelaySettings {
+                time: DelayTime::Seconds(0.1),
+                feedback: 0.5,
+                ping_pong: false,
+            },
+            SAMPLE_RATE,
+        );
+
+        let response = impulse_response(&mut delay, 350);
+
+        assert!(peaks(&response, 0) == [(100, 1.0), (200, 0.5), (300, 0.25)]);
+        assert!(peaks(&response, 1) == [(100, 0.5), (200, 0.25), (300, 0.125)]);
+    }
+
+    #[test]
+    fn ping_pong_alternates_sides() {
+        let mut delay = Delay::new(
+            DelaySettings {
+                time: DelayTime::Seconds(0.1),
+                feedback: 0.5,
+                ping_pong: true,
+            },
+            SAMPLE_RATE,
+        );
+
+        let response = impulse_response(&mut delay, 350);
+
+        assert!(peaks(&response, 0) == [(100, 0.75), (300, 0.1875)]);
+        assert!(peaks(&response, 1) == [(200, 0.375)]);
+    }
+
+    #[test]
+    fn syncs_to_tempo() {
+        let mut delay = Delay::new(
+            DelaySettings {
+                time: DelayTime::Beats(0.5),
+                ..DelaySettings::default()
+            },
+            SAMPLE_RATE,
+        );
+
+        delay.set_tempo(150.0);
+        assert!(delay.seconds() == 0.2);
+
+        delay.set_smoothing(0.0, Ramp::OnePole);
+        delay.reset();
+        assert!(peaks(&impulse_response(&mut delay, 250), 0)[0].0 == 200);
+    }
+}
@@ -0,0 +1,432 @@
+use std::{
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    BufferSize, FromSample, Sample, SampleFormat, SampleRate, SizedSample, StreamConfig,
+    StreamError, SupportedBufferSize, SupportedStreamConfig,
+};
+
+use crate::{channel_layout::ChannelLayout, engine::Engine};
+
+///
+/// How often to look for a device again once every one has failed.
+///
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+///
+/// Which output to play on. Anything left `None` falls back to the host's
+/// default.
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceRequest {
+    pub host: Option<String>,
+    pub device: Option<String>,
+    pub sample_rate: Option<u32>,
+    /// Frames per callback.
+    pub buffer_size: Option<u32>,
+}
+
+///
+/// Keeps the engine playing from a supervisor thread of its own, which
+/// rebuilds the stream on a fallback device whenever the current one fails
+/// or disappears.
+///
+pub struct Output {
+    messages: mpsc::Sender<Message>,
+}
+
+enum Message {
+    Open(DeviceRequest, mpsc::Sender<Result<String, String>>),
+    /// A stream error, tagged with the stream it came from so that late
+    /// errors from a stream already replaced are ignored.
+    Failed(u64, StreamError),
+}
+
+struct Stream {
+    /// Plays until dropped.
+    _stream: cpal::Stream,
+    description: String,
+}
+
+pub fn hosts() -> Vec<String> {
+    cpal::available_hosts()
+        .into_iter()
+        .map(|id| id.name().to_string())
+        .collect()
+}
+
+///
+/// Output device names, the host's default marked with a leading `*` and
+/// the rest indented to match, so that names copy out unchanged.
+///
+pub fn devices(host: Option<&str>) -> Result<Vec<String>, String> {
+    let host = find_host(host)?;
+    let default = host
+        .default_output_device()
+        .and_then(|device| device.name().ok());
+
+    Ok(host
+        .output_devices()
+        .map_err(|e| e.to_string())?
+        .filter_map(|device| device.name().ok())
+        .map(|name| match Some(&name) == default.as_ref() {
+            true => format!("* {name}"),
+            false => format!("  {name}"),
+        })
+        .collect())
+}
+
+pub fn configs(host: Option<&str>, device: Option<&str>) -> Result<Vec<String>, String> {
+    let device = find_device(&find_host(host)?, device)?;
+
+    Ok(device
+        .supported_output_configs()
+        .map_err(|e| e.to_string())?
+        .map(|config| {
+            let buffer = match config.buffer_size() {
+                SupportedBufferSize::Range { min, max } => format!("{min}-{max} frames"),
+                SupportedBufferSize::Unknown => "unknown buffer size".to_string(),
+            };
+
+            format!(
+                "{} ch, {}-{} Hz, {}, {buffer}",
+                config.channels(),
+                config.min_sample_rate().0,
+                config.max_sample_rate().0,
+                config.sample_format(),
+            )
+        })
+        .collect())
+}
+
+fn find_host(name: Option<&str>) -> Result<cpal::Host, String> {
+    let Some(name) = name else {
+        return Ok(cpal::default_host());
+    };
+
+    let id = cpal::available_hosts()
+        .into_iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| format!("No audio host '{name}'"))?;
+
+    cpal::host_from_id(id).map_err(|e| e.to_string())
+}
+
+fn find_device(host: &cpal::Host, name: Option<&str>) -> Result<cpal::Device, String> {
+    match name {
+        None => host
+            .default_output_device()
+            .ok_or_else(|| "No default output device".to_string()),
+        Some(name) => host
+            .output_devices()
+            .map_err(|e| e.to_string())?
+            .find(|device| device.name().is_ok_and(|device| device == name))
+            .ok_or_else(|| format!("No output device '{name}'")),
+    }
+}
+
+///
+/// Formats the stream callback can write.
+///
+fn playable(format: SampleFormat) -> bool {
+    use SampleFormat::*;
+
+    matches!(
+        format,
+        I8 | I16 | I32 | I64 | U8 | U16 | U32 | U64 | F32 | F64
+    )
+}
+
+///
+/// The best playable config at the requested rate, or else at `preferred`
+/// (the engine's current rate), or else the device's own default.
+///
+fn output_config(
+    device: &cpal::Device,
+    sample_rate: Option<u32>,
+    preferred: u32,
+) -> Result<SupportedStreamConfig, String> {
+    let at_rate = |rate: u32| -> Result<Option<SupportedStreamConfig>, String> {
+        Ok(device
+            .supported_output_configs()
+            .map_err(|e| e.to_string())?
+            .filter(|config| playable(config.sample_format()))
+            .filter(|config| {
+                (config.min_sample_rate().0..=config.max_sample_rate().0).contains(&rate)
+            })
+            .max_by(|a, b| a.cmp_default_heuristics(b))
+            .map(|config| config.with_sample_rate(SampleRate(rate))))
+    };
+
+    if let Some(rate) = sample_rate {
+        return at_rate(rate)?.ok_or_else(|| format!("Device does not support {rate} Hz"));
+    }
+
+    if let Some(config) = at_rate(preferred)? {
+        return Ok(config);
+    }
+
+    if let Ok(config) = device.default_output_config() {
+        if playable(config.sample_format()) {
+            return Ok(config);
+        }
+    }
+
+    device
+        .supported_output_configs()
+        .map_err(|e| e.to_string())?
+        .filter(|config| playable(config.sample_format()))
+        .max_by(|a, b| a.cmp_default_heuristics(b))
+        .map(|config| config.with_max_sample_rate())
+        .ok_or_else(|| "No output config in a playable sample format".to_string())
+}
+
+impl Output {
+    ///
+    /// Opens `request` in the background, retrying until a device turns up.
+    ///
+    pub fn start(engine: Engine, request: DeviceRequest) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let errors = sender.clone();
+
+        thread::spawn(move || supervise(Arc::new(Mutex::new(engine)), request, receiver, errors));
+
+        Self { messages: sender }
+    }
+
+    ///
+    /// Moves playback to another device, describing the new stream once it
+    /// plays. On failure the previous device keeps playing.
+    ///
+    pub fn open(&self, request: DeviceRequest) -> Result<String, String> {
+        let (reply, result) = mpsc::channel();
+        let stopped = || "Audio output thread has stopped".to_string();
+
+        self.messages
+            .send(Message::Open(request, reply))
+            .map_err(|_| stopped())?;
+
+        result.recv().map_err(|_| stopped())?
+    }
+}
+
+fn supervise(
+    engine: Arc<Mutex<Engine>>,
+    mut request: DeviceRequest,
+    messages: mpsc::Receiver<Message>,
+    errors: mpsc::Sender<Message>,
+) {
+    let mut generation = 0;
+    let mut stream = recover(&engine, &request, &errors, generation);
+
+    loop {
+        let message = match stream {
+            Some(_) => messages.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            None => messages.recv_timeout(RETRY_INTERVAL),
+        };
+
+        match message {
+            Ok(Message::Open(next, reply)) => {
+                generation += 1;
+
+                // @note: Dropped first, as some devices only allow one stream at a time
+                drop(stream.take());
+
+                match open(&engine, &next, &errors, generation) {
+                    Ok(opened) => {
+                        let _ = reply.send(Ok(opened.description.clone()));
+
+                        request = next;
+                        stream = Some(opened);
+                    }
+                    Err(err) => {
+                        let _ = reply.send(Err(err));
+
+                        stream = recover(&engine, &request, &errors, generation);
+                    }
+                }
+            }
+            Ok(Message::Failed(failed, err)) => {
+                if failed != generation {
+                    continue;
+                }
+
+                eprintln!("Audio stream failed: {err}");
+
+                generation += 1;
+                drop(stream.take());
+                stream = recover(&engine, &request, &errors, generation);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                stream = recover(&engine, &request, &errors, generation);
+            }
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+///
+/// Tries the requested device, then the host's default, then any device
+/// that opens at all.
+///
+fn recover(
+    engine: &Arc<Mutex<Engine>>,
+    request: &DeviceRequest,
+    errors: &mpsc::Sender<Message>,
+    generation: u64,
+) -> Option<Stream> {
+    let fallbacks = find_host(request.host.as_deref())
+        .and_then(|host| host.output_devices().map_err(|e| e.to_string()))
+        .into_iter()
+        .flatten()
+        .filter_map(|device| device.name().ok())
+        .map(|name| DeviceRequest {
+            device: Some(name),
+            sample_rate: None,
+            buffer_size: None,
+            ..request.clone()
+        });
+
+    let default = DeviceRequest {
+        device: None,
+        ..request.clone()
+    };
+
+    let stream = std::iter::once(request.clone())
+        .chain(std::iter::once(default))
+        .chain(fallbacks)
+        .find_map(|request| open(engine, &request, errors, generation).ok());
+
+    match &stream {
+        Some(stream) => println!("Playing on {}", stream.description),
+        None => eprintln!("No output device available, retrying"),
+    }
+
+    stream
+}
+
+fn open(
+    engine: &Arc<Mutex<Engine>>,
+    request: &DeviceRequest,
+    errors: &mpsc::Sender<Message>,
+    generation: u64,
+) -> Result<Stream, String> {
+    let device = find_device(
+        &find_host(request.host.as_deref())?,
+        request.device.as_deref(),
+    )?;
+    let name = device.name().map_err(|e| e.to_string())?;
+
+    let preferred = engine.lock().unwrap().sample_rate() as u32;
+    let supported = output_config(&device, request.sample_rate, preferred)?;
+
+    let mut config: StreamConfig = supported.config();
+
+    if let Some(frames) = request.buffer_size {
+        if let SupportedBufferSize::Range { min, max } = supported.buffer_size() {
+            if !(*min..=*max).contains(&frames) {
+                return Err(format!("Buffer size must be {min}-{max} frames on {name}"));
+            }
+        }
+
+        config.buffer_size = BufferSize::Fixed(frames);
+    }
+
+    {
+        let mut engine = engine.lock().unwrap();
+        let format = supported.sample_format();
+
+        engine.set_sample_rate(config.sample_rate.0 as f64);
+
+        // @note: Float formats are at least 32 bits, clearing a previous device's quantizers
+        engine.set_output_bits(format.sample_size() as u32 * 8);
+    }
+
+    let errors = errors.clone();
+    let on_error = move |err| {
+        let _ = errors.send(Message::Failed(generation, err));
+    };
+
+    // @note: cpal 0.15 has no packed 24/48-bit formats, hosts hand 24-bit interfaces over as I32
+    let stream = match supported.sample_format() {
+        SampleFormat::I8 => build::<i8>(&device, &config, engine, on_error),
+        SampleFormat::I16 => build::<i16>(&device, &config, engine, on_error),
+        SampleFormat::I32 => build::<i32>(&device, &config, engine, on_error),
+        SampleFormat::I64 => build::<i64>(&device, &config, engine, on_error),
+        SampleFormat::U8 => build::<u8>(&device, &config, engine, on_error),
+        SampleFormat::U16 => build::<u16>(&device, &config, engine, on_error),
+        SampleFormat::U32 => build::<u32>(&device, &config, engine, on_error),
+        SampleFormat::U64 => build::<u64>(&device, &config, engine, on_error),
+        SampleFormat::F32 => build::<f32>(&device, &config, engine, on_error),
+        SampleFormat::F64 => build::<f64>(&device, &config, engine, on_error),
+        sample_format => unreachable!("'{sample_format}' filtered by output_config"),
+    }?;
+
+    stream.play().map_err(|e| e.to_string())?;
+
+    Ok(Stream {
+        _stream: stream,
+        description: format!(
+            "{name} at {} Hz, {} ch, {}",
+            config.sample_rate.0,
+            config.channels,
+            supported.sample_format()
+        ),
+    })
+}
+
+fn build<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    engine: &Arc<Mutex<Engine>>,
+    on_error: impl FnMut(StreamError) + Send + 'static,
+) -> Result<cpal::Stream, String>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let engine = Arc::clone(engine);
+    let layout = ChannelLayout::for_channels(config.channels as usize);
+
+    let mut block: Vec<[f32; 2]> = Vec::with_capacity(4_096);
+
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                // @note: Never blocks, the lock is only contended while a stream is being swapped
+                let Ok(mut engine) = engine.try_lock() else {
+                    data.fill(T::EQUILIBRIUM);
+                    return;
+                };
+
+                let frames = data.len() / layout.channels();
+
+                // @note: Only allocates if the device asks for a bigger block than any before
+                block.resize(frames, [0.0; 2]);
+                engine.process(&mut block);
+
+                write_data(data, &layout, &block)
+            },
+            on_error,
+            None,
+        )
+        .map_err(|e| e.to_string())
+}
+
+fn write_data<T>(output: &mut [T], layout: &ChannelLayout, block: &[[f32; 2]])
+where
+    T: Sample + FromSample<f32>,
+{
+    for (frame, stereo) in output.chunks_mut(layout.channels()).zip(block) {
+        for (sample, value) in frame.iter_mut().zip(layout.route(*stereo)) {
+            *sample = T::from_sample(value);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use crate::waveform::WhiteNoise;
+
+///
+/// Noise added before rounding to a shorter word, which trades the
+/// signal-dependent distortion of plain rounding for a steady noise floor.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dither {
+    /// Plain rounding.
+    None,
+    /// One least significant bit of uniform noise.
+    Rectangular,
+    /// Two bits of triangular noise, which also decouples the noise power
+    /// from the signal.
+    #[default]
+    Triangular,
+    /// Triangular noise with first-order error feedback, pushing the noise
+    /// floor up towards Nyquist where it is least audible.
+    NoiseShaped,
+}
+
+///
+/// Rounds samples to the grid of a `bits`-wide signed integer, still as
+/// floats in `-1.0..1.0`, so that the conversion to the device format is
+/// exact.
+///
+#[derive(Clone, Debug)]
+pub struct Quantizer {
+    pub dither: Dither,
+    scale: f64,
+    noise: WhiteNoise,
+    error: f64,
+}
+
+impl Quantizer {
+    pub fn new(bits: u32, dither: Dither) -> Self {
+        assert!((2..=32).contains(&bits), "Cannot quantize to {bits} bits");
+
+        Self {
+            dither,
+            scale: (1u64 << (bits - 1)) as f64,
+            noise: WhiteNoise::default(),
+            error: 0.0,
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.scale.log2() as u32 + 1
+    }
+
+    pub fn quantize(&mut self, sample: f64) -> f64 {
+        let mut value = sample * self.scale;
+
+        if self.dither == Dither::NoiseShaped {
+            value -= self.error;
+        }
+
+        let noise = match self.dither {
+            Dither::None => 0.0,
+            Dither::Rectangular => self.noise.next_sample() / 2.0,
+            Dither::Triangular | Dither::NoiseShaped => {
+                (self.noise.next_sample() + self.noise.next_sample()) / 2.0
+            }
+        };
+
+        let rounded = (value + noise).round();
+
+        // @note: Taken before clipping, which would otherwise wind the error up without bound
+        self.error = rounded - value;
+
+        rounded.clamp(-self.scale, self.scale - 1.0) / self.scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BITS: u32 = 8;
+
+    fn quantize(dither: Dither, input: impl Fn(usize) -> f64) -> Vec<f64> {
+        let mut quantizer = Quantizer::new(BITS, dither);
+
+        (0..48_000).map(|n| quantizer.quantize(input(n))).collect()
+    }
+
+    #[test]
+    fn lands_on_the_grid() {
+        let scale = (1 << (BITS - 1)) as f64;
+
+        for dither in [
+            Dither::None,
+            Dither::Rectangular,
+            Dither::Triangular,
+            Dither::NoiseShaped,
+        ] {
+            let output = quantize(dither, |n| (n as f64 * 0.01).sin() * 1.2);
+
+            assert!(output
+                .iter()
+                .all(|sample| (sample * scale).fract() == 0.0 && (-1.0..1.0).contains(sample)));
+        }
+
+        assert!(Quantizer::new(BITS, Dither::None).bits() == BITS);
+    }
+
+    #[test]
+    fn dither_preserves_detail_below_one_bit() {
+        // A third of a bit, which plain rounding erases entirely.
+        let level = 1.0 / 3.0 / (1 << (BITS - 1)) as f64;
+        let mean = |samples: Vec<f64>| samples.iter().sum::<f64>() / samples.len() as f64;
+
+        assert!(mean(quantize(Dither::None, |_| level)) == 0.0);
+
+        for dither in [Dither::Rectangular, Dither::Triangular, Dither::NoiseShaped] {
+            let mean = mean(quantize(dither, |_| level));
+
+            assert!((mean / level - 1.0).abs() < 0.05, "{dither:?} {mean}");
+        }
+    }
+
+    #[test]
+    fn noise_shaping_tilts_error_upwards() {
+        let input = |n: usize| (n as f64 * 0.01).sin() * 0.5;
+
+        // Correlation of neighbouring errors, which turns negative when the
+        // error is mostly high frequency.
+        let correlation = |dither| {
+            let error: Vec<f64> = quantize(dither, input)
+                .iter()
+                .enumerate()
+                .map(|(n, sample)| sample - input(n))
+                .collect();
+
+            error.windows(2).map(|w| w[0] * w[1]).sum::<f64>()
+                / error.iter().map(|e| e * e).sum::<f64>()
+        };
+
+        assert!(correlation(Dither::Triangular).abs() < 0.05);
+        assert!(correlation(Dither::NoiseShaped) < -0.3);
+    }
+
+    #[test]
+    fn noise_shaping_recovers_from_clipping() {
+        let scale = (1 << (BITS - 1)) as f64;
+        let output = quantize(Dither::NoiseShaped, |n| match n < 24_000 {
+            true => 2.0,
+            false => 0.0,
+        });
+
+        assert!(output[23_999] == (scale - 1.0) / scale);
+        assert!(output[24_001..]
+            .iter()
+            .all(|sample| (sample * scale).abs() <= 2.0));
+    }
+}
@@ -0,0 +1,380 @@
+use crate::{
+    chorus::{Chorus, ChorusSettings},
+    delay::{Delay, DelaySettings},
+    reverb::{Reverb, ReverbSettings},
+    smoothing::{Ramp, Smoothed},
+};
+
+pub const EFFECT_COUNT: usize = 3;
+
+///
+/// Frames processed per pass, which sizes the chain's scratch buffers so
+/// that blocks of any length never allocate.
+///
+const BLOCK: usize = 256;
+
+///
+/// A stereo processor in the effects chain. The chain takes care of dry/wet
+/// mixing, so effects only produce their wet signal.
+///
+pub trait Effect {
+    ///
+    /// Replaces every frame of `block` with the effect's wet output.
+    ///
+    fn process(&mut self, block: &mut [[f64; 2]]);
+
+    ///
+    /// Clears any tail still ringing.
+    ///
+    fn reset(&mut self);
+
+    ///
+    /// May reallocate buffers, so it belongs on the control side.
+    ///
+    fn set_sample_rate(&mut self, sample_rate: f64);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum EffectKind {
+    Chorus,
+    Delay,
+    Reverb,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Routing {
+    /// In series, replacing the signal with a dry/wet blend of it.
+    #[default]
+    Insert,
+    /// In parallel, tapping the signal at its place in the chain and adding
+    /// its wet output to the end.
+    Send,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SlotSettings {
+    pub routing: Routing,
+    /// Wet level, from `0.0` (dry) to `1.0` (wet only when inserted).
+    pub mix: f64,
+    pub bypass: bool,
+}
+
+///
+/// Where an effect sits in the chain and how it is mixed in.
+///
+#[derive(Clone, Debug)]
+struct Slot {
+    settings: SlotSettings,
+    /// Glides to zero on bypass, so that switching an effect in or out
+    /// fades rather than clicks.
+    mix: Smoothed,
+    /// Whether the effect ran last block, so that it can start afresh
+    /// rather than replay a stale tail.
+    active: bool,
+}
+
+///
+/// The chorus, delay and reverb, run in a configurable order after the
+/// voices. Every effect starts bypassed.
+///
+pub struct EffectChain {
+    pub chorus: Chorus,
+    pub delay: Delay,
+    pub reverb: Reverb,
+    slots: [Slot; EFFECT_COUNT],
+    order: [EffectKind; EFFECT_COUNT],
+    wet: Vec<[f64; 2]>,
+    sends: Vec<[f64; 2]>,
+}
+
+impl EffectKind {
+    pub const ALL: [EffectKind; EFFECT_COUNT] =
+        [EffectKind::Chorus, EffectKind::Delay, EffectKind::Reverb];
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl SlotSettings {
+    ///
+    /// Where each effect starts out in a fresh chain.
+    ///
+    pub fn default_for(kind: EffectKind) -> Self {
+        let (routing, mix) = match kind {
+            EffectKind::Chorus => (Routing::Insert, 0.5),
+            EffectKind::Delay => (Routing::Insert, 0.3),
+            EffectKind::Reverb => (Routing::Send, 0.3),
+        };
+
+        Self {
+            routing,
+            mix,
+            bypass: true,
+        }
+    }
+}
+
+///
+/// Checks that `order` names every effect exactly once.
+///
+pub fn check_order(order: &[EffectKind]) -> Result<(), String> {
+    for kind in EffectKind::ALL {
+        if order.iter().filter(|other| **other == kind).count() != 1 {
+            return Err(format!("The effect order must name {kind:?} exactly once"));
+        }
+    }
+
+    Ok(())
+}
+
+impl EffectChain {
+    pub fn new(sample_rate: f64) -> Self {
+        Self {
+            chorus: Chorus::new(ChorusSettings::default(), sample_rate),
+            delay: Delay::new(DelaySettings::default(), sample_rate),
+            reverb: Reverb::new(ReverbSettings::default(), sample_rate),
+            slots: EffectKind::ALL.map(|kind| Slot {
+                settings: SlotSettings::default_for(kind),
+                mix: Smoothed::new(0.0, sample_rate),
+                active: false,
+            }),
+            order: EffectKind::ALL,
+            wet: vec![[0.0; 2]; BLOCK],
+            sends: vec![[0.0; 2]; BLOCK],
+        }
+    }
+
+    pub fn order(&self) -> [EffectKind; EFFECT_COUNT] {
+        self.order
+    }
+
+    pub fn set_order(&mut self, order: [EffectKind; EFFECT_COUNT]) -> Result<(), String> {
+        check_order(&order)?;
+        self.order = order;
+
+        Ok(())
+    }
+
+    pub fn slot(&self, kind: EffectKind) -> SlotSettings {
+        self.slots[kind.index()].settings
+    }
+
+    pub fn set_slot(&mut self, kind: EffectKind, settings: SlotSettings) {
+        self.slots[kind.index()].settings = settings;
+    }
+
+    ///
+    /// Beats per minute for the tempo-synced delay.
+    ///
+    pub fn set_tempo(&mut self, tempo: f64) {
+        self.delay.set_tempo(tempo);
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.chorus.set_sample_rate(sample_rate);
+        self.delay.set_sample_rate(sample_rate);
+        self.reverb.set_sample_rate(sample_rate);
+
+        for slot in &mut self.slots {
+            slot.mix.set_sample_rate(sample_rate);
+        }
+    }
+
+    ///
+    /// How changes to the mix, bypass and every effect's settings are
+    /// smoothed.
+    ///
+    pub fn set_smoothing(&mut self, time: f64, ramp: Ramp) {
+        self.chorus.set_smoothing(time, ramp);
+        self.delay.set_smoothing(time, ramp);
+        self.reverb.set_smoothing(time, ramp);
+
+        for slot in &mut self.slots {
+            slot.mix.configure(time, ramp);
+        }
+    }
+
+    ///
+    /// Clears every effect's tail.
+    ///
+    pub fn reset(&mut self) {
+        self.chorus.reset();
+        self.delay.reset();
+        self.reverb.reset();
+    }
+
+    pub fn process(&mut self, block: &mut [[f64; 2]]) {
+        for chunk in block.chunks_mut(BLOCK) {
+            let wet = &mut self.wet[..chunk.len()];
+            let sends = &mut self.sends[..chunk.len()];
+
+            sends.fill([0.0; 2]);
+
+            for kind in self.order {
+                let slot = &mut self.slots[kind.index()];
+                let effect: &mut dyn Effect = match kind {
+                    EffectKind::Chorus => &mut self.chorus,
+                    EffectKind::Delay => &mut self.delay,
+                    EffectKind::Reverb => &mut self.reverb,
+                };
+
+                slot.mix.set_target(match slot.settings.bypass {
+                    true => 0.0,
+                    false => slot.settings.mix.clamp(0.0, 1.0),
+                });
+
+                if slot.mix.is_settled() && slot.mix.value() == 0.0 {
+                    slot.active = false;
+                    continue;
+                }
+
+                if !slot.active {
+                    effect.reset();
+                    slot.active = true;
+                }
+
+                wet.copy_from_slice(chunk);
+                effect.process(wet);
+
+                for ((frame, wet), send) in chunk.iter_mut().zip(wet.iter()).zip(sends.iter_mut()) {
+                    let mix = slot.mix.next_sample();
+
+                    for side in 0..2 {
+                        match slot.settings.routing {
+                            Routing::Insert => {
+                                frame[side] += (wet[side] - frame[side]) * mix;
+                            }
+                            Routing::Send => send[side] += wet[side] * mix,
+                        }
+                    }
+                }
+            }
+
+            for (frame, send) in chunk.iter_mut().zip(sends.iter()) {
+                frame[0] += send[0];
+                frame[1] += send[1];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::delay::DelayTime;
+
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 1_000.0;
+
+    fn chain() -> EffectChain {
+        let mut chain = EffectChain::new(SAMPLE_RATE);
+
+        chain.set_smoothing(0.0, Ramp::OnePole);
+        chain.delay.settings = DelaySettings {
+            time: DelayTime::Seconds(0.1),
+            feedback: 0.0,
+            ping_pong: false,
+        };
+
+        chain
+    }
+
+    fn impulse_response(chain: &mut EffectChain) -> Vec<[f64; 2]> {
+        let mut block = vec![[0.0; 2]; 1_000];
+
+        block[0] = [1.0; 2];
+        chain.process(&mut block);
+        block
+    }
+
+    fn enable(chain: &mut EffectChain, kind: EffectKind, routing: Routing, mix: f64) {
+        chain.set_slot(
+            kind,
+            SlotSettings {
+                routing,
+                mix,
+                bypass: false,
+            },
+        );
+    }
+
+    #[test]
+    fn bypassed_chain_passes_dry() {
+        let mut chain = chain();
+        let response = impulse_response(&mut chain);
+
+        assert!(response[0] == [1.0; 2]);
+        assert!(response[1..].iter().all(|frame| *frame == [0.0; 2]));
+    }
+
+    #[test]
+    fn insert_blends_and_send_adds() {
+        let mut chain = chain();
+
+        enable(&mut chain, EffectKind::Delay, Routing::Insert, 0.25);
+        let response = impulse_response(&mut chain);
+
+        assert!(response[0] == [0.75; 2] && response[100] == [0.25; 2]);
+
+        enable(&mut chain, EffectKind::Delay, Routing::Send, 0.25);
+        let response = impulse_response(&mut chain);
+
+        assert!(response[0] == [1.0; 2] && response[100] == [0.25; 2]);
+    }
+
+    #[test]
+    fn runs_in_configured_order() {
+        // A reverb send after a fully wet delay only hears the echo, while
+        // one before it starts ringing well before the echo arrives.
+        let rings_before_echo = |order| {
+            let mut chain = chain();
+
+            enable(&mut chain, EffectKind::Delay, Routing::Insert, 1.0);
+            enable(&mut chain, EffectKind::Reverb, Routing::Send, 1.0);
+            chain.set_order(order).unwrap();
+
+            impulse_response(&mut chain)[..100]
+                .iter()
+                .any(|frame| *frame != [0.0; 2])
+        };
+
+        use EffectKind::*;
+
+        assert!(rings_before_echo([Chorus, Reverb, Delay]));
+        assert!(!rings_before_echo([Chorus, Delay, Reverb]));
+        assert!(chain().set_order([Delay, Delay, Reverb]).is_err());
+    }
+
+    #[test]
+    fn bypass_fades_out() {
+        let mut chain = EffectChain::new(SAMPLE_RATE);
+
+        chain.delay.settings = DelaySettings {
+            time: DelayTime::Seconds(0.001),
+            feedback: 0.0,
+            ping_pong: false,
+        };
+        enable(&mut chain, EffectKind::Delay, Routing::Send, 1.0);
+
+        // Enabling fades the echo in rather than switching it on.
+        let mut block = vec![[1.0; 2]; 100];
+
+        chain.process(&mut block);
+        assert!(block[1][0] > 1.0 && block[1][0] < 2.0);
+        assert!(block[99][0] == 2.0);
+
+        chain.set_slot(
+            EffectKind::Delay,
+            SlotSettings {
+                bypass: true,
+                ..chain.slot(EffectKind::Delay)
+            },
+        );
+        chain.process(&mut block.clone());
+
+        block.fill([1.0; 2]);
+        chain.process(&mut block);
+        assert!(block.iter().all(|frame| *frame == [1.0; 2]));
+    }
+}
@@ -0,0 +1,501 @@
+use std::sync::Arc;
+
+use crossbeam::queue::ArrayQueue;
+
+use crate::{
+    arpeggiator::ArpSettings,
+    chorus::ChorusSettings,
+    delay::DelaySettings,
+    dither::{Dither, Quantizer},
+    effects::{EffectKind, SlotSettings, EFFECT_COUNT},
+    envelope::Adsr,
+    filter::FilterSettings,
+    lfo::LfoSettings,
+    master::{LimiterSettings, MeterHandle, MeterReading},
+    midi::{MidiMessage, MidiSource},
+    modulation::ModRoute,
+    oscillator::Oscillator,
+    reverb::ReverbSettings,
+    sequencer::{SequencerSettings, Step},
+    smoothing::Ramp,
+    synth::Synth,
+    unison::{UnisonSettings, MAX_UNISON},
+    voice::MAX_POLYPHONY,
+};
+
+const COMMAND_CAPACITY: usize = 1_024;
+
+///
+/// One copy of an oscillator per voice, as handed to the audio thread.
+///
+type Oscillators = Vec<Box<dyn Oscillator + Send>>;
+
+///
+/// Frames rendered per pass, sizing the buffer the synth renders into.
+///
+const BLOCK: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    NoteOn {
+        note: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        note: u8,
+    },
+    Midi(MidiMessage),
+    SetGain(f64),
+    SetDither(Dither),
+    SetPan(f64),
+    SetWidth(f64),
+    SetSmoothing {
+        time: f64,
+        ramp: Ramp,
+    },
+    SetPolyphony(usize),
+    SetEnvelope(Adsr),
+    SetFilter(FilterSettings),
+    SetLfo {
+        index: usize,
+        settings: LfoSettings,
+    },
+    SetTempo(f64),
+    AddRoute(ModRoute),
+    SetChorus(ChorusSettings),
+    SetDelay(DelaySettings),
+    SetReverb(ReverbSettings),
+    SetEffect {
+        effect: EffectKind,
+        settings: SlotSettings,
+    },
+    SetEffectOrder([EffectKind; EFFECT_COUNT]),
+    SetLimiter(LimiterSettings),
+    SetSequencer(SequencerSettings),
+    SetStep {
+        index: usize,
+        step: Option<Step>,
+    },
+    ClearSteps,
+    SetArpeggiator(ArpSettings),
+    SetUnison(UnisonSettings),
+    SetNoteFrequency {
+        note: u8,
+        frequency: Option<f64>,
+    },
+    ClearRoutes,
+}
+
+///
+/// The audio thread's half of the engine. It owns the synth outright and
+/// only ever pops from the lock-free command queue, so `process` never
+/// blocks or allocates inside the device callback.
+///
+pub struct Engine {
+    synth: Synth,
+    commands: Arc<ArrayQueue<Command>>,
+    dither: Dither,
+    /// One per side, present only when the device's word is shorter than
+    /// the 24-bit mantissa of an `f32`.
+    quantizers: Option<[Quantizer; 2]>,
+    rendered: Vec<[f64; 2]>,
+    /// Built on the control side, as building them allocates.
+    oscillators: Arc<ArrayQueue<Oscillators>>,
+    /// The oscillators swapped out, sent back so that freeing them doesn't
+    /// happen in the device callback either.
+    retired: Arc<ArrayQueue<Oscillators>>,
+}
+
+///
+/// The control thread's half of the engine, which queues commands to be
+/// applied at the start of the next block. Clones share the same queue, so
+/// a MIDI input thread can hold its own.
+///
+#[derive(Clone)]
+pub struct EngineHandle {
+    commands: Arc<ArrayQueue<Command>>,
+    meter: MeterHandle,
+    oscillators: Arc<ArrayQueue<Oscillators>>,
+    retired: Arc<ArrayQueue<Oscillators>>,
+}
+
+impl Engine {
+    pub fn new(synth: Synth) -> (Self, EngineHandle) {
+        let commands = Arc::new(ArrayQueue::new(COMMAND_CAPACITY));
+        let meter = synth.master.meter();
+
+        // @note: One swap in flight at a time leaves room to retire every one
+        let oscillators = Arc::new(ArrayQueue::new(1));
+        let retired = Arc::new(ArrayQueue::new(1));
+
+        (
+            Self {
+                synth,
+                commands: Arc::clone(&commands),
+                dither: Dither::default(),
+                quantizers: None,
+                rendered: vec![[0.0; 2]; BLOCK],
+                oscillators: Arc::clone(&oscillators),
+                retired: Arc::clone(&retired),
+            },
+            EngineHandle {
+                commands,
+                meter,
+                oscillators,
+                retired,
+            },
+        )
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.synth.sample_rate()
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.synth.set_sample_rate(sample_rate);
+    }
+
+    ///
+    /// Integer bits per sample of the device, so that output reduced to
+    /// fewer than 24 bits is dithered rather than truncated.
+    ///
+    pub fn set_output_bits(&mut self, bits: u32) {
+        self.quantizers = (bits < 24).then(|| {
+            let quantizer = Quantizer::new(bits, self.dither);
+
+            [quantizer.clone(), quantizer]
+        });
+    }
+
+    pub fn process(&mut self, block: &mut [[f32; 2]]) {
+        while let Some(command) = self.commands.pop() {
+            self.apply(command);
+        }
+
+        if let Some(mut oscillators) = self.oscillators.pop() {
+            self.synth.voices.swap_oscillators(&mut oscillators);
+
+            let _ = self.retired.push(oscillators);
+        }
+
+        for chunk in block.chunks_mut(BLOCK) {
+            let rendered = &mut self.rendered[..chunk.len()];
+
+            self.synth.render(rendered);
+
+            for (frame, output) in chunk.iter_mut().zip(rendered.iter_mut()) {
+                if let Some(quantizers) = &mut self.quantizers {
+                    for (sample, quantizer) in output.iter_mut().zip(quantizers.iter_mut()) {
+                        *sample = quantizer.quantize(*sample);
+                    }
+                }
+
+                *frame = output.map(|sample| sample as f32);
+            }
+        }
+    }
+
+    fn apply(&mut self, command: Command) {
+        match command {
+            Command::SetDither(dither) => {
+                self.dither = dither;
+
+                for quantizer in self.quantizers.iter_mut().flatten() {
+                    quantizer.dither = dither;
+                }
+            }
+            command => self.synth.apply(command),
+        }
+    }
+}
+
+impl EngineHandle {
+    pub fn send(&self, command: Command) -> Result<(), String> {
+        self.commands
+            .push(command)
+            .map_err(|command| format!("Engine command queue full, dropped {command:?}"))
+    }
+
+    ///
+    /// Gives every unison copy on every voice its own copy of `oscillator`
+    /// from the start of the next block, cutting any notes playing. Only one
+    /// change can be in flight at a time.
+    ///
+    pub fn set_oscillator(&self, oscillator: Box<dyn Oscillator + Send>) -> Result<(), String> {
+        // Whatever the last change replaced is freed here, off the audio thread.
+        while self.retired.pop().is_some() {}
+
+        let oscillators = (0..MAX_POLYPHONY * MAX_UNISON)
+            .map(|_| oscillator.clone_box())
+            .collect();
+
+        self.oscillators
+            .push(oscillators)
+            .map_err(|_| "Still changing to the previous oscillator".to_string())
+    }
+
+    ///
+    /// Latest levels from the master bus.
+    ///
+    pub fn meter(&self) -> MeterReading {
+        self.meter.read()
+    }
+
+    ///
+    /// Queues every message `source` has due by `time` seconds.
+    ///
+    pub fn forward<S: MidiSource>(&self, source: &mut S, time: f64) -> Result<(), String> {
+        while let Some(message) = source.poll(time) {
+            self.send(Command::Midi(message))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        master::LOOKAHEAD,
+        waveform::{self, WaveformIter},
+    };
+
+    use super::*;
+
+    #[test]
+    fn applies_commands_at_block_start() {
+        let (mut engine, handle) = Engine::new(Synth::new(48_000.0));
+        let mut block = [[0.0; 2]; 256];
+
+        engine.process(&mut block);
+        assert!(block.iter().all(|frame| *frame == [0.0; 2]));
+
+        handle
+            .send(Command::NoteOn {
+                note: 60,
+                velocity: 100,
+            })
+            .unwrap();
+        engine.process(&mut block);
+        assert!(block.iter().any(|frame| *frame != [0.0; 2]));
+
+        handle
+            .send(Command::SetSmoothing {
+                time: 0.0,
+                ramp: Ramp::OnePole,
+            })
+            .unwrap();
+        handle.send(Command::SetGain(0.0)).unwrap();
+        engine.process(&mut block);
+
+        // The note is still leaving the limiter's lookahead, and only the
+        // DC blocker's tail follows it.
+        let latency = (LOOKAHEAD * 48_000.0) as usize;
+
+        assert!(block[..latency].iter().any(|frame| *frame != [0.0; 2]));
+        assert!(block[latency..]
+            .iter()
+            .flatten()
+            .all(|sample| sample.abs() < 1e-3));
+    }
+
+    #[test]
+    fn smooths_parameter_steps() {
+        let jump = |block: &[[f32; 2]]| {
+            block
+                .windows(2)
+                .flat_map(|w| [(w[1][0] - w[0][0]).abs(), (w[1][1] - w[0][1]).abs()])
+                .fold(0.0, f32::max)
+        };
+
+        // Largest sample-to-sample jump in a sine voice while a parameter
+        // steps, relative to the largest before or after.
+        let largest_jump = |time, step: Command| {
+            let mut synth = Synth::new(48_000.0);
+
+            synth
+                .voices
+                .set_oscillator(WaveformIter::new(waveform::sine, 0.0, 48_000.0));
+
+            let (mut engine, handle) = Engine::new(synth);
+            let mut block = [[0.0; 2]; 4_800];
+
+            // The limiter would tame the unsmoothed step too.
+            for command in [
+                Command::SetLimiter(LimiterSettings {
+                    enabled: false,
+                    ..LimiterSettings::default()
+                }),
+                Command::SetFilter(FilterSettings {
+                    cutoff: 1_000.0,
+                    ..FilterSettings::default()
+                }),
+                Command::SetGain(0.2),
+                Command::NoteOn {
+                    note: 69,
+                    velocity: 127,
+                },
+            ] {
+                handle.send(command).unwrap();
+            }
+
+            engine.process(&mut block);
+
+            // Steps at the peak of the next cycle, where a jump is largest.
+            // The output lags the voice by the limiter's lookahead, a little
+            // over two cycles.
+            let cycle = &block[4_800 - 109..];
+            let peak = (0..109)
+                .max_by(|a, b| cycle[*a][0].total_cmp(&cycle[*b][0]))
+                .unwrap();
+            let latency = (LOOKAHEAD * 48_000.0) as usize;
+            let before = jump(cycle);
+
+            let (head, tail) = block.split_at_mut((peak + 3 * 109 - latency) % 109 + 1);
+
+            engine.process(head);
+            handle
+                .send(Command::SetSmoothing {
+                    time,
+                    ramp: Ramp::Linear,
+                })
+                .unwrap();
+            handle.send(step).unwrap();
+            engine.process(tail);
+
+            jump(&block[..2_400]) / jump(&block[2_400..]).max(before)
+        };
+
+        let steps = [
+            Command::SetGain(1.0),
+            Command::SetPan(1.0),
+            Command::SetFilter(FilterSettings {
+                cutoff: 200.0,
+                ..FilterSettings::default()
+            }),
+        ];
+
+        for step in steps {
+            assert!(largest_jump(0.01, step) < 1.05, "{step:?}");
+        }
+
+        // Without smoothing the gain step is a click many times over.
+        assert!(largest_jump(0.0, steps[0]) > 5.0);
+    }
+
+    #[test]
+    fn matches_offline_synth() {
+        let (mut engine, handle) = Engine::new(Synth::new(48_000.0));
+        let mut synth = Synth::new(48_000.0);
+
+        handle
+            .send(Command::NoteOn {
+                note: 64,
+                velocity: 90,
+            })
+            .unwrap();
+        handle
+            .send(Command::NoteOn {
+                note: 67,
+                velocity: 90,
+            })
+            .unwrap();
+        synth.note_on(64, 90);
+        synth.note_on(67, 90);
+
+        let mut block = [[0.0; 2]; 128];
+        let mut expected = [[0.0; 2]; 128];
+
+        engine.process(&mut block);
+        synth.render(&mut expected);
+
+        for (a, b) in block.iter().zip(expected) {
+            assert!(*a == b.map(|sample| sample as f32));
+        }
+    }
+
+    ///
+    /// Stands in for a live device, handing over everything it holds.
+    ///
+    struct Queue(Vec<MidiMessage>);
+
+    impl MidiSource for Queue {
+        fn poll(&mut self, _time: f64) -> Option<MidiMessage> {
+            (!self.0.is_empty()).then(|| self.0.remove(0))
+        }
+    }
+
+    #[test]
+    fn forwards_midi_source() {
+        let (mut engine, handle) = Engine::new(Synth::new(48_000.0));
+        let mut block = [[0.0; 2]; 64];
+
+        handle
+            .forward(
+                &mut Queue(vec![MidiMessage::NoteOn {
+                    channel: 0,
+                    note: 60,
+                    velocity: 100,
+                }]),
+                0.0,
+            )
+            .unwrap();
+        engine.process(&mut block);
+        assert!(engine.synth.voices.active_voices() == 1);
+
+        handle
+            .forward(
+                &mut Queue(vec![MidiMessage::NoteOff {
+                    channel: 0,
+                    note: 60,
+                }]),
+                0.0,
+            )
+            .unwrap();
+        engine.process(&mut block);
+        assert!(!engine.synth.voices.voices()[0].envelope().is_gated());
+    }
+
+    #[test]
+    fn dithers_to_device_word_length() {
+        let (mut engine, handle) = Engine::new(Synth::new(48_000.0));
+        let mut block = [[0.0; 2]; 256];
+
+        engine.set_output_bits(8);
+        handle
+            .send(Command::SetDither(Dither::NoiseShaped))
+            .unwrap();
+        handle
+            .send(Command::NoteOn {
+                note: 60,
+                velocity: 100,
+            })
+            .unwrap();
+        engine.process(&mut block);
+
+        assert!(block
+            .iter()
+            .flatten()
+            .all(|sample| (sample * 128.0).fract() == 0.0));
+        assert!(block.iter().flatten().any(|sample| *sample != 0.0));
+
+        // 24 bits and up pass the `f32` output straight through.
+        engine.set_output_bits(24);
+        engine.process(&mut block);
+        assert!(block
+            .iter()
+            .flatten()
+            .any(|sample| (sample * 128.0).fract() != 0.0));
+    }
+
+    #[test]
+    fn reports_full_queue() {
+        let (_engine, handle) = Engine::new(Synth::new(48_000.0));
+
+        for _ in 0..COMMAND_CAPACITY {
+            handle.send(Command::SetGain(0.5)).unwrap();
+        }
+
+        assert!(handle.send(Command::SetGain(0.5)).is_err());
+    }
+}
@@ -0,0 +1,382 @@
+use crate::smoothing::{Ramp, Smoothed};
+
+///
+/// Stage times in seconds for a DAHDSR envelope. Leaving `delay` and `hold`
+/// at zero gives a plain ADSR.
+///
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Adsr {
+    pub delay: f64,
+    pub attack: f64,
+    pub hold: f64,
+    pub decay: f64,
+    /// Level held while the gate stays on, from `0.0` to `1.0`.
+    pub sustain: f64,
+    pub release: f64,
+    pub curve: Curve,
+    pub mode: TriggerMode,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Curve {
+    Linear,
+    /// RC-style segments that move fast at first and settle slowly.
+    #[default]
+    Exponential,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum TriggerMode {
+    /// Every gate-on restarts the envelope from its current level.
+    #[default]
+    Retrigger,
+    /// A gate-on while the gate is already on is ignored.
+    Legato,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Idle,
+    Delay,
+    Attack,
+    Hold,
+    Decay,
+    Sustain,
+    Release,
+}
+
+#[derive(Clone, Debug)]
+pub struct Envelope {
+    pub settings: Adsr,
+    sample_rate: f64,
+    stage: Stage,
+    /// Samples spent in the current stage.
+    elapsed: usize,
+    /// Level at the start of the current stage.
+    from: f64,
+    level: f64,
+    sustain: Smoothed,
+}
+
+///
+/// How sharply `Curve::Exponential` bends, in time constants per stage.
+///
+const CURVATURE: f64 = 5.0;
+
+impl Default for Adsr {
+    fn default() -> Self {
+        Self {
+            delay: 0.0,
+            attack: 0.005,
+            hold: 0.0,
+            decay: 0.1,
+            sustain: 0.8,
+            release: 0.2,
+            curve: Curve::default(),
+            mode: TriggerMode::default(),
+        }
+    }
+}
+
+impl Curve {
+    fn shape(&self, progress: f64) -> f64 {
+        match self {
+            Curve::Linear => progress,
+            Curve::Exponential => {
+                (1.0 - (-CURVATURE * progress).exp()) / (1.0 - (-CURVATURE).exp())
+            }
+        }
+    }
+}
+
+impl Envelope {
+    pub fn new(settings: Adsr, sample_rate: f64) -> Self {
+        Self {
+            settings,
+            sample_rate,
+            stage: Stage::Idle,
+            elapsed: 0,
+            from: 0.0,
+            level: 0.0,
+            sustain: Smoothed::new(settings.sustain, sample_rate),
+        }
+    }
+
+    ///
+    /// Takes effect from the next stage.
+    ///
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.sustain.set_sample_rate(sample_rate);
+    }
+
+    ///
+    /// How a change to the sustain level is smoothed while it is held.
+    ///
+    pub fn set_smoothing(&mut self, time: f64, ramp: Ramp) {
+        self.sustain.configure(time, ramp);
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    pub fn level(&self) -> f64 {
+        self.level
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.stage == Stage::Idle
+    }
+
+    pub fn is_gated(&self) -> bool {
+        !matches!(self.stage, Stage::Idle | Stage::Release)
+    }
+
+    ///
+    /// Takes effect from the very next sample.
+    ///
+    pub fn gate_on(&mut self) {
+        if self.settings.mode == TriggerMode::Legato && self.is_gated() {
+            return;
+        }
+
+        self.enter(Stage::Delay);
+    }
+
+    pub fn gate_off(&mut self) {
+        if self.is_gated() {
+            self.enter(Stage::Release);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.level = 0.0;
+        self.enter(Stage::Idle);
+    }
+
+    pub fn next_sample(&mut self) -> f64 {
+        loop {
+            let (target, seconds) = match self.stage {
+                Stage::Idle => return 0.0,
+                Stage::Sustain => {
+                    self.sustain.set_target(self.settings.sustain);
+                    self.level = self.sustain.next_sample();
+                    return self.level;
+                }
+                Stage::Delay => (self.from, self.settings.delay),
+                Stage::Attack => (1.0, self.settings.attack),
+                Stage::Hold => (1.0, self.settings.hold),
+                Stage::Decay => (self.settings.sustain, self.settings.decay),
+                Stage::Release => (0.0, self.settings.release),
+            };
+
+            let length = (seconds * self.sample_rate).round() as usize;
+
+            if self.elapsed >= length {
+                self.level = target;
+                self.enter(self.stage.next());
+                continue;
+            }
+
+            self.elapsed += 1;
+
+            let progress = self.elapsed as f64 / length as f64;
+
+            self.level = self.from + (target - self.from) * self.settings.curve.shape(progress);
+
+            return self.level;
+        }
+    }
+
+    pub fn process(&mut self, output: &mut [f64]) {
+        for sample in output.iter_mut() {
+            *sample = self.next_sample();
+        }
+    }
+
+    fn enter(&mut self, stage: Stage) {
+        self.stage = stage;
+        self.elapsed = 0;
+        self.from = self.level;
+
+        if stage == Stage::Sustain {
+            self.sustain.reset(self.level);
+        }
+    }
+}
+
+impl Stage {
+    fn next(&self) -> Stage {
+        match self {
+            Stage::Idle => Stage::Idle,
+            Stage::Delay => Stage::Attack,
+            Stage::Attack => Stage::Hold,
+            Stage::Hold => Stage::Decay,
+            Stage::Decay => Stage::Sustain,
+            Stage::Sustain => Stage::Sustain,
+            Stage::Release => Stage::Idle,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 1_000.0;
+
+    fn linear() -> Adsr {
+        Adsr {
+            delay: 0.0,
+            attack: 0.01,
+            hold: 0.0,
+            decay: 0.01,
+            sustain: 0.5,
+            release: 0.01,
+            curve: Curve::Linear,
+            mode: TriggerMode::Retrigger,
+        }
+    }
+
+    fn run(envelope: &mut Envelope, samples: usize) -> Vec<f64> {
+        let mut output = vec![0.0; samples];
+
+        envelope.process(&mut output);
+
+        output
+    }
+
+    #[test]
+    fn runs_linear_stages() {
+        let mut envelope = Envelope::new(linear(), SAMPLE_RATE);
+
+        assert!(envelope.next_sample() == 0.0);
+
+        envelope.gate_on();
+
+        let attack = run(&mut envelope, 10);
+        assert!((attack[0] - 0.1).abs() < 1e-9);
+        assert!((attack[9] - 1.0).abs() < 1e-9);
+
+        let decay = run(&mut envelope, 10);
+        assert!((decay[4] - 0.75).abs() < 1e-9);
+        assert!((decay[9] - 0.5).abs() < 1e-9);
+
+        assert!(run(&mut envelope, 100).iter().all(|level| *level == 0.5));
+        assert!(envelope.stage() == Stage::Sustain);
+
+        envelope.gate_off();
+
+        let release = run(&mut envelope, 10);
+        assert!((release[4] - 0.25).abs() < 1e-9);
+        assert!(release[9].abs() < 1e-9);
+        assert!(envelope.next_sample() == 0.0);
+        assert!(envelope.is_idle());
+    }
+
+    #[test]
+    fn delays_and_holds() {
+        let mut envelope = Envelope::new(
+            Adsr {
+                delay: 0.005,
+                hold: 0.005,
+                ..linear()
+            },
+            SAMPLE_RATE,
+        );
+
+        envelope.gate_on();
+
+        let output = run(&mut envelope, 25);
+
+        assert!(output[..5].iter().all(|level| *level == 0.0));
+        assert!(output[5] > 0.0);
+        assert!(output[14..20].iter().all(|level| *level == 1.0));
+        assert!(output[20] < 1.0);
+    }
+
+    #[test]
+    fn exponential_settles_slowly() {
+        let mut envelope = Envelope::new(
+            Adsr {
+                curve: Curve::Exponential,
+                ..linear()
+            },
+            SAMPLE_RATE,
+        );
+
+        envelope.gate_on();
+
+        let attack = run(&mut envelope, 10);
+
+        assert!(attack[0] > 0.1);
+        assert!(attack[4] > 0.5);
+        assert!((attack[9] - 1.0).abs() < 1e-9);
+        assert!(attack.windows(2).all(|pair| pair[1] > pair[0]));
+    }
+
+    #[test]
+    fn releases_from_current_level() {
+        let mut envelope = Envelope::new(linear(), SAMPLE_RATE);
+
+        envelope.gate_on();
+        run(&mut envelope, 5);
+        envelope.gate_off();
+
+        let release = run(&mut envelope, 10);
+
+        assert!((release[0] - 0.45).abs() < 1e-9);
+        assert!(release[9].abs() < 1e-9);
+    }
+
+    #[test]
+    fn retriggers_without_jumping() {
+        let mut envelope = Envelope::new(linear(), SAMPLE_RATE);
+
+        envelope.gate_on();
+        run(&mut envelope, 30);
+        envelope.gate_on();
+
+        assert!(envelope.stage() == Stage::Delay);
+        assert!((envelope.next_sample() - 0.55).abs() < 1e-9);
+    }
+
+    #[test]
+    fn legato_ignores_gate_while_held() {
+        let mut envelope = Envelope::new(
+            Adsr {
+                mode: TriggerMode::Legato,
+                ..linear()
+            },
+            SAMPLE_RATE,
+        );
+
+        envelope.gate_on();
+        run(&mut envelope, 30);
+        envelope.gate_on();
+
+        assert!(envelope.stage() == Stage::Sustain);
+
+        envelope.gate_off();
+        run(&mut envelope, 5);
+        envelope.gate_on();
+
+        assert!(envelope.stage() == Stage::Delay);
+    }
+
+    #[test]
+    fn gates_are_sample_accurate() {
+        let mut envelope = Envelope::new(
+            Adsr {
+                attack: 0.0,
+                ..linear()
+            },
+            SAMPLE_RATE,
+        );
+
+        assert!(envelope.next_sample() == 0.0);
+        envelope.gate_on();
+        assert!(envelope.next_sample() > 0.9);
+    }
+}
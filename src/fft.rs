@@ -0,0 +1,122 @@
+use std::f64::consts::PI;
+
+///
+/// In-place iterative radix-2 FFT over separate real and imaginary buffers.
+/// Both buffers must share the same power-of-two length.
+///
+pub fn fft(re: &mut [f64], im: &mut [f64]) {
+    transform(re, im, -1.0);
+}
+
+///
+/// Inverse of `fft`, including the `1 / n` normalisation.
+///
+pub fn ifft(re: &mut [f64], im: &mut [f64]) {
+    transform(re, im, 1.0);
+
+    let n = re.len() as f64;
+
+    for (re, im) in re.iter_mut().zip(im.iter_mut()) {
+        *re /= n;
+        *im /= n;
+    }
+}
+
+fn transform(re: &mut [f64], im: &mut [f64], sign: f64) {
+    let n = re.len();
+
+    assert!(n == im.len(), "Real and imaginary buffers differ in length");
+    assert!(n.is_power_of_two(), "FFT length {n} is not a power of two");
+
+    let mut j = 0;
+
+    for i in 1..n {
+        let mut bit = n >> 1;
+
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+
+        j |= bit;
+
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut size = 2;
+
+    while size <= n {
+        let angle = sign * 2.0 * PI / size as f64;
+        let (w_im, w_re) = angle.sin_cos();
+
+        for start in (0..n).step_by(size) {
+            let (mut t_re, mut t_im) = (1.0, 0.0);
+
+            for k in 0..size / 2 {
+                let a = start + k;
+                let b = a + size / 2;
+
+                let x_re = re[b] * t_re - im[b] * t_im;
+                let x_im = re[b] * t_im + im[b] * t_re;
+
+                re[b] = re[a] - x_re;
+                im[b] = im[a] - x_im;
+                re[a] += x_re;
+                im[a] += x_im;
+
+                (t_re, t_im) = (t_re * w_re - t_im * w_im, t_re * w_im + t_im * w_re);
+            }
+        }
+
+        size *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_naive_dft() {
+        let n = 16;
+        let signal: Vec<f64> = (0..n).map(|i| ((i * 7 % 5) as f64 - 2.0) * 0.3).collect();
+
+        let mut re = signal.clone();
+        let mut im = vec![0.0; n];
+
+        fft(&mut re, &mut im);
+
+        for k in 0..n {
+            let (mut expected_re, mut expected_im) = (0.0, 0.0);
+
+            for (i, x) in signal.iter().enumerate() {
+                let phase = -2.0 * PI * (k * i) as f64 / n as f64;
+                expected_re += x * phase.cos();
+                expected_im += x * phase.sin();
+            }
+
+            assert!((re[k] - expected_re).abs() < 1e-9);
+            assert!((im[k] - expected_im).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let signal: Vec<f64> = (0..64).map(|i| (i as f64 * 0.37).sin()).collect();
+
+        let mut re = signal.clone();
+        let mut im = vec![0.0; 64];
+
+        fft(&mut re, &mut im);
+        ifft(&mut re, &mut im);
+
+        for (a, b) in re.iter().zip(&signal) {
+            assert!((a - b).abs() < 1e-12);
+        }
+
+        assert!(im.iter().all(|x| x.abs() < 1e-12));
+    }
+}
@@ -0,0 +1,386 @@
+use std::f64::consts::{PI, SQRT_2};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum FilterType {
+    #[default]
+    LowPass,
+    HighPass,
+    BandPass,
+    Notch,
+    /// Four-pole (24 dB/octave) ladder lowpass.
+    Ladder,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FilterSettings {
+    pub kind: FilterType,
+    pub cutoff: f64,
+    /// From `0.0` (none) to `1.0` (self-oscillation).
+    pub resonance: f64,
+}
+
+///
+/// Zero-delay-feedback state-variable filter (Simper's trapezoidal SVF).
+/// The cutoff can change every sample without the state blowing up.
+///
+#[derive(Clone, Debug)]
+pub struct StateVariableFilter {
+    kind: FilterType,
+    sample_rate: f64,
+    g: f64,
+    k: f64,
+    ic1eq: f64,
+    ic2eq: f64,
+}
+
+///
+/// Zero-delay-feedback four-pole ladder lowpass, made of trapezoidal
+/// one-poles with the global feedback loop solved instantaneously.
+///
+#[derive(Clone, Debug)]
+pub struct LadderFilter {
+    sample_rate: f64,
+    g: f64,
+    k: f64,
+    state: [f64; 4],
+}
+
+#[derive(Clone, Debug)]
+pub enum Filter {
+    StateVariable(StateVariableFilter),
+    Ladder(LadderFilter),
+}
+
+impl Default for FilterSettings {
+    fn default() -> Self {
+        Self {
+            kind: FilterType::default(),
+            cutoff: 20_000.0,
+            resonance: 0.0,
+        }
+    }
+}
+
+///
+/// Prewarped integrator gain, with the cutoff kept safely below Nyquist.
+///
+fn prewarp(cutoff: f64, sample_rate: f64) -> f64 {
+    // @note: The upper bound is floored too, so that tiny or NaN rates can't invert the range
+    let cutoff = cutoff.clamp(1.0, (sample_rate * 0.49).max(1.0));
+
+    (PI * cutoff / sample_rate).tan()
+}
+
+impl StateVariableFilter {
+    pub fn new(kind: FilterType, cutoff: f64, resonance: f64, sample_rate: f64) -> Self {
+        assert!(
+            kind != FilterType::Ladder,
+            "Use LadderFilter for the ladder type"
+        );
+
+        let mut filter = Self {
+            kind,
+            sample_rate,
+            g: 0.0,
+            k: 0.0,
+            ic1eq: 0.0,
+            ic2eq: 0.0,
+        };
+
+        filter.set_cutoff(cutoff);
+        filter.set_resonance(resonance);
+        filter
+    }
+
+    pub fn set_cutoff(&mut self, cutoff: f64) {
+        self.g = prewarp(cutoff, self.sample_rate);
+    }
+
+    ///
+    /// Zero resonance is a Butterworth response (Q of 1/√2).
+    ///
+    pub fn set_resonance(&mut self, resonance: f64) {
+        self.k = SQRT_2 * (1.0 - resonance.clamp(0.0, 1.0));
+    }
+
+    pub fn reset(&mut self) {
+        self.ic1eq = 0.0;
+        self.ic2eq = 0.0;
+    }
+
+    pub fn process(&mut self, input: f64) -> f64 {
+        let a1 = 1.0 / (1.0 + self.g * (self.g + self.k));
+        let a2 = self.g * a1;
+        let a3 = self.g * a2;
+
+        let v3 = input - self.ic2eq;
+        let v1 = a1 * self.ic1eq + a2 * v3;
+        let v2 = self.ic2eq + a2 * self.ic1eq + a3 * v3;
+
+        self.ic1eq = 2.0 * v1 - self.ic1eq;
+        self.ic2eq = 2.0 * v2 - self.ic2eq;
+
+        match self.kind {
+            FilterType::LowPass | FilterType::Ladder => v2,
+            FilterType::BandPass => v1,
+            FilterType::HighPass => input - self.k * v1 - v2,
+            FilterType::Notch => input - self.k * v1,
+        }
+    }
+}
+
+impl LadderFilter {
+    pub fn new(cutoff: f64, resonance: f64, sample_rate: f64) -> Self {
+        let mut filter = Self {
+            sample_rate,
+            g: 0.0,
+            k: 0.0,
+            state: [0.0; 4],
+        };
+
+        filter.set_cutoff(cutoff);
+        filter.set_resonance(resonance);
+        filter
+    }
+
+    pub fn set_cutoff(&mut self, cutoff: f64) {
+        self.g = prewarp(cutoff, self.sample_rate);
+    }
+
+    ///
+    /// Full resonance is a feedback gain of four, where the ladder
+    /// self-oscillates.
+    ///
+    pub fn set_resonance(&mut self, resonance: f64) {
+        self.k = 4.0 * resonance.clamp(0.0, 1.0);
+    }
+
+    pub fn reset(&mut self) {
+        self.state = [0.0; 4];
+    }
+
+    pub fn process(&mut self, input: f64) -> f64 {
+        let gain = self.g / (1.0 + self.g);
+
+        // Each stage is `y = gain * x + s / (1 + g)`, so the ladder output is
+        // `gain^4 * u + sigma`, letting the feedback loop be solved directly.
+        let sigma = self
+            .state
+            .iter()
+            .fold(0.0, |sigma, state| sigma * gain + state / (1.0 + self.g));
+
+        let mut x = (input - self.k * sigma) / (1.0 + self.k * gain.powi(4));
+
+        for state in self.state.iter_mut() {
+            let v = (x - *state) * gain;
+            let y = v + *state;
+
+            *state = y + v;
+            x = y;
+        }
+
+        x
+    }
+}
+
+impl Filter {
+    pub fn new(settings: FilterSettings, sample_rate: f64) -> Self {
+        match settings.kind {
+            FilterType::Ladder => Filter::Ladder(LadderFilter::new(
+                settings.cutoff,
+                settings.resonance,
+                sample_rate,
+            )),
+            kind => Filter::StateVariable(StateVariableFilter::new(
+                kind,
+                settings.cutoff,
+                settings.resonance,
+                sample_rate,
+            )),
+        }
+    }
+
+    ///
+    /// Retunes in place, keeping the filter state unless the type changes.
+    ///
+    pub fn configure(&mut self, settings: FilterSettings) {
+        let (kind, sample_rate) = match self {
+            Filter::StateVariable(filter) => (filter.kind, filter.sample_rate),
+            Filter::Ladder(filter) => (FilterType::Ladder, filter.sample_rate),
+        };
+
+        if kind != settings.kind {
+            *self = Filter::new(settings, sample_rate);
+            return;
+        }
+
+        self.set_cutoff(settings.cutoff);
+        self.set_resonance(settings.resonance);
+    }
+
+    pub fn set_cutoff(&mut self, cutoff: f64) {
+        match self {
+            Filter::StateVariable(filter) => filter.set_cutoff(cutoff),
+            Filter::Ladder(filter) => filter.set_cutoff(cutoff),
+        }
+    }
+
+    pub fn set_resonance(&mut self, resonance: f64) {
+        match self {
+            Filter::StateVariable(filter) => filter.set_resonance(resonance),
+            Filter::Ladder(filter) => filter.set_resonance(resonance),
+        }
+    }
+
+    ///
+    /// The new rate applies from the next `set_cutoff`.
+    ///
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        match self {
+            Filter::StateVariable(filter) => filter.sample_rate = sample_rate,
+            Filter::Ladder(filter) => filter.sample_rate = sample_rate,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        match self {
+            Filter::StateVariable(filter) => filter.reset(),
+            Filter::Ladder(filter) => filter.reset(),
+        }
+    }
+
+    pub fn process(&mut self, input: f64) -> f64 {
+        match self {
+            Filter::StateVariable(filter) => filter.process(input),
+            Filter::Ladder(filter) => filter.process(input),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 48_000.0;
+
+    ///
+    /// Renders a sine through `filter` and returns its steady-state gain in dB.
+    ///
+    fn response(mut filter: Filter, frequency: f64) -> f64 {
+        let length = (SAMPLE_RATE * 0.5) as usize;
+        let mut input_power = 0.0;
+        let mut output_power = 0.0;
+
+        for n in 0..length {
+            let input = (2.0 * PI * frequency * n as f64 / SAMPLE_RATE).sin();
+            let output = filter.process(input);
+
+            if n >= length / 2 {
+                input_power += input * input;
+                output_power += output * output;
+            }
+        }
+
+        10.0 * (output_power / input_power).log10()
+    }
+
+    fn filter(kind: FilterType, cutoff: f64, resonance: f64) -> Filter {
+        Filter::new(
+            FilterSettings {
+                kind,
+                cutoff,
+                resonance,
+            },
+            SAMPLE_RATE,
+        )
+    }
+
+    #[test]
+    fn lowpass_response() {
+        let lowpass = || filter(FilterType::LowPass, 1_000.0, 0.0);
+
+        assert!(response(lowpass(), 100.0).abs() < 0.1);
+        assert!((response(lowpass(), 1_000.0) + 3.0).abs() < 0.2);
+        assert!(response(lowpass(), 10_000.0) < -38.0);
+    }
+
+    #[test]
+    fn highpass_response() {
+        let highpass = || filter(FilterType::HighPass, 1_000.0, 0.0);
+
+        assert!(response(highpass(), 100.0) < -38.0);
+        assert!((response(highpass(), 1_000.0) + 3.0).abs() < 0.2);
+        assert!(response(highpass(), 10_000.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn bandpass_and_notch_response() {
+        let bandpass = || filter(FilterType::BandPass, 1_000.0, 0.5);
+        let notch = || filter(FilterType::Notch, 1_000.0, 0.5);
+
+        assert!(response(bandpass(), 1_000.0) > response(bandpass(), 200.0) + 10.0);
+        assert!(response(bandpass(), 1_000.0) > response(bandpass(), 5_000.0) + 10.0);
+
+        assert!(response(notch(), 1_000.0) < -40.0);
+        assert!(response(notch(), 100.0).abs() < 0.5);
+        assert!(response(notch(), 10_000.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn ladder_response() {
+        let ladder = |resonance| filter(FilterType::Ladder, 1_000.0, resonance);
+
+        assert!(response(ladder(0.0), 100.0).abs() < 0.25);
+        assert!(response(ladder(0.0), 10_000.0) < -75.0);
+
+        // Resonance thins the passband and peaks around the cutoff.
+        assert!(response(ladder(0.8), 1_000.0) > response(ladder(0.8), 100.0) + 6.0);
+    }
+
+    #[test]
+    fn resonance_peaks() {
+        let flat = response(filter(FilterType::LowPass, 1_000.0, 0.0), 1_000.0);
+        let resonant = response(filter(FilterType::LowPass, 1_000.0, 0.9), 1_000.0);
+
+        assert!(resonant > flat + 12.0);
+    }
+
+    #[test]
+    fn stable_under_per_sample_modulation() {
+        for kind in [
+            FilterType::LowPass,
+            FilterType::HighPass,
+            FilterType::BandPass,
+            FilterType::Notch,
+            FilterType::Ladder,
+        ] {
+            let mut filter = filter(kind, 1_000.0, 0.95);
+            let mut seed: u32 = 1;
+
+            for n in 0..SAMPLE_RATE as usize {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+
+                let cutoff = 20.0 + (seed >> 8) as f64 / (1 << 24) as f64 * 23_000.0;
+                let input = if n % 100 < 50 { 1.0 } else { -1.0 };
+
+                filter.set_cutoff(cutoff);
+
+                let output = filter.process(input);
+
+                assert!(
+                    output.is_finite() && output.abs() < 100.0,
+                    "{kind:?} {output}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn prewarp_survives_degenerate_sample_rates() {
+        for sample_rate in [0.0, 1.0, f64::NAN] {
+            prewarp(1_000.0, sample_rate);
+        }
+
+        assert!(prewarp(1_000.0, 2.0).is_finite());
+    }
+}
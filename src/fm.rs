@@ -0,0 +1,367 @@
+use crate::{oscillator::Oscillator, wavetable::WavetableIter};
+
+pub const OPERATOR_COUNT: usize = 4;
+
+pub const ALGORITHM_COUNT: usize = ALGORITHMS.len();
+
+///
+/// The operator whose output can feed back into its own phase, at the top
+/// of every stack.
+///
+pub const FEEDBACK_OPERATOR: usize = OPERATOR_COUNT - 1;
+
+///
+/// Cycles of phase a modulator at full level swings its target by, or in
+/// frequency mode the fraction of its target's frequency.
+///
+const MODULATION_DEPTH: f64 = 1.0;
+
+///
+/// How the operators connect. Operators are only ever modulated by
+/// higher-numbered ones, so running them from the top down has every
+/// modulator's output ready in time.
+///
+struct Algorithm {
+    modulators: [&'static [usize]; OPERATOR_COUNT],
+    /// Operators heard at the output, mixed evenly.
+    carriers: &'static [usize],
+}
+
+///
+/// The eight four-operator algorithms of the DX7's smaller siblings, from a
+/// single stack to four carriers side by side.
+///
+const ALGORITHMS: [Algorithm; 8] = [
+    // 3 -> 2 -> 1 -> 0
+    Algorithm {
+        modulators: [&[1], &[2], &[3], &[]],
+        carriers: &[0],
+    },
+    // (2 + 3) -> 1 -> 0
+    Algorithm {
+        modulators: [&[1], &[2, 3], &[], &[]],
+        carriers: &[0],
+    },
+    // (3 + (2 -> 1)) -> 0
+    Algorithm {
+        modulators: [&[1, 3], &[2], &[], &[]],
+        carriers: &[0],
+    },
+    // (1 + (3 -> 2)) -> 0
+    Algorithm {
+        modulators: [&[1, 2], &[], &[3], &[]],
+        carriers: &[0],
+    },
+    // 1 -> 0, 3 -> 2
+    Algorithm {
+        modulators: [&[1], &[], &[3], &[]],
+        carriers: &[0, 2],
+    },
+    // 3 -> 0, 1 and 2
+    Algorithm {
+        modulators: [&[3], &[3], &[3], &[]],
+        carriers: &[0, 1, 2],
+    },
+    // 3 -> 2, with 0 and 1 alone
+    Algorithm {
+        modulators: [&[], &[], &[3], &[]],
+        carriers: &[0, 1, 2],
+    },
+    // Every operator alone, as additive synthesis
+    Algorithm {
+        modulators: [&[], &[], &[], &[]],
+        carriers: &[0, 1, 2, 3],
+    },
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum FmMode {
+    /// Modulators push their targets' phase, as on the DX7, so the pitch
+    /// stays put however deep the modulation.
+    #[default]
+    Phase,
+    /// Modulators bend their targets' frequency. Feedback still moves phase.
+    Frequency,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct OperatorSettings {
+    /// Frequency as a multiple of the note's.
+    pub ratio: f64,
+    /// Detune in cents on top of `ratio`.
+    pub fine: f64,
+    /// Output level for a carrier, modulation depth for a modulator.
+    pub level: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FmSettings {
+    /// From `1` to `ALGORITHM_COUNT`.
+    pub algorithm: usize,
+    pub operators: [OperatorSettings; OPERATOR_COUNT],
+    /// How much of `FEEDBACK_OPERATOR`'s output goes back into its phase.
+    pub feedback: f64,
+    pub mode: FmMode,
+}
+
+///
+/// Operators reading the same wavetable, wired together by one of the
+/// `ALGORITHMS`.
+///
+#[derive(Clone)]
+pub struct FmOscillator {
+    settings: FmSettings,
+    frequency: f64,
+    operators: [WavetableIter; OPERATOR_COUNT],
+    /// Each operator's frequency as a multiple of the note's, from its ratio
+    /// and fine tune.
+    multipliers: [f64; OPERATOR_COUNT],
+    /// The feedback operator's last two outputs, averaged as on the DX7 to
+    /// keep high feedback from squealing.
+    history: [f64; 2],
+}
+
+impl Default for OperatorSettings {
+    fn default() -> Self {
+        Self {
+            ratio: 1.0,
+            fine: 0.0,
+            level: 1.0,
+        }
+    }
+}
+
+impl Default for FmSettings {
+    fn default() -> Self {
+        Self {
+            algorithm: 1,
+            operators: [OperatorSettings::default(); OPERATOR_COUNT],
+            feedback: 0.0,
+            mode: FmMode::default(),
+        }
+    }
+}
+
+impl FmOscillator {
+    ///
+    /// Every operator plays its own copy of `operator`, sharing its tables.
+    ///
+    pub fn new(operator: WavetableIter, settings: FmSettings) -> Self {
+        let multipliers = settings
+            .operators
+            .map(|operator| operator.ratio * 2.0_f64.powf(operator.fine / 1_200.0));
+        let frequency = operator.frequency;
+
+        let mut oscillator = Self {
+            settings,
+            frequency,
+            operators: std::array::from_fn(|_| operator.clone()),
+            multipliers,
+            history: [0.0; 2],
+        };
+
+        oscillator.set_frequency(frequency);
+        oscillator.reset_phase();
+        oscillator
+    }
+}
+
+impl Oscillator for FmOscillator {
+    fn frequency(&self) -> f64 {
+        self.frequency
+    }
+
+    fn set_frequency(&mut self, frequency: f64) {
+        self.frequency = frequency;
+
+        for (operator, multiplier) in self.operators.iter_mut().zip(self.multipliers) {
+            operator.frequency = frequency * multiplier;
+        }
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.operators[0].sample_rate()
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        for operator in &mut self.operators {
+            operator.set_sample_rate(sample_rate);
+        }
+    }
+
+    ///
+    /// The first operator's phase, which is always a carrier.
+    ///
+    fn phase(&self) -> f64 {
+        self.operators[0].phase()
+    }
+
+    fn sync(&mut self, phase: f64) {
+        for (operator, multiplier) in self.operators.iter_mut().zip(self.multipliers) {
+            operator.sync((phase * multiplier).rem_euclid(1.0));
+        }
+
+        self.history = [0.0; 2];
+    }
+
+    fn set_position(&mut self, position: f64) {
+        for operator in &mut self.operators {
+            operator.position = position;
+        }
+    }
+
+    fn next_sample(&mut self) -> f64 {
+        let FmSettings {
+            algorithm,
+            operators: settings,
+            feedback,
+            mode,
+        } = self.settings;
+        let algorithm = &ALGORITHMS[algorithm.clamp(1, ALGORITHM_COUNT) - 1];
+        let mut outputs = [0.0; OPERATOR_COUNT];
+
+        for index in (0..OPERATOR_COUNT).rev() {
+            let modulation: f64 = algorithm.modulators[index]
+                .iter()
+                .map(|modulator| outputs[*modulator] * settings[*modulator].level)
+                .sum();
+            let feedback = match index == FEEDBACK_OPERATOR {
+                true => feedback * (self.history[0] + self.history[1]) / 2.0,
+                false => 0.0,
+            };
+            let operator = &mut self.operators[index];
+
+            outputs[index] = match mode {
+                FmMode::Phase => operator.next_offset((modulation + feedback) * MODULATION_DEPTH),
+                FmMode::Frequency => {
+                    let frequency = self.frequency * self.multipliers[index];
+
+                    operator.frequency = frequency * (1.0 + modulation * MODULATION_DEPTH);
+                    operator.next_offset(feedback * MODULATION_DEPTH)
+                }
+            };
+        }
+
+        self.history = [outputs[FEEDBACK_OPERATOR], self.history[0]];
+
+        let mix: f64 = algorithm
+            .carriers
+            .iter()
+            .map(|carrier| outputs[*carrier] * settings[*carrier].level)
+            .sum();
+
+        mix / algorithm.carriers.len() as f64
+    }
+
+    fn clone_box(&self) -> Box<dyn Oscillator + Send> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        waveform,
+        wavetable::{detect_cycle_length, Interpolation, MipmappedWavetable},
+    };
+
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 48_000.0;
+
+    fn sine() -> WavetableIter {
+        MipmappedWavetable::new(2048, waveform::sine).iter(
+            0.0,
+            SAMPLE_RATE,
+            Interpolation::CubicHermite,
+        )
+    }
+
+    fn play(oscillator: &mut impl Oscillator, frequency: f64, samples: usize) -> Vec<f64> {
+        oscillator.set_frequency(frequency);
+
+        (0..samples).map(|_| oscillator.next_sample()).collect()
+    }
+
+    ///
+    /// Amplitude of `harmonic` over one cycle of `samples`.
+    ///
+    fn harmonic(samples: &[f64], harmonic: usize) -> f64 {
+        let (re, im) = samples
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(re, im), (n, x)| {
+                let angle = std::f64::consts::TAU * (harmonic * n) as f64 / samples.len() as f64;
+
+                (re + x * angle.cos(), im - x * angle.sin())
+            });
+
+        2.0 * re.hypot(im) / samples.len() as f64
+    }
+
+    fn settings(algorithm: usize, levels: [f64; OPERATOR_COUNT]) -> FmSettings {
+        FmSettings {
+            algorithm,
+            operators: levels.map(|level| OperatorSettings {
+                level,
+                ..OperatorSettings::default()
+            }),
+            ..FmSettings::default()
+        }
+    }
+
+    #[test]
+    fn plays_a_lone_carrier_unchanged() {
+        let mut fm = FmOscillator::new(sine(), settings(8, [4.0, 0.0, 0.0, 0.0]));
+        let mut plain = sine();
+
+        for (a, b) in play(&mut fm, 440.0, 1_000)
+            .iter()
+            .zip(play(&mut plain, 440.0, 1_000))
+        {
+            assert!((a - b).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn modulation_adds_harmonics_at_the_same_pitch() {
+        let phase = settings(1, [1.0, 0.5, 0.0, 0.0]);
+        let frequency = FmSettings {
+            mode: FmMode::Frequency,
+            ..phase
+        };
+        let feedback = FmSettings {
+            feedback: 0.5,
+            ..settings(8, [0.0, 0.0, 0.0, 4.0])
+        };
+
+        for settings in [phase, frequency, feedback] {
+            // A period of exactly 100 samples, skipping the first while
+            // feedback settles.
+            let samples = play(&mut FmOscillator::new(sine(), settings), 480.0, 1_000);
+
+            for (a, b) in samples[100..900].iter().zip(&samples[200..]) {
+                assert!((a - b).abs() < 1e-6);
+            }
+
+            assert!(harmonic(&samples[100..200], 2) > 0.05);
+        }
+    }
+
+    #[test]
+    fn tunes_operators_by_ratio_and_fine() {
+        let mut settings = settings(8, [1.0, 0.0, 0.0, 0.0]);
+
+        settings.operators[0].ratio = 2.0;
+        settings.operators[0].fine = 700.0;
+
+        let mut fm = FmOscillator::new(sine(), settings);
+        let samples = play(&mut fm, 220.0, 4_800);
+        let expected = 440.0 * 2.0_f64.powf(700.0 / 1_200.0);
+
+        assert!(fm.frequency() == 220.0);
+        assert!(
+            (SAMPLE_RATE / detect_cycle_length(&samples).unwrap() / expected - 1.0).abs() < 0.005
+        );
+    }
+}
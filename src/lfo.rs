@@ -0,0 +1,219 @@
+use std::f64::consts::PI;
+
+use crate::{
+    smoothing::{Ramp, Smoothed},
+    waveform::{self, WhiteNoise},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum LfoShape {
+    #[default]
+    Sine,
+    Triangle,
+    Sawtooth,
+    Square,
+    /// A new random level at the start of every cycle.
+    SampleAndHold,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum LfoRate {
+    Hertz(f64),
+    /// Cycle length in quarter-note beats, following the tempo.
+    Beats(f64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LfoSettings {
+    pub shape: LfoShape,
+    pub rate: LfoRate,
+    pub depth: f64,
+    /// Starting point in the cycle, from `0.0` to `1.0`.
+    pub phase: f64,
+}
+
+///
+/// A bipolar low-frequency oscillator, outputting `-depth..=depth`.
+///
+#[derive(Clone, Debug)]
+pub struct Lfo {
+    pub settings: LfoSettings,
+    sample_rate: f64,
+    tempo: f64,
+    /// Position in the current cycle, from `0.0` to `1.0`.
+    phase: f64,
+    held: f64,
+    noise: WhiteNoise,
+    depth: Smoothed,
+}
+
+impl Default for LfoSettings {
+    fn default() -> Self {
+        Self {
+            shape: LfoShape::default(),
+            rate: LfoRate::Hertz(1.0),
+            depth: 1.0,
+            phase: 0.0,
+        }
+    }
+}
+
+impl Lfo {
+    pub fn new(settings: LfoSettings, sample_rate: f64) -> Self {
+        let mut lfo = Self {
+            settings,
+            sample_rate,
+            tempo: 120.0,
+            phase: 0.0,
+            held: 0.0,
+            noise: WhiteNoise::default(),
+            depth: Smoothed::new(settings.depth, sample_rate),
+        };
+
+        lfo.reset();
+        lfo
+    }
+
+    ///
+    /// Beats per minute used by `LfoRate::Beats`.
+    ///
+    pub fn set_tempo(&mut self, tempo: f64) {
+        self.tempo = tempo;
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.depth.set_sample_rate(sample_rate);
+    }
+
+    ///
+    /// How a change to `settings.depth` is smoothed.
+    ///
+    pub fn set_smoothing(&mut self, time: f64, ramp: Ramp) {
+        self.depth.configure(time, ramp);
+    }
+
+    ///
+    /// Cycles per second, negative to run backwards. Rates that don't give
+    /// a finite frequency, such as a cycle of `0.0` beats, stop the LFO.
+    ///
+    pub fn frequency(&self) -> f64 {
+        let frequency = match self.settings.rate {
+            LfoRate::Hertz(frequency) => frequency,
+            LfoRate::Beats(beats) if beats > 0.0 => self.tempo / 60.0 / beats,
+            LfoRate::Beats(_) => 0.0,
+        };
+
+        match frequency.is_finite() {
+            true => frequency,
+            false => 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.phase = self.settings.phase.rem_euclid(1.0);
+        self.held = self.noise.next_sample();
+    }
+
+    pub fn next_sample(&mut self) -> f64 {
+        let phase = 2.0 * PI * self.phase;
+
+        let value = match self.settings.shape {
+            LfoShape::Sine => waveform::sine(phase),
+            LfoShape::Triangle => waveform::triangle(phase),
+            LfoShape::Sawtooth => waveform::sawtooth(phase),
+            LfoShape::Square => waveform::square(phase),
+            LfoShape::SampleAndHold => self.held,
+        };
+
+        self.phase += self.frequency() / self.sample_rate;
+
+        // Wraps either way, so that running backwards still holds new values.
+        if !(0.0..1.0).contains(&self.phase) {
+            self.phase = self.phase.rem_euclid(1.0);
+            self.held = self.noise.next_sample();
+        }
+
+        self.depth.set_target(self.settings.depth);
+
+        value * self.depth.next_sample()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lfo(shape: LfoShape, rate: LfoRate) -> Lfo {
+        Lfo::new(
+            LfoSettings {
+                shape,
+                rate,
+                ..LfoSettings::default()
+            },
+            1_000.0,
+        )
+    }
+
+    #[test]
+    fn cycles_at_rate() {
+        let mut lfo = lfo(LfoShape::Sine, LfoRate::Hertz(10.0));
+        let cycle: Vec<f64> = (0..100).map(|_| lfo.next_sample()).collect();
+
+        assert!(cycle[0].abs() < 1e-9);
+        assert!((cycle[25] - 1.0).abs() < 1e-9);
+        assert!((cycle[75] + 1.0).abs() < 1e-9);
+        assert!((lfo.next_sample() - cycle[0]).abs() < 1e-9);
+    }
+
+    #[test]
+    fn syncs_to_tempo() {
+        let mut lfo = lfo(LfoShape::Square, LfoRate::Beats(0.5));
+
+        lfo.set_tempo(150.0);
+
+        assert!((lfo.frequency() - 5.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn applies_depth_and_phase() {
+        let mut lfo = Lfo::new(
+            LfoSettings {
+                shape: LfoShape::Triangle,
+                rate: LfoRate::Hertz(1.0),
+                depth: 0.5,
+                phase: 0.25,
+            },
+            1_000.0,
+        );
+
+        assert!((lfo.next_sample() - 0.5).abs() < 1e-9);
+        assert!((0..1_000).all(|_| lfo.next_sample().abs() <= 0.5 + 1e-9));
+    }
+
+    #[test]
+    fn holds_random_values_per_cycle() {
+        let mut lfo = lfo(LfoShape::SampleAndHold, LfoRate::Hertz(10.0));
+
+        let first: Vec<f64> = (0..100).map(|_| lfo.next_sample()).collect();
+        let second = lfo.next_sample();
+
+        assert!(first.iter().all(|value| *value == first[0]));
+        assert!(second != first[0]);
+        assert!((-1.0..=1.0).contains(&second));
+    }
+
+    #[test]
+    fn survives_degenerate_rates() {
+        let mut stopped = lfo(LfoShape::Sine, LfoRate::Beats(0.0));
+
+        assert!(stopped.frequency() == 0.0);
+        assert!((0..1_000).all(|_| stopped.next_sample().is_finite()));
+
+        let mut backwards = lfo(LfoShape::SampleAndHold, LfoRate::Hertz(-10.0));
+        let first: Vec<f64> = (0..300).map(|_| backwards.next_sample()).collect();
+
+        assert!(first.iter().any(|value| *value != first[0]));
+        assert!((0..1_000).all(|_| (-1.0..=1.0).contains(&backwards.next_sample())));
+    }
+}
@@ -1,4 +1,35 @@
+pub mod arpeggiator;
+pub mod channel_layout;
+pub mod chorus;
+pub mod clock;
+pub mod delay;
+pub mod device;
+pub mod dither;
+pub mod effects;
+pub mod engine;
+pub mod envelope;
+pub mod fft;
+pub mod filter;
+// @note: Kept as they were, outside the lint gate
+#[allow(dead_code, unused_imports, clippy::needless_return)]
 pub mod fixed_window_rate_limiter;
+pub mod fm;
+pub mod lfo;
+pub mod master;
+pub mod midi;
+pub mod modulation;
+#[allow(clippy::bool_comparison, clippy::needless_borrow)]
 pub mod operational_transformation;
+pub mod oscillator;
+pub mod preset;
+pub mod render;
+pub mod reverb;
+pub mod sequencer;
+pub mod smoothing;
+pub mod synth;
+pub mod tuning;
+pub mod unison;
+pub mod voice;
+pub mod wav;
 pub mod waveform;
 pub mod wavetable;
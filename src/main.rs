@@ -1,30 +1,55 @@
 use std::io::Write;
 use std::thread;
+use std::time::{Duration, Instant};
 
-use clap::Command;
-use cpal::{
-    traits::{DeviceTrait, HostTrait, StreamTrait},
-    FromSample, Sample, SampleRate, SizedSample,
-};
-use crossbeam::channel::bounded;
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
 use eframe::egui;
-use rust_playground::{waveform, wavetable};
+use rust_playground::{
+    arpeggiator::{ArpMode, ArpSettings, MAX_OCTAVES},
+    chorus::ChorusSettings,
+    delay::{DelaySettings, DelayTime},
+    device::{self, DeviceRequest, Output},
+    dither::Dither,
+    effects::{self, EffectKind, Routing, SlotSettings, EFFECT_COUNT},
+    engine::{self, Engine, EngineHandle},
+    envelope::Adsr,
+    filter::{FilterSettings, FilterType},
+    fm::{FmMode, FmSettings, OperatorSettings, ALGORITHM_COUNT, OPERATOR_COUNT},
+    lfo::{LfoRate, LfoSettings, LfoShape},
+    master::{to_decibels, LimiterSettings, MeterHandle},
+    midi::{MidiFile, MidiMessage},
+    modulation::{ModDestination, ModRoute, ModSource, LFO_COUNT},
+    preset::{self, OscillatorSettings, Preset, PresetFormat, Shape, WavetableSource},
+    render,
+    reverb::ReverbSettings,
+    sequencer::{SequencerSettings, Step, MAX_STEPS},
+    smoothing::Ramp,
+    synth::Synth,
+    tuning::{KeyboardMapping, Scale, Tuning},
+    unison::{UnisonSettings, MAX_UNISON},
+    wav::SampleFormat,
+    wavetable::PhaseAccumulator,
+};
 
-enum MainThreadMessage {
-    Sample(f64),
-}
+///
+/// Seconds rendered past the last event of a MIDI file, for releases to ring out.
+///
+const MIDI_TAIL: f64 = 1.0;
 
-enum AudioThreadMessage {
-    RequestSample,
-}
+const DITHER_MODES: [&str; 4] = ["none", "rectangular", "triangular", "shaped"];
+
+const EFFECTS: [&str; EFFECT_COUNT] = ["chorus", "delay", "reverb"];
 
+#[allow(dead_code)] // @note: Unused until the egui window is re-enabled
 #[derive(Default)]
 struct App {
     frequency: f64,
+    meter: Option<MeterHandle>,
 }
 
+#[allow(dead_code)]
 impl App {
-    fn new(cc: &eframe::CreationContext<'_>) -> Self {
+    fn new(_cc: &eframe::CreationContext<'_>) -> Self {
         // Customize egui here with cc.egui_ctx.set_fonts and cc.egui_ctx.set_visuals.
         // Restore app state using cc.storage (requires the "persistence" feature).
         // Use the cc.gl (a glow::Context) to create graphics shaders and buffers that you can use
@@ -34,44 +59,48 @@ impl App {
 }
 
 impl eframe::App for App {
-    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading("Hello World!");
             ui.add(egui::Slider::new(&mut self.frequency, 0.0..=120.0).text("frequency"));
+
+            if let Some(meter) = &self.meter {
+                let reading = meter.read();
+
+                for (label, levels) in [("Peak", reading.peak), ("RMS", reading.rms)] {
+                    let [left, right] = levels.map(to_decibels);
+
+                    ui.label(format!("{label}: {left:.1} / {right:.1} dBFS"));
+                }
+
+                ui.label(format!(
+                    "Limiter: {:.1} dB",
+                    to_decibels(reading.limiter_gain)
+                ));
+
+                if reading.muted {
+                    ui.colored_label(egui::Color32::RED, "Muted after a non-finite sample");
+                }
+
+                ctx.request_repaint();
+            }
         });
     }
 }
 
 fn main() -> Result<(), String> {
-    let host = cpal::default_host();
-
-    let device = host
-        .default_output_device()
-        .expect("No default audio device active on host");
-
-    let config = device
-        .default_output_config()
-        .expect("No default audio output device config");
-
-    let handle = thread::spawn(move || {
-        match config.sample_format() {
-            cpal::SampleFormat::I8 => run::<i8>(&device, &config.into()),
-            cpal::SampleFormat::I16 => run::<i16>(&device, &config.into()),
-            // cpal::SampleFormat::I24 => run::<I24>(&device, &config.into()),
-            cpal::SampleFormat::I32 => run::<i32>(&device, &config.into()),
-            // cpal::SampleFormat::I48 => run::<I48>(&device, &config.into()),
-            cpal::SampleFormat::I64 => run::<i64>(&device, &config.into()),
-            cpal::SampleFormat::U8 => run::<u8>(&device, &config.into()),
-            cpal::SampleFormat::U16 => run::<u16>(&device, &config.into()),
-            // cpal::SampleFormat::U24 => run::<U24>(&device, &config.into()),
-            cpal::SampleFormat::U32 => run::<u32>(&device, &config.into()),
-            // cpal::SampleFormat::U48 => run::<U48>(&device, &config.into()),
-            cpal::SampleFormat::U64 => run::<u64>(&device, &config.into()),
-            cpal::SampleFormat::F32 => run::<f32>(&device, &config.into()),
-            cpal::SampleFormat::F64 => run::<f64>(&device, &config.into()),
-            sample_format => panic!("Unsupported sample format '{sample_format}'"),
-        }
-    });
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // @note: Commands given on the command line run once, without opening a device
+    if !args.is_empty() {
+        return dispatch(args, None, None, &mut Preset::default()).map(|_| ());
+    }
+
+    // @note: The supervisor retunes the synth to whatever rate the device opens at
+    let (engine, engine_handle) = Engine::new(Synth::new(48_000.0));
+
+    let output = Output::start(engine, DeviceRequest::default());
+    let mut patch = Preset::default();
 
     // let native_options = eframe::NativeOptions::default();
 
@@ -82,8 +111,6 @@ fn main() -> Result<(), String> {
     // )
     // .unwrap();
 
-    // handle.join().unwrap();
-
     loop {
         let line = readline()?;
         let line = line.trim();
@@ -92,7 +119,7 @@ fn main() -> Result<(), String> {
             continue;
         }
 
-        match respond(line) {
+        match respond(line, &engine_handle, &output, &mut patch) {
             Ok(quit) => {
                 if quit {
                     break Ok(());
@@ -106,79 +133,631 @@ fn main() -> Result<(), String> {
     }
 }
 
-pub fn run<T>(device: &cpal::Device, config: &cpal::StreamConfig) -> !
+fn respond(
+    line: &str,
+    engine: &EngineHandle,
+    output: &Output,
+    patch: &mut Preset,
+) -> Result<bool, String> {
+    dispatch(line.split_whitespace(), Some(engine), Some(output), patch)
+}
+
+fn dispatch<I, T>(
+    args: I,
+    engine: Option<&EngineHandle>,
+    output: Option<&Output>,
+    patch: &mut Preset,
+) -> Result<bool, String>
 where
-    T: SizedSample + FromSample<f32>,
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
 {
-    let channels = config.channels as usize;
-    let SampleRate(sample_rate) = config.sample_rate;
+    let matches = cli()
+        .try_get_matches_from(args)
+        .map_err(|e| e.to_string())?;
 
-    let (main_producer, audio_consumer) = bounded(1_000); // @note: Could also use a zero-size?
-    let (audio_producer, main_consumer) = bounded(1_000);
+    match matches.subcommand() {
+        Some(("ping", _matches)) => {
+            write!(std::io::stdout(), "Pong").map_err(|e| e.to_string())?;
+            std::io::stdout().flush().map_err(|e| e.to_string())?;
+        }
+        Some(("render", matches)) => {
+            let path = matches.get_one::<String>("PATH").unwrap();
+            let seconds = matches.get_one::<f64>("seconds").copied();
+            let sample_rate = *matches.get_one::<u32>("sample-rate").unwrap();
+            let dither = parse_dither(matches.get_one::<String>("dither").unwrap());
+            let format = match matches.get_one::<String>("format").unwrap().as_str() {
+                "pcm16" => SampleFormat::Pcm16,
+                "pcm24" => SampleFormat::Pcm24,
+                "float32" => SampleFormat::Float32,
+                _ => unreachable!("format restricted by value parser"),
+            };
 
-    let mut saw_table = wavetable::Wavetable::new(64);
+            // @note: Without --preset the REPL renders whatever it is playing
+            let preset = match matches.get_one::<String>("preset") {
+                Some(name) => Preset::read(preset::resolve(name)?)?,
+                None => patch.clone(),
+            };
 
-    saw_table.fill(waveform::sawtooth);
+            let seconds = match matches.get_one::<String>("midi") {
+                Some(midi) => {
+                    let mut file = MidiFile::read(midi)?;
+                    let seconds = seconds.unwrap_or(file.duration() + MIDI_TAIL);
 
-    let mut wavetable_iter_220 = saw_table.iter(220.0_f64, sample_rate as f64);
+                    render::render_midi_to_wav(
+                        path,
+                        &preset,
+                        &mut file,
+                        seconds,
+                        sample_rate,
+                        format,
+                        dither,
+                    )?;
+                    seconds
+                }
+                None => {
+                    let seconds = seconds.unwrap_or(2.0);
 
-    let mut next_sample = move || {
-        audio_producer
-            .send(AudioThreadMessage::RequestSample)
-            .unwrap();
+                    render::render_to_wav(path, &preset, seconds, sample_rate, format, dither)?;
+                    seconds
+                }
+            };
 
-        match audio_consumer.recv().unwrap() {
-            MainThreadMessage::Sample(sample) => sample.to_sample(),
+            writeln!(std::io::stdout(), "Rendered {seconds}s to {path}")
+                .map_err(|e| e.to_string())?;
+            std::io::stdout().flush().map_err(|e| e.to_string())?;
         }
-    };
+        Some(("note-on", matches)) => {
+            let note = *matches.get_one::<u8>("NOTE").unwrap();
+            let velocity = *matches.get_one::<u8>("VELOCITY").unwrap();
 
-    let stream = device
-        .build_output_stream(
-            config,
-            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-                write_data(data, channels, &mut next_sample) // @note: Separate thread
-            },
-            move |err| println!("Stream error: {}", err),
-            None,
-        )
-        .expect("Stream built based on proper config");
+            send(engine, patch, engine::Command::NoteOn { note, velocity })?;
+        }
+        Some(("note-off", matches)) => {
+            let note = *matches.get_one::<u8>("NOTE").unwrap();
 
-    stream.play().expect("Stream is played");
+            send(engine, patch, engine::Command::NoteOff { note })?;
+        }
+        Some(("midi", matches)) => {
+            let bytes: Vec<u8> = matches.get_many::<u8>("BYTES").unwrap().copied().collect();
+            let message = MidiMessage::parse(&bytes)
+                .ok_or_else(|| format!("Unsupported MIDI message {bytes:02X?}"))?;
 
-    loop {
-        match main_consumer.recv().unwrap() {
-            AudioThreadMessage::RequestSample => {
-                let sample = wavetable_iter_220.next().unwrap() * 0.1;
+            send(engine, patch, engine::Command::Midi(message))?;
+        }
+        Some(("play", matches)) => {
+            let mut file = MidiFile::read(matches.get_one::<String>("FILE").unwrap())?;
+            let engine = engine
+                .ok_or("No audio engine running, start the REPL to play live")?
+                .clone();
+
+            // @note: Playback runs on its own thread so the REPL stays responsive
+            thread::spawn(move || {
+                let start = Instant::now();
 
-                main_producer
-                    .send(MainThreadMessage::Sample(sample))
-                    .unwrap();
+                while !file.is_finished() {
+                    if engine
+                        .forward(&mut file, start.elapsed().as_secs_f64())
+                        .is_err()
+                    {
+                        break;
+                    }
+
+                    thread::sleep(Duration::from_millis(1));
+                }
+            });
+        }
+        Some(("envelope", matches)) => {
+            let seconds = |name: &str| *matches.get_one::<f64>(name).unwrap();
+
+            let settings = Adsr {
+                attack: seconds("ATTACK"),
+                decay: seconds("DECAY"),
+                sustain: seconds("SUSTAIN"),
+                release: seconds("RELEASE"),
+                ..Adsr::default()
+            };
+
+            send(engine, patch, engine::Command::SetEnvelope(settings))?;
+        }
+        Some(("filter", matches)) => {
+            let kind = match matches.get_one::<String>("TYPE").unwrap().as_str() {
+                "lowpass" => FilterType::LowPass,
+                "highpass" => FilterType::HighPass,
+                "bandpass" => FilterType::BandPass,
+                "notch" => FilterType::Notch,
+                "ladder" => FilterType::Ladder,
+                _ => unreachable!("type restricted by value parser"),
+            };
+
+            let settings = FilterSettings {
+                kind,
+                cutoff: *matches.get_one::<f64>("CUTOFF").unwrap(),
+                resonance: *matches.get_one::<f64>("RESONANCE").unwrap(),
+            };
+
+            send(engine, patch, engine::Command::SetFilter(settings))?;
+        }
+        Some(("lfo", matches)) => {
+            let index = *matches.get_one::<usize>("INDEX").unwrap();
+            let shape = match matches.get_one::<String>("SHAPE").unwrap().as_str() {
+                "sine" => LfoShape::Sine,
+                "triangle" => LfoShape::Triangle,
+                "sawtooth" => LfoShape::Sawtooth,
+                "square" => LfoShape::Square,
+                "random" => LfoShape::SampleAndHold,
+                _ => unreachable!("shape restricted by value parser"),
+            };
+            let rate = *matches.get_one::<f64>("RATE").unwrap();
+
+            if !rate.is_finite() {
+                return Err("LFO rates must be finite".to_string());
             }
+
+            if matches.get_flag("sync") && rate <= 0.0 {
+                return Err("Synced LFO rates must be above 0 beats".to_string());
+            }
+
+            let settings = LfoSettings {
+                shape,
+                rate: if matches.get_flag("sync") {
+                    LfoRate::Beats(rate)
+                } else {
+                    LfoRate::Hertz(rate)
+                },
+                depth: *matches.get_one::<f64>("DEPTH").unwrap(),
+                ..LfoSettings::default()
+            };
+
+            if index >= LFO_COUNT {
+                return Err(format!("No LFO {index}, there are {LFO_COUNT}"));
+            }
+
+            send(engine, patch, engine::Command::SetLfo { index, settings })?;
         }
-    }
-}
+        Some(("tempo", matches)) => {
+            let tempo = *matches.get_one::<f64>("BPM").unwrap();
 
-fn write_data<T>(output: &mut [T], channels: usize, next_sample: &mut dyn FnMut() -> f32)
-where
-    T: Sample + FromSample<f32>,
-{
-    for frame in output.chunks_mut(channels) {
-        let value: T = T::from_sample(next_sample());
-        for sample in frame.iter_mut() {
-            *sample = value;
+            send(engine, patch, engine::Command::SetTempo(tempo))?;
         }
-    }
-}
+        Some(("step", matches)) => {
+            let index = *matches.get_one::<usize>("INDEX").unwrap();
 
-fn respond(line: &str) -> Result<bool, String> {
-    let args = line.split('\n');
-    let matches = cli()
-        .try_get_matches_from(args)
-        .map_err(|e| e.to_string())?;
+            if index >= MAX_STEPS {
+                return Err(format!("No step {index}, there are {MAX_STEPS}"));
+            }
 
-    match matches.subcommand() {
-        Some(("ping", _matches)) => {
-            write!(std::io::stdout(), "Pong").map_err(|e| e.to_string())?;
+            let step = match matches.get_flag("rest") {
+                true => None,
+                false => Some(Step {
+                    note: *matches
+                        .get_one::<u8>("NOTE")
+                        .ok_or("Give a NOTE, or --rest")?,
+                    velocity: *matches.get_one::<u8>("VELOCITY").unwrap(),
+                    gate: *matches.get_one::<f64>("gate").unwrap(),
+                    probability: *matches.get_one::<f64>("probability").unwrap(),
+                }),
+            };
+
+            send(engine, patch, engine::Command::SetStep { index, step })?;
+        }
+        Some(("steps-clear", _matches)) => {
+            send(engine, patch, engine::Command::ClearSteps)?;
+        }
+        Some(("sequencer", matches)) => {
+            let current = patch.sequencer.settings;
+
+            let settings = SequencerSettings {
+                running: !matches.get_flag("stop"),
+                length: matches
+                    .get_one::<usize>("length")
+                    .copied()
+                    .unwrap_or(current.length),
+                division: matches
+                    .get_one::<f64>("division")
+                    .copied()
+                    .unwrap_or(current.division),
+                swing: matches
+                    .get_one::<f64>("swing")
+                    .copied()
+                    .unwrap_or(current.swing),
+            };
+
+            if !(1..=MAX_STEPS).contains(&settings.length) {
+                return Err(format!("Patterns are 1 to {MAX_STEPS} steps long"));
+            }
+
+            send(engine, patch, engine::Command::SetSequencer(settings))?;
+        }
+        Some(("arp", matches)) => {
+            let mode = match matches.get_one::<String>("MODE").unwrap().as_str() {
+                "off" => None,
+                "up" => Some(ArpMode::Up),
+                "down" => Some(ArpMode::Down),
+                "updown" => Some(ArpMode::UpDown),
+                "random" => Some(ArpMode::Random),
+                "chord" => Some(ArpMode::Chord),
+                _ => unreachable!("mode restricted by value parser"),
+            };
+            let value = |name: &str| *matches.get_one::<f64>(name).unwrap();
+
+            let settings = ArpSettings {
+                enabled: mode.is_some(),
+                mode: mode.unwrap_or(patch.arpeggiator.mode),
+                octaves: *matches.get_one::<usize>("octaves").unwrap(),
+                division: value("division"),
+                gate: value("gate"),
+                swing: value("swing"),
+            };
+
+            if !(1..=MAX_OCTAVES).contains(&settings.octaves) {
+                return Err(format!("Arpeggios span 1 to {MAX_OCTAVES} octaves"));
+            }
+
+            send(engine, patch, engine::Command::SetArpeggiator(settings))?;
+        }
+        Some(("mod", matches)) => {
+            let source = match matches.get_one::<String>("SOURCE").unwrap().as_str() {
+                "envelope" => ModSource::Envelope,
+                "velocity" => ModSource::Velocity,
+                "key" => ModSource::KeyTrack,
+                "modwheel" => ModSource::ModWheel,
+                "bend" => ModSource::PitchBend,
+                lfo => match lfo.strip_prefix("lfo").map(str::parse::<usize>) {
+                    Some(Ok(index)) if index < LFO_COUNT => ModSource::Lfo(index),
+                    _ => return Err(format!("Unknown modulation source '{lfo}'")),
+                },
+            };
+            let destination = match matches.get_one::<String>("DESTINATION").unwrap().as_str() {
+                "pitch" => ModDestination::Pitch,
+                "amplitude" => ModDestination::Amplitude,
+                "cutoff" => ModDestination::FilterCutoff,
+                "position" => ModDestination::WavetablePosition,
+                "pan" => ModDestination::Pan,
+                _ => unreachable!("destination restricted by value parser"),
+            };
+            let amount = *matches.get_one::<f64>("AMOUNT").unwrap();
+
+            send(
+                engine,
+                patch,
+                engine::Command::AddRoute(ModRoute {
+                    source,
+                    destination,
+                    amount,
+                }),
+            )?;
+        }
+        Some(("mod-clear", _matches)) => {
+            send(engine, patch, engine::Command::ClearRoutes)?;
+        }
+        Some(("polyphony", matches)) => {
+            let voices = *matches.get_one::<usize>("VOICES").unwrap();
+
+            send(engine, patch, engine::Command::SetPolyphony(voices))?;
+        }
+        Some(("gain", matches)) => {
+            let gain = *matches.get_one::<f64>("AMOUNT").unwrap();
+
+            send(engine, patch, engine::Command::SetGain(gain))?;
+        }
+        Some(("dither", matches)) => {
+            let dither = parse_dither(matches.get_one::<String>("MODE").unwrap());
+
+            send(engine, patch, engine::Command::SetDither(dither))?;
+        }
+        Some(("pan", matches)) => {
+            let pan = *matches.get_one::<f64>("POSITION").unwrap();
+
+            send(engine, patch, engine::Command::SetPan(pan))?;
+        }
+        Some(("width", matches)) => {
+            let width = *matches.get_one::<f64>("AMOUNT").unwrap();
+
+            send(engine, patch, engine::Command::SetWidth(width))?;
+        }
+        Some(("chorus", matches)) => {
+            let value = |name: &str| *matches.get_one::<f64>(name).unwrap();
+
+            let settings = ChorusSettings {
+                rate: value("RATE"),
+                depth: value("DEPTH") / 1_000.0,
+                delay: value("DELAY") / 1_000.0,
+                feedback: value("FEEDBACK"),
+            };
+
+            send(engine, patch, engine::Command::SetChorus(settings))?;
+        }
+        Some(("delay", matches)) => {
+            let time = *matches.get_one::<f64>("TIME").unwrap();
+
+            let settings = DelaySettings {
+                time: match matches.get_flag("sync") {
+                    true => DelayTime::Beats(time),
+                    false => DelayTime::Seconds(time),
+                },
+                feedback: *matches.get_one::<f64>("FEEDBACK").unwrap(),
+                ping_pong: matches.get_flag("ping-pong"),
+            };
+
+            send(engine, patch, engine::Command::SetDelay(settings))?;
+        }
+        Some(("reverb", matches)) => {
+            let value = |name: &str| *matches.get_one::<f64>(name).unwrap();
+
+            let settings = ReverbSettings {
+                room_size: value("ROOM"),
+                damping: value("DAMPING"),
+                width: value("WIDTH"),
+            };
+
+            send(engine, patch, engine::Command::SetReverb(settings))?;
+        }
+        Some(("effect", matches)) => {
+            let effect = parse_effect(matches.get_one::<String>("EFFECT").unwrap());
+
+            let settings = SlotSettings {
+                routing: match matches.get_flag("send") {
+                    true => Routing::Send,
+                    false => Routing::Insert,
+                },
+                mix: *matches.get_one::<f64>("MIX").unwrap(),
+                bypass: matches.get_flag("bypass"),
+            };
+
+            send(
+                engine,
+                patch,
+                engine::Command::SetEffect { effect, settings },
+            )?;
+        }
+        Some(("effect-order", matches)) => {
+            let order: Vec<EffectKind> = matches
+                .get_many::<String>("EFFECTS")
+                .unwrap()
+                .map(|name| parse_effect(name))
+                .collect();
+
+            effects::check_order(&order)?;
+
+            send(
+                engine,
+                patch,
+                engine::Command::SetEffectOrder(order.try_into().unwrap()),
+            )?;
+        }
+        Some(("smoothing", matches)) => {
+            let time = *matches.get_one::<f64>("SECONDS").unwrap();
+            let ramp = match matches.get_flag("linear") {
+                true => Ramp::Linear,
+                false => Ramp::OnePole,
+            };
+
+            send(engine, patch, engine::Command::SetSmoothing { time, ramp })?;
+        }
+        Some(("limiter", matches)) => {
+            let settings = LimiterSettings {
+                enabled: !matches.get_flag("off"),
+                ceiling: *matches.get_one::<f64>("CEILING").unwrap(),
+                release: *matches.get_one::<f64>("RELEASE").unwrap(),
+            };
+
+            send(engine, patch, engine::Command::SetLimiter(settings))?;
+        }
+        Some(("meter", _matches)) => {
+            let reading = engine
+                .ok_or("No audio engine running, start the REPL to play live")?
+                .meter();
+            let levels =
+                |levels: [f64; 2]| levels.map(|level| format!("{:.1}", to_decibels(level)));
+            let [peak_left, peak_right] = levels(reading.peak);
+            let [rms_left, rms_right] = levels(reading.rms);
+
+            print_lines(vec![
+                format!("Peak: {peak_left} / {peak_right} dBFS"),
+                format!("RMS: {rms_left} / {rms_right} dBFS"),
+                format!("Limiter: {:.1} dB", to_decibels(reading.limiter_gain)),
+                format!(
+                    "Faults: {}{}",
+                    reading.faults,
+                    match reading.muted {
+                        true => " (muted)",
+                        false => "",
+                    }
+                ),
+            ])?;
+        }
+        Some(("oscillator", matches)) => {
+            let source = match matches.get_one::<String>("SOURCE").unwrap().as_str() {
+                "sine" => WavetableSource::Waveform(Shape::Sine),
+                "triangle" => WavetableSource::Waveform(Shape::Triangle),
+                "sawtooth" => WavetableSource::Waveform(Shape::Sawtooth),
+                "square" => WavetableSource::Waveform(Shape::Square),
+                path => WavetableSource::Wav {
+                    path: path.into(),
+                    cycle_length: matches.get_one::<usize>("cycle-length").copied(),
+                },
+            };
+
+            let settings = OscillatorSettings {
+                source,
+                accumulator: match matches.get_flag("fixed-phase") {
+                    true => PhaseAccumulator::Fixed,
+                    false => PhaseAccumulator::Float,
+                },
+                ..patch.oscillator.clone()
+            };
+
+            switch_oscillator(engine, patch, settings)?;
+        }
+        Some(("fm", matches)) => {
+            let fm = match matches.get_one::<String>("ALGORITHM").unwrap().as_str() {
+                "off" => None,
+                algorithm => {
+                    let algorithm = algorithm
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|algorithm| (1..=ALGORITHM_COUNT).contains(algorithm))
+                        .ok_or(format!("Algorithms run from 1 to {ALGORITHM_COUNT}"))?;
+
+                    Some(FmSettings {
+                        algorithm,
+                        feedback: *matches.get_one::<f64>("feedback").unwrap(),
+                        mode: match matches.get_flag("frequency") {
+                            true => FmMode::Frequency,
+                            false => FmMode::Phase,
+                        },
+                        ..patch.oscillator.fm.unwrap_or_default()
+                    })
+                }
+            };
+
+            let settings = OscillatorSettings {
+                fm,
+                ..patch.oscillator.clone()
+            };
+
+            switch_oscillator(engine, patch, settings)?;
+        }
+        Some(("operator", matches)) => {
+            let index = *matches.get_one::<usize>("INDEX").unwrap();
+            let mut fm = patch
+                .oscillator
+                .fm
+                .ok_or("FM is off, turn it on with `fm ALGORITHM` first")?;
+
+            if index >= OPERATOR_COUNT {
+                return Err(format!("No operator {index}, there are {OPERATOR_COUNT}"));
+            }
+
+            fm.operators[index] = OperatorSettings {
+                ratio: *matches.get_one::<f64>("RATIO").unwrap(),
+                fine: *matches.get_one::<f64>("fine").unwrap(),
+                level: *matches.get_one::<f64>("LEVEL").unwrap(),
+            };
+
+            let settings = OscillatorSettings {
+                fm: Some(fm),
+                ..patch.oscillator.clone()
+            };
+
+            switch_oscillator(engine, patch, settings)?;
+        }
+        Some(("unison", matches)) => {
+            let value = |name: &str| *matches.get_one::<f64>(name).unwrap();
+
+            let settings = UnisonSettings {
+                count: *matches.get_one::<usize>("COUNT").unwrap(),
+                detune: value("detune"),
+                curve: value("curve"),
+                spread: value("spread"),
+                random_phase: !matches.get_flag("in-phase"),
+                blend: value("blend"),
+            };
+
+            if !(1..=MAX_UNISON).contains(&settings.count) {
+                return Err(format!("Unison stacks 1 to {MAX_UNISON} copies"));
+            }
+
+            send(engine, patch, engine::Command::SetUnison(settings))?;
+        }
+        Some(("tuning", matches)) => {
+            let mapping = match matches.get_one::<String>("kbm") {
+                Some(path) => KeyboardMapping::read(path)?,
+                None => KeyboardMapping::default(),
+            };
+            let mapping = match matches.get_one::<u8>("root") {
+                Some(root) => KeyboardMapping {
+                    middle_note: *root,
+                    ..mapping
+                },
+                None => mapping,
+            };
+            let scale = match matches.get_one::<String>("SCALE").unwrap().as_str() {
+                "equal" => Scale::equal(*matches.get_one::<usize>("divisions").unwrap()),
+                "just" => Scale::just_intonation(),
+                path => Scale::read(path)?,
+            };
+            let tuning = Tuning::new(scale, mapping)?;
+
+            for (note, frequency) in tuning.frequencies().into_iter().enumerate() {
+                send(
+                    engine,
+                    patch,
+                    engine::Command::SetNoteFrequency {
+                        note: note as u8,
+                        frequency,
+                    },
+                )?;
+            }
+
+            patch.tuning = tuning;
+        }
+        Some(("save", matches)) => {
+            let format = match matches.get_flag("binary") {
+                true => PresetFormat::Binary,
+                false => PresetFormat::Json,
+            };
+            let path = preset::path_for(matches.get_one::<String>("NAME").unwrap(), format);
+
+            std::fs::create_dir_all(preset::PRESET_DIR).map_err(|e| e.to_string())?;
+            patch.write(&path)?;
+
+            writeln!(std::io::stdout(), "Saved {}", path.display()).map_err(|e| e.to_string())?;
+        }
+        Some(("load", matches)) => {
+            let loaded =
+                Preset::read(preset::resolve(matches.get_one::<String>("NAME").unwrap())?)?;
+
+            loaded.send(engine.ok_or("No audio engine running, start the REPL to play live")?)?;
+
+            *patch = loaded;
+        }
+        Some(("presets", _matches)) => {
+            print_lines(preset::list(preset::PRESET_DIR)?)?;
+        }
+        Some(("preset-diff", matches)) => {
+            let mut names = matches.get_many::<String>("NAMES").unwrap();
+            let ours = Preset::read(preset::resolve(names.next().unwrap())?)?;
+            let theirs = match names.next() {
+                Some(name) => Preset::read(preset::resolve(name)?)?,
+                None => patch.clone(),
+            };
+
+            let lines = ours.diff(&theirs)?;
+
+            match lines.is_empty() {
+                true => print_lines(vec!["No differences".to_string()])?,
+                false => print_lines(lines)?,
+            }
+        }
+        Some(("hosts", _matches)) => {
+            print_lines(device::hosts())?;
+        }
+        Some(("devices", matches)) => {
+            let host = matches.get_one::<String>("host").map(String::as_str);
+
+            print_lines(device::devices(host)?)?;
+        }
+        Some(("configs", matches)) => {
+            let host = matches.get_one::<String>("host").map(String::as_str);
+            let device = words(matches, "DEVICE");
+
+            print_lines(device::configs(host, device.as_deref())?)?;
+        }
+        Some(("device", matches)) => {
+            let request = DeviceRequest {
+                host: matches.get_one::<String>("host").cloned(),
+                device: words(matches, "NAME"),
+                sample_rate: matches.get_one::<u32>("sample-rate").copied(),
+                buffer_size: matches.get_one::<u32>("buffer-size").copied(),
+            };
+
+            let opened = output
+                .ok_or("No audio output running, start the REPL to choose a device")?
+                .open(request)?;
+
+            writeln!(std::io::stdout(), "Playing on {opened}").map_err(|e| e.to_string())?;
             std::io::stdout().flush().map_err(|e| e.to_string())?;
         }
         Some(("quit", _matches)) => {
@@ -193,6 +772,81 @@ fn respond(line: &str) -> Result<bool, String> {
     Ok(false)
 }
 
+///
+/// The words given for `name`, joined back together with single spaces, so
+/// that device names with spaces need no quoting.
+///
+fn words(matches: &ArgMatches, name: &str) -> Option<String> {
+    matches
+        .get_many::<String>(name)
+        .map(|words| words.cloned().collect::<Vec<String>>().join(" "))
+}
+
+fn parse_dither(mode: &str) -> Dither {
+    match mode {
+        "none" => Dither::None,
+        "rectangular" => Dither::Rectangular,
+        "triangular" => Dither::Triangular,
+        "shaped" => Dither::NoiseShaped,
+        _ => unreachable!("dither restricted by value parser"),
+    }
+}
+
+fn parse_effect(name: &str) -> EffectKind {
+    match name {
+        "chorus" => EffectKind::Chorus,
+        "delay" => EffectKind::Delay,
+        "reverb" => EffectKind::Reverb,
+        _ => unreachable!("effect restricted by value parser"),
+    }
+}
+
+fn print_lines(lines: Vec<String>) -> Result<(), String> {
+    let mut stdout = std::io::stdout();
+
+    for line in lines {
+        writeln!(stdout, "{line}").map_err(|e| e.to_string())?;
+    }
+
+    stdout.flush().map_err(|e| e.to_string())
+}
+
+///
+/// Queues `command` and keeps `patch` in step with it, so that `save` captures
+/// what is playing.
+///
+fn send(
+    engine: Option<&EngineHandle>,
+    patch: &mut Preset,
+    command: engine::Command,
+) -> Result<(), String> {
+    engine
+        .ok_or("No audio engine running, start the REPL to play live")?
+        .send(command)?;
+
+    patch.record(command);
+
+    Ok(())
+}
+
+///
+/// Builds `settings` and swaps the engine over to it, cutting any notes
+/// playing.
+///
+fn switch_oscillator(
+    engine: Option<&EngineHandle>,
+    patch: &mut Preset,
+    settings: OscillatorSettings,
+) -> Result<(), String> {
+    engine
+        .ok_or("No audio engine running, start the REPL to play live")?
+        .set_oscillator(settings.build(48_000.0)?)?;
+
+    patch.oscillator = settings;
+
+    Ok(())
+}
+
 fn readline() -> Result<String, String> {
     write!(std::io::stdout(), "$ ").map_err(|e| e.to_string())?;
     std::io::stdout().flush().map_err(|e| e.to_string())?;
@@ -228,6 +882,630 @@ fn cli() -> Command {
                 .about("Get a response")
                 .help_template(APPLET_TEMPLATE),
         )
+        .subcommand(
+            Command::new("note-on")
+                .about("Start playing a MIDI note")
+                .arg(
+                    Arg::new("NOTE")
+                        .required(true)
+                        .value_parser(value_parser!(u8).range(0..=127)),
+                )
+                .arg(
+                    Arg::new("VELOCITY")
+                        .value_parser(value_parser!(u8).range(0..=127))
+                        .default_value("100"),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("note-off")
+                .about("Stop playing a MIDI note")
+                .arg(
+                    Arg::new("NOTE")
+                        .required(true)
+                        .value_parser(value_parser!(u8).range(0..=127)),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("midi")
+                .about("Send a raw MIDI message, given as hex bytes")
+                .arg(
+                    Arg::new("BYTES")
+                        .required(true)
+                        .num_args(1..=3)
+                        .value_parser(|byte: &str| u8::from_str_radix(byte, 16)),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("play")
+                .about("Play a standard MIDI file")
+                .arg(Arg::new("FILE").required(true))
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("envelope")
+                .about("Set the amplitude envelope (seconds, and sustain level)")
+                .args(["ATTACK", "DECAY", "SUSTAIN", "RELEASE"].map(|name| {
+                    Arg::new(name)
+                        .required(true)
+                        .value_parser(value_parser!(f64))
+                }))
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("filter")
+                .about("Set the voice filter type, cutoff (Hz) and resonance (0 to 1)")
+                .arg(
+                    Arg::new("TYPE")
+                        .required(true)
+                        .value_parser(["lowpass", "highpass", "bandpass", "notch", "ladder"]),
+                )
+                .arg(
+                    Arg::new("CUTOFF")
+                        .required(true)
+                        .value_parser(value_parser!(f64)),
+                )
+                .arg(
+                    Arg::new("RESONANCE")
+                        .value_parser(value_parser!(f64))
+                        .default_value("0.0"),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("lfo")
+                .about("Set an LFO's shape, rate (Hz, or beats with --sync) and depth")
+                .arg(
+                    Arg::new("INDEX")
+                        .required(true)
+                        .value_parser(value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("SHAPE")
+                        .required(true)
+                        .value_parser(["sine", "triangle", "sawtooth", "square", "random"]),
+                )
+                .arg(
+                    Arg::new("RATE")
+                        .required(true)
+                        .value_parser(value_parser!(f64)),
+                )
+                .arg(
+                    Arg::new("DEPTH")
+                        .value_parser(value_parser!(f64))
+                        .default_value("1.0"),
+                )
+                .arg(Arg::new("sync").long("sync").action(ArgAction::SetTrue))
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("tempo")
+                .about("Set the tempo for synced LFOs and delays, the sequencer and the arpeggiator")
+                .arg(
+                    Arg::new("BPM")
+                        .required(true)
+                        .value_parser(value_parser!(f64)),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("step")
+                .about("Set a sequencer step's note and velocity, or make it a rest")
+                .arg(
+                    Arg::new("INDEX")
+                        .required(true)
+                        .value_parser(value_parser!(usize)),
+                )
+                .arg(Arg::new("NOTE").value_parser(value_parser!(u8).range(0..=127)))
+                .arg(
+                    Arg::new("VELOCITY")
+                        .value_parser(value_parser!(u8).range(1..=127))
+                        .default_value("100"),
+                )
+                .arg(
+                    Arg::new("gate")
+                        .long("gate")
+                        .help("Fraction of the step the note is held for")
+                        .value_parser(value_parser!(f64))
+                        .default_value("0.5"),
+                )
+                .arg(
+                    Arg::new("probability")
+                        .long("probability")
+                        .help("Chance of the step playing each time round, 0 to 1")
+                        .value_parser(value_parser!(f64))
+                        .default_value("1.0"),
+                )
+                .arg(Arg::new("rest").long("rest").action(ArgAction::SetTrue))
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("steps-clear")
+                .about("Make every sequencer step a rest")
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("sequencer")
+                .about("Start the step sequencer, or stop it; settings not given are kept")
+                .arg(
+                    Arg::new("length")
+                        .long("length")
+                        .help("Steps before the pattern repeats")
+                        .value_parser(value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("division")
+                        .long("division")
+                        .help("Steps per beat, so 4 plays sixteenths")
+                        .value_parser(value_parser!(f64)),
+                )
+                .arg(
+                    Arg::new("swing")
+                        .long("swing")
+                        .help("Fraction of a step every second step is delayed by, up to 0.5")
+                        .value_parser(value_parser!(f64)),
+                )
+                .arg(Arg::new("stop").long("stop").action(ArgAction::SetTrue))
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("arp")
+                .about("Arpeggiate the held notes, or turn the arpeggiator off")
+                .arg(
+                    Arg::new("MODE")
+                        .required(true)
+                        .value_parser(["up", "down", "updown", "random", "chord", "off"]),
+                )
+                .arg(
+                    Arg::new("octaves")
+                        .long("octaves")
+                        .value_parser(value_parser!(usize))
+                        .default_value("1"),
+                )
+                .arg(
+                    Arg::new("division")
+                        .long("division")
+                        .help("Steps per beat, so 4 plays sixteenths")
+                        .value_parser(value_parser!(f64))
+                        .default_value("4.0"),
+                )
+                .arg(
+                    Arg::new("gate")
+                        .long("gate")
+                        .help("Fraction of the step each note is held for")
+                        .value_parser(value_parser!(f64))
+                        .default_value("0.5"),
+                )
+                .arg(
+                    Arg::new("swing")
+                        .long("swing")
+                        .value_parser(value_parser!(f64))
+                        .default_value("0.0"),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("mod")
+                .about("Route a modulation source (lfo0, lfo1, envelope, velocity, key, modwheel, bend) to a destination")
+                .arg(Arg::new("SOURCE").required(true))
+                .arg(
+                    Arg::new("DESTINATION")
+                        .required(true)
+                        .value_parser(["pitch", "amplitude", "cutoff", "position", "pan"]),
+                )
+                .arg(
+                    Arg::new("AMOUNT")
+                        .required(true)
+                        .value_parser(value_parser!(f64))
+                        .allow_negative_numbers(true),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("mod-clear")
+                .about("Remove every modulation route")
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("polyphony")
+                .about("Set the number of simultaneous voices")
+                .arg(
+                    Arg::new("VOICES")
+                        .required(true)
+                        .value_parser(value_parser!(usize)),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("gain")
+                .about("Set the output gain")
+                .arg(
+                    Arg::new("AMOUNT")
+                        .required(true)
+                        .value_parser(value_parser!(f64)),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("dither")
+                .about("Set the dither used when the device has fewer than 24 bits")
+                .arg(
+                    Arg::new("MODE")
+                        .required(true)
+                        .value_parser(DITHER_MODES),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("pan")
+                .about("Pan every voice, from -1 (left) to 1 (right)")
+                .arg(
+                    Arg::new("POSITION")
+                        .required(true)
+                        .value_parser(value_parser!(f64))
+                        .allow_negative_numbers(true),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("width")
+                .about("Set the stereo width, from 0 (mono) to 2")
+                .arg(
+                    Arg::new("AMOUNT")
+                        .required(true)
+                        .value_parser(value_parser!(f64)),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("render")
+                .about("Render the synth offline to a WAV file")
+                .arg(Arg::new("PATH").required(true))
+                .arg(
+                    Arg::new("seconds")
+                        .long("seconds")
+                        .help("Defaults to 2s, or the MIDI file plus its release")
+                        .value_parser(value_parser!(f64)),
+                )
+                .arg(Arg::new("midi").long("midi").help("Play a standard MIDI file"))
+                .arg(
+                    Arg::new("preset")
+                        .long("preset")
+                        .help("Play a saved preset instead of the current sound"),
+                )
+                .arg(
+                    Arg::new("sample-rate")
+                        .long("sample-rate")
+                        .value_parser(value_parser!(u32).range(8_000..))
+                        .default_value("48000"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_parser(["pcm16", "pcm24", "float32"])
+                        .default_value("pcm16"),
+                )
+                .arg(
+                    Arg::new("dither")
+                        .long("dither")
+                        .value_parser(DITHER_MODES)
+                        .default_value("triangular"),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("chorus")
+                .about("Set the chorus rate (Hz), depth and delay (ms) and feedback; short delays with feedback flange")
+                .args(["RATE", "DEPTH", "DELAY"].map(|name| {
+                    Arg::new(name)
+                        .required(true)
+                        .value_parser(value_parser!(f64))
+                }))
+                .arg(
+                    Arg::new("FEEDBACK")
+                        .value_parser(value_parser!(f64))
+                        .default_value("0.0"),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("delay")
+                .about("Set the delay time (seconds, or beats with --sync) and feedback")
+                .arg(
+                    Arg::new("TIME")
+                        .required(true)
+                        .value_parser(value_parser!(f64)),
+                )
+                .arg(
+                    Arg::new("FEEDBACK")
+                        .value_parser(value_parser!(f64))
+                        .default_value("0.4"),
+                )
+                .arg(Arg::new("sync").long("sync").action(ArgAction::SetTrue))
+                .arg(
+                    Arg::new("ping-pong")
+                        .long("ping-pong")
+                        .action(ArgAction::SetTrue),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("reverb")
+                .about("Set the reverb room size, damping and width (0 to 1)")
+                .args(["ROOM", "DAMPING"].map(|name| {
+                    Arg::new(name)
+                        .required(true)
+                        .value_parser(value_parser!(f64))
+                }))
+                .arg(
+                    Arg::new("WIDTH")
+                        .value_parser(value_parser!(f64))
+                        .default_value("1.0"),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("effect")
+                .about("Mix an effect in (0 to 1 wet), as an insert or a send, or bypass it")
+                .arg(Arg::new("EFFECT").required(true).value_parser(EFFECTS))
+                .arg(
+                    Arg::new("MIX")
+                        .required(true)
+                        .value_parser(value_parser!(f64)),
+                )
+                .arg(Arg::new("send").long("send").action(ArgAction::SetTrue))
+                .arg(Arg::new("bypass").long("bypass").action(ArgAction::SetTrue))
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("effect-order")
+                .about("Set the order the effects run in")
+                .arg(
+                    Arg::new("EFFECTS")
+                        .required(true)
+                        .num_args(EFFECT_COUNT)
+                        .value_parser(EFFECTS),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("smoothing")
+                .about("Set how long parameter changes take to glide (seconds)")
+                .arg(
+                    Arg::new("SECONDS")
+                        .required(true)
+                        .value_parser(value_parser!(f64)),
+                )
+                .arg(
+                    Arg::new("linear")
+                        .long("linear")
+                        .help("Ramp in a straight line instead of exponentially")
+                        .action(ArgAction::SetTrue),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("limiter")
+                .about("Set the output ceiling (dBFS) and release (seconds), or turn the limiter off")
+                .arg(
+                    Arg::new("CEILING")
+                        .required(true)
+                        .allow_negative_numbers(true)
+                        .value_parser(value_parser!(f64)),
+                )
+                .arg(
+                    Arg::new("RELEASE")
+                        .value_parser(value_parser!(f64))
+                        .default_value("0.1"),
+                )
+                .arg(Arg::new("off").long("off").action(ArgAction::SetTrue))
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("meter")
+                .about("Show the output's peak and RMS levels, limiting and faults")
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("oscillator")
+                .about("Play a waveform (sine, triangle, sawtooth or square) or cycles from a WAV file")
+                .arg(Arg::new("SOURCE").required(true))
+                .arg(
+                    Arg::new("cycle-length")
+                        .long("cycle-length")
+                        .help("Samples per cycle in the WAV file, detected if not given")
+                        .value_parser(value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("fixed-phase")
+                        .long("fixed-phase")
+                        .help("Keep phase in fixed point, so tuning never drifts")
+                        .action(ArgAction::SetTrue),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("fm")
+                .about("Run the oscillator through four FM operators wired by an algorithm (1 to 8), or turn FM off")
+                .arg(Arg::new("ALGORITHM").required(true))
+                .arg(
+                    Arg::new("feedback")
+                        .long("feedback")
+                        .help("How much the top operator modulates itself")
+                        .value_parser(value_parser!(f64))
+                        .default_value("0.0"),
+                )
+                .arg(
+                    Arg::new("frequency")
+                        .long("frequency")
+                        .help("Modulate frequency instead of phase")
+                        .action(ArgAction::SetTrue),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("operator")
+                .about("Set an FM operator's frequency ratio and level (output for carriers, depth for modulators)")
+                .arg(
+                    Arg::new("INDEX")
+                        .required(true)
+                        .value_parser(value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("RATIO")
+                        .required(true)
+                        .value_parser(value_parser!(f64)),
+                )
+                .arg(
+                    Arg::new("LEVEL")
+                        .value_parser(value_parser!(f64))
+                        .default_value("1.0"),
+                )
+                .arg(
+                    Arg::new("fine")
+                        .long("fine")
+                        .help("Detune in cents")
+                        .allow_negative_numbers(true)
+                        .value_parser(value_parser!(f64))
+                        .default_value("0.0"),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("unison")
+                .about("Stack detuned copies of the oscillator on every note, spread across the stereo field")
+                .arg(
+                    Arg::new("COUNT")
+                        .required(true)
+                        .value_parser(value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("detune")
+                        .long("detune")
+                        .help("Cents between the note and the outermost copies")
+                        .value_parser(value_parser!(f64))
+                        .default_value("20.0"),
+                )
+                .arg(
+                    Arg::new("curve")
+                        .long("curve")
+                        .help("1 spaces the copies evenly, higher bunches them towards the note")
+                        .value_parser(value_parser!(f64))
+                        .default_value("1.0"),
+                )
+                .arg(
+                    Arg::new("spread")
+                        .long("spread")
+                        .help("Stereo spread, from 0 (mono) to 1")
+                        .value_parser(value_parser!(f64))
+                        .default_value("1.0"),
+                )
+                .arg(
+                    Arg::new("blend")
+                        .long("blend")
+                        .help("From 0 (centre copies only) to 1 (side copies only)")
+                        .value_parser(value_parser!(f64))
+                        .default_value("0.5"),
+                )
+                .arg(
+                    Arg::new("in-phase")
+                        .long("in-phase")
+                        .help("Start every copy at the same phase")
+                        .action(ArgAction::SetTrue),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("tuning")
+                .about("Retune the keyboard to equal temperament, just intonation or a Scala .scl file")
+                .arg(Arg::new("SCALE").required(true))
+                .arg(
+                    Arg::new("divisions")
+                        .long("divisions")
+                        .help("Steps to the octave in equal temperament")
+                        .value_parser(value_parser!(usize))
+                        .default_value("12"),
+                )
+                .arg(
+                    Arg::new("root")
+                        .long("root")
+                        .help("The MIDI note playing the scale's first degree")
+                        .value_parser(value_parser!(u8).range(0..=127)),
+                )
+                .arg(
+                    Arg::new("kbm")
+                        .long("kbm")
+                        .help("A Scala .kbm file mapping the scale onto the keys"),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("save")
+                .about("Save the current sound as a preset")
+                .arg(Arg::new("NAME").required(true))
+                .arg(
+                    Arg::new("binary")
+                        .long("binary")
+                        .help("Write the compact binary form instead of JSON")
+                        .action(ArgAction::SetTrue),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("load")
+                .about("Load a preset by name or path")
+                .arg(Arg::new("NAME").required(true))
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("presets")
+                .about("List the saved presets")
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("preset-diff")
+                .about("Show how one preset differs from another, or from the current sound")
+                .arg(Arg::new("NAMES").required(true).num_args(1..=2))
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("hosts")
+                .about("List the audio hosts")
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("devices")
+                .about("List a host's output devices")
+                .arg(Arg::new("host").long("host"))
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("configs")
+                .about("List an output device's supported configs (default device if omitted)")
+                .arg(Arg::new("DEVICE").num_args(1..))
+                .arg(Arg::new("host").long("host"))
+                .help_template(APPLET_TEMPLATE),
+        )
+        .subcommand(
+            Command::new("device")
+                .about("Play on another output device (default device if omitted)")
+                .arg(Arg::new("NAME").num_args(1..))
+                .arg(Arg::new("host").long("host"))
+                .arg(
+                    Arg::new("sample-rate")
+                        .long("sample-rate")
+                        .value_parser(value_parser!(u32).range(1..)),
+                )
+                .arg(
+                    Arg::new("buffer-size")
+                        .long("buffer-size")
+                        .help("Frames per callback")
+                        .value_parser(value_parser!(u32).range(1..)),
+                )
+                .help_template(APPLET_TEMPLATE),
+        )
         .subcommand(
             Command::new("quit")
                 .alias("exit")
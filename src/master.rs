@@ -0,0 +1,499 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+///
+/// How far ahead the limiter looks, in seconds, which is also the latency
+/// it adds.
+///
+pub const LOOKAHEAD: f64 = 0.005;
+
+///
+/// Corner of the DC blocker, in Hz.
+///
+const DC_CUTOFF: f64 = 5.0;
+
+///
+/// Level below which the DC blocker's output counts as silence.
+///
+const SILENCE: f64 = 1e-12;
+
+///
+/// Seconds the bus stays silent after a non-finite sample.
+///
+const MUTE_TIME: f64 = 0.1;
+
+///
+/// Time constant of the RMS average and the peak fall, in seconds.
+///
+const METER_TIME: f64 = 0.3;
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LimiterSettings {
+    pub enabled: bool,
+    /// Highest output level, in dBFS.
+    pub ceiling: f64,
+    /// Seconds for the gain to recover once a peak has passed.
+    pub release: f64,
+}
+
+impl Default for LimiterSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ceiling: -0.3,
+            release: 0.1,
+        }
+    }
+}
+
+///
+/// A one-pole high-pass that keeps an offset from reaching the device.
+///
+#[derive(Clone, Debug)]
+pub struct DcBlocker {
+    coefficient: f64,
+    input: [f64; 2],
+    output: [f64; 2],
+}
+
+///
+/// A stereo-linked brickwall limiter. The signal is delayed by the
+/// lookahead while the gain ramps down ahead of each peak, so nothing gets
+/// past the ceiling and nothing is clipped hard.
+///
+#[derive(Clone, Debug)]
+pub struct Limiter {
+    pub settings: LimiterSettings,
+    sample_rate: f64,
+    delay: Vec<[f64; 2]>,
+    position: usize,
+    /// Gain each recent frame needs, kept increasing from front to back so
+    /// that the front is the lowest gain in the window.
+    minimum: VecDeque<(u64, f64)>,
+    /// The last lookahead's worth of window minimums, averaged into a ramp.
+    held: Vec<f64>,
+    sum: f64,
+    gain: f64,
+    clock: u64,
+}
+
+///
+/// Levels from the master bus, in linear amplitude.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MeterReading {
+    pub peak: [f64; 2],
+    pub rms: [f64; 2],
+    /// Gain the limiter is applying, `1.0` when it is idle.
+    pub limiter_gain: f64,
+    /// Non-finite samples the bus has had to mute.
+    pub faults: u64,
+    pub muted: bool,
+}
+
+#[derive(Debug, Default)]
+struct Readings {
+    /// Each an `f64` stored by its bits, as peak, RMS and limiter gain.
+    levels: [AtomicU64; 5],
+    faults: AtomicU64,
+    muted: AtomicBool,
+}
+
+///
+/// The control side's view of the meter, cheap to clone and to read from
+/// any thread.
+///
+#[derive(Clone, Debug)]
+pub struct MeterHandle {
+    readings: Arc<Readings>,
+}
+
+#[derive(Debug)]
+struct Meter {
+    readings: Arc<Readings>,
+    coefficient: f64,
+    peak: [f64; 2],
+    mean_square: [f64; 2],
+}
+
+///
+/// The last stage before the device: a NaN/Inf guard, DC blocker, limiter
+/// and meter, in that order.
+///
+#[derive(Debug)]
+pub struct MasterBus {
+    pub limiter: Limiter,
+    dc_blocker: DcBlocker,
+    meter: Meter,
+    sample_rate: f64,
+    /// Samples left to stay muted.
+    muted: usize,
+    faults: u64,
+}
+
+pub fn to_decibels(level: f64) -> f64 {
+    20.0 * level.log10()
+}
+
+pub fn from_decibels(decibels: f64) -> f64 {
+    10.0_f64.powf(decibels / 20.0)
+}
+
+impl DcBlocker {
+    pub fn new(sample_rate: f64) -> Self {
+        Self {
+            coefficient: 1.0 - std::f64::consts::TAU * DC_CUTOFF / sample_rate,
+            input: [0.0; 2],
+            output: [0.0; 2],
+        }
+    }
+
+    pub fn process(&mut self, frame: [f64; 2]) -> [f64; 2] {
+        for ((input, output), sample) in self.input.iter_mut().zip(&mut self.output).zip(frame) {
+            *output = sample - *input + self.coefficient * *output;
+            *input = sample;
+
+            // Lets the tail settle on exact silence rather than decaying
+            // through denormals forever.
+            if output.abs() < SILENCE {
+                *output = 0.0;
+            }
+        }
+
+        self.output
+    }
+
+    pub fn reset(&mut self) {
+        self.input = [0.0; 2];
+        self.output = [0.0; 2];
+    }
+}
+
+impl Limiter {
+    pub fn new(settings: LimiterSettings, sample_rate: f64) -> Self {
+        let length = ((LOOKAHEAD * sample_rate).round() as usize).max(1);
+
+        Self {
+            settings,
+            sample_rate,
+            delay: vec![[0.0; 2]; length],
+            position: 0,
+            minimum: VecDeque::with_capacity(length + 1),
+            held: vec![1.0; length],
+            sum: length as f64,
+            gain: 1.0,
+            clock: 0,
+        }
+    }
+
+    ///
+    /// Gain applied to the frame last returned.
+    ///
+    pub fn gain(&self) -> f64 {
+        self.gain
+    }
+
+    pub fn reset(&mut self) {
+        self.delay.fill([0.0; 2]);
+        self.minimum.clear();
+        self.held.fill(1.0);
+        self.sum = self.held.len() as f64;
+        self.gain = 1.0;
+    }
+
+    pub fn process(&mut self, frame: [f64; 2]) -> [f64; 2] {
+        let length = self.delay.len();
+        let ceiling = from_decibels(self.settings.ceiling);
+        let peak = frame[0].abs().max(frame[1].abs());
+
+        let required = match self.settings.enabled && peak > ceiling {
+            true => ceiling / peak,
+            false => 1.0,
+        };
+
+        // The lowest gain needed by any frame from here back to the one
+        // leaving the delay, which covers every frame the ramp below spans.
+        // @note: Stale frames go first, so the window never outgrows its capacity
+        while self
+            .minimum
+            .front()
+            .is_some_and(|(clock, _)| clock + (length as u64) < self.clock)
+        {
+            self.minimum.pop_front();
+        }
+
+        while self
+            .minimum
+            .back()
+            .is_some_and(|(_, gain)| *gain >= required)
+        {
+            self.minimum.pop_back();
+        }
+
+        self.minimum.push_back((self.clock, required));
+
+        let held = self.minimum.front().map_or(1.0, |(_, gain)| *gain);
+
+        self.sum += held - self.held[self.position];
+        self.held[self.position] = held;
+
+        let target = (self.sum / length as f64).min(1.0);
+        let release = (-1.0 / (self.settings.release.max(1e-3) * self.sample_rate)).exp();
+
+        self.gain = match target < self.gain {
+            true => target,
+            false => target - (target - self.gain) * release,
+        };
+
+        let delayed = std::mem::replace(&mut self.delay[self.position], frame);
+
+        self.position = (self.position + 1) % length;
+        self.clock += 1;
+
+        // @note: Only rounding in the running sum can leave anything to clamp
+        delayed.map(|sample| match self.settings.enabled {
+            true => (sample * self.gain).clamp(-ceiling, ceiling),
+            false => sample * self.gain,
+        })
+    }
+}
+
+impl MeterHandle {
+    pub fn read(&self) -> MeterReading {
+        let level =
+            |index: usize| f64::from_bits(self.readings.levels[index].load(Ordering::Relaxed));
+
+        MeterReading {
+            peak: [level(0), level(1)],
+            rms: [level(2), level(3)],
+            limiter_gain: level(4),
+            faults: self.readings.faults.load(Ordering::Relaxed),
+            muted: self.readings.muted.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Meter {
+    fn new(readings: Arc<Readings>, sample_rate: f64) -> Self {
+        Self {
+            readings,
+            coefficient: (-1.0 / (METER_TIME * sample_rate)).exp(),
+            peak: [0.0; 2],
+            mean_square: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, frame: [f64; 2]) {
+        let sides = self.peak.iter_mut().zip(&mut self.mean_square).zip(frame);
+
+        for ((peak, mean_square), sample) in sides {
+            *peak = sample.abs().max(*peak * self.coefficient);
+            *mean_square = sample * sample + (*mean_square - sample * sample) * self.coefficient;
+        }
+    }
+
+    fn publish(&self, limiter_gain: f64, faults: u64, muted: bool) {
+        let levels = [
+            self.peak[0],
+            self.peak[1],
+            self.mean_square[0].sqrt(),
+            self.mean_square[1].sqrt(),
+            limiter_gain,
+        ];
+
+        for (reading, level) in self.readings.levels.iter().zip(levels) {
+            reading.store(level.to_bits(), Ordering::Relaxed);
+        }
+
+        self.readings.faults.store(faults, Ordering::Relaxed);
+        self.readings.muted.store(muted, Ordering::Relaxed);
+    }
+}
+
+impl MasterBus {
+    pub fn new(sample_rate: f64) -> Self {
+        let meter = Meter::new(Arc::new(Readings::default()), sample_rate);
+
+        // Reads as an idle limiter until the first block comes through.
+        meter.publish(1.0, 0, false);
+
+        Self {
+            limiter: Limiter::new(LimiterSettings::default(), sample_rate),
+            dc_blocker: DcBlocker::new(sample_rate),
+            meter,
+            sample_rate,
+            muted: 0,
+            faults: 0,
+        }
+    }
+
+    pub fn meter(&self) -> MeterHandle {
+        MeterHandle {
+            readings: Arc::clone(&self.meter.readings),
+        }
+    }
+
+    ///
+    /// Reallocates the limiter's lookahead, so it belongs on the control side.
+    ///
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.limiter = Limiter::new(self.limiter.settings, sample_rate);
+        self.dc_blocker = DcBlocker::new(sample_rate);
+        self.meter = Meter::new(Arc::clone(&self.meter.readings), sample_rate);
+    }
+
+    ///
+    /// Returns whether a non-finite sample turned up, in which case the bus
+    /// mutes for a moment and whatever produced it should be reset.
+    ///
+    pub fn process(&mut self, block: &mut [[f64; 2]]) -> bool {
+        let mut faulted = false;
+
+        for frame in block.iter_mut() {
+            if !frame.iter().all(|sample| sample.is_finite()) {
+                faulted = true;
+                self.faults += 1;
+                self.muted = (MUTE_TIME * self.sample_rate) as usize;
+
+                // @note: Both keep state that one bad sample would poison for good
+                self.dc_blocker.reset();
+                self.limiter.reset();
+            }
+
+            if self.muted > 0 {
+                self.muted -= 1;
+                *frame = [0.0; 2];
+            } else {
+                *frame = self.limiter.process(self.dc_blocker.process(*frame));
+            }
+
+            self.meter.process(*frame);
+        }
+
+        self.meter
+            .publish(self.limiter.gain(), self.faults, self.muted > 0);
+
+        faulted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 48_000.0;
+
+    fn sine(amplitude: f64, length: usize) -> Vec<[f64; 2]> {
+        (0..length)
+            .map(|n| [(n as f64 * 0.05).sin() * amplitude; 2])
+            .collect()
+    }
+
+    #[test]
+    fn blocks_dc() {
+        let mut blocker = DcBlocker::new(SAMPLE_RATE);
+        let output: Vec<[f64; 2]> = (0..48_000).map(|_| blocker.process([0.5, -0.5])).collect();
+
+        assert!(output[0] == [0.5, -0.5]);
+        assert!(output[47_999].iter().all(|sample| sample.abs() < 1e-6));
+    }
+
+    #[test]
+    fn limits_to_ceiling_without_touching_quiet_signals() {
+        let settings = LimiterSettings::default();
+        let ceiling = from_decibels(settings.ceiling);
+        let delay = (LOOKAHEAD * SAMPLE_RATE) as usize;
+
+        let mut limiter = Limiter::new(settings, SAMPLE_RATE);
+        let input = sine(0.5, 4_800);
+        let output: Vec<[f64; 2]> = input.iter().map(|frame| limiter.process(*frame)).collect();
+
+        assert!(output[delay..] == input[..4_800 - delay]);
+
+        // A burst ten times too loud, in the middle of a quiet passage.
+        let mut limiter = Limiter::new(settings, SAMPLE_RATE);
+        let mut input = sine(0.5, 48_000);
+
+        input[4_800..4_900]
+            .iter_mut()
+            .for_each(|frame| *frame = frame.map(|sample| sample * 20.0));
+
+        let (output, gains): (Vec<[f64; 2]>, Vec<f64>) = input
+            .iter()
+            .map(|frame| (limiter.process(*frame), limiter.gain()))
+            .unzip();
+
+        assert!(output
+            .iter()
+            .flatten()
+            .all(|sample| sample.abs() <= ceiling));
+
+        // The gain ramps down evenly while the burst is still in the delay,
+        // rather than clamping it when it leaves.
+        assert!(gains[4_799] == 1.0 && gains[4_800 + delay - 1] < 0.11);
+        assert!(gains
+            .windows(2)
+            .all(|w| w[0] - w[1] <= 1.0 / delay as f64 + 1e-9));
+
+        // And recovers once the release has passed.
+        assert!(limiter.gain() > 0.999);
+    }
+
+    #[test]
+    fn limiter_window_keeps_its_capacity() {
+        let mut limiter = Limiter::new(LimiterSettings::default(), SAMPLE_RATE);
+        let capacity = limiter.minimum.capacity();
+
+        // A loud note decaying above the ceiling needs more gain every frame.
+        for n in 0..48_000 {
+            limiter.process([10.0 * (-(n as f64) / 48_000.0).exp(); 2]);
+            assert!(limiter.minimum.capacity() == capacity);
+        }
+    }
+
+    #[test]
+    fn mutes_and_reports_non_finite_samples() {
+        let mut bus = MasterBus::new(SAMPLE_RATE);
+        let meter = bus.meter();
+        let mut block = sine(0.5, 4_800);
+
+        block[100][1] = f64::NAN;
+
+        assert!(bus.process(&mut block));
+        assert!(block[100..].iter().all(|frame| *frame == [0.0; 2]));
+
+        let reading = meter.read();
+
+        assert!(reading.faults == 1 && reading.muted);
+
+        let mut block = sine(0.5, 4_800);
+
+        assert!(!bus.process(&mut block));
+        assert!(block.iter().flatten().all(|sample| sample.is_finite()));
+        assert!(!meter.read().muted);
+    }
+
+    #[test]
+    fn meters_peak_and_rms() {
+        let mut bus = MasterBus::new(SAMPLE_RATE);
+        let mut block = sine(0.5, 96_000);
+
+        bus.process(&mut block);
+
+        let reading = bus.meter().read();
+
+        for side in 0..2 {
+            assert!((reading.peak[side] - 0.5).abs() < 1e-2);
+            assert!((reading.rms[side] - 0.5 / 2.0_f64.sqrt()).abs() < 1e-2);
+        }
+
+        assert!(reading.limiter_gain == 1.0);
+        assert!((to_decibels(from_decibels(-6.0)) + 6.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,497 @@
+use std::path::Path;
+
+pub const MOD_WHEEL: u8 = 1;
+pub const PAN: u8 = 10;
+pub const SUSTAIN_PEDAL: u8 = 64;
+pub const ALL_SOUND_OFF: u8 = 120;
+pub const ALL_NOTES_OFF: u8 = 123;
+
+///
+/// Tempo of a standard MIDI file until its first tempo event, 120 BPM.
+///
+const DEFAULT_MICROSECONDS_PER_BEAT: u32 = 500_000;
+
+///
+/// The channel voice messages the synth responds to. Everything else, such as
+/// aftertouch, program changes and system messages, is dropped by `parse`.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MidiMessage {
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        channel: u8,
+        note: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    /// 14-bit bend, from `0` to `16383` with `8192` at rest.
+    PitchBend {
+        channel: u8,
+        value: u16,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MidiEvent {
+    /// Seconds from the start of the file.
+    pub time: f64,
+    pub message: MidiMessage,
+}
+
+///
+/// Where the synth reads MIDI from. A live device backend returns whatever
+/// has arrived since the last poll, while a file replays its events once
+/// their time has come.
+///
+pub trait MidiSource {
+    ///
+    /// The next message due at or before `time` seconds, if any.
+    ///
+    fn poll(&mut self, time: f64) -> Option<MidiMessage>;
+}
+
+///
+/// A standard MIDI file (format 0 or 1) flattened into one list of events in
+/// time order, with the tempo map already applied.
+///
+#[derive(Clone, Debug)]
+pub struct MidiFile {
+    events: Vec<MidiEvent>,
+    cursor: usize,
+}
+
+///
+/// Bounds-checked cursor over the bytes of a track chunk.
+///
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl MidiMessage {
+    ///
+    /// Decodes one complete message, without running status.
+    ///
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let (&status, data) = bytes.split_first()?;
+        let channel = status & 0x0F;
+
+        if data.len() < data_length(status)? || data.iter().any(|byte| byte & 0x80 != 0) {
+            return None;
+        }
+
+        match status & 0xF0 {
+            0x80 => Some(MidiMessage::NoteOff {
+                channel,
+                note: data[0],
+            }),
+            // @note: A note-on with velocity zero is a note-off
+            0x90 if data[1] == 0 => Some(MidiMessage::NoteOff {
+                channel,
+                note: data[0],
+            }),
+            0x90 => Some(MidiMessage::NoteOn {
+                channel,
+                note: data[0],
+                velocity: data[1],
+            }),
+            0xB0 => Some(MidiMessage::ControlChange {
+                channel,
+                controller: data[0],
+                value: data[1],
+            }),
+            0xE0 => Some(MidiMessage::PitchBend {
+                channel,
+                value: data[0] as u16 | (data[1] as u16) << 7,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match *self {
+            MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => vec![0x90 | channel, note, velocity],
+            MidiMessage::NoteOff { channel, note } => vec![0x80 | channel, note, 0],
+            MidiMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            } => vec![0xB0 | channel, controller, value],
+            MidiMessage::PitchBend { channel, value } => {
+                vec![0xE0 | channel, (value & 0x7F) as u8, (value >> 7) as u8]
+            }
+        }
+    }
+}
+
+///
+/// Data bytes following a channel status byte, or `None` for system messages.
+///
+fn data_length(status: u8) -> Option<usize> {
+    match status & 0xF0 {
+        0x80 | 0x90 | 0xA0 | 0xB0 | 0xE0 => Some(2),
+        0xC0 | 0xD0 => Some(1),
+        _ => None,
+    }
+}
+
+impl MidiFile {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let bytes = std::fs::read(path.as_ref())
+            .map_err(|e| format!("{}: {e}", path.as_ref().display()))?;
+
+        Self::parse(&bytes)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 14 || &bytes[0..4] != b"MThd" {
+            return Err("Not a standard MIDI file".to_string());
+        }
+
+        let format = u16::from_be_bytes([bytes[8], bytes[9]]);
+        let division = u16::from_be_bytes([bytes[12], bytes[13]]);
+
+        if format > 1 {
+            return Err(format!("Unsupported MIDI file format {format}"));
+        }
+
+        if division & 0x8000 != 0 || division == 0 {
+            return Err("SMPTE time division is not supported".to_string());
+        }
+
+        // Tick and message pairs, which a stable sort by tick keeps in file
+        // order when simultaneous.
+        let mut messages = Vec::new();
+        let mut tempos = Vec::new();
+        let header_size = u32::from_be_bytes(bytes[4..8].try_into().unwrap()) as usize;
+        let mut offset = 8 + header_size;
+
+        while offset + 8 <= bytes.len() {
+            let id = &bytes[offset..offset + 4];
+            let size =
+                u32::from_be_bytes(bytes[offset + 4..offset + 8].try_into().unwrap()) as usize;
+            let body = &bytes[offset + 8..(offset + 8 + size).min(bytes.len())];
+
+            if id == b"MTrk" {
+                parse_track(body, &mut messages, &mut tempos)?;
+            }
+
+            offset += 8 + size;
+        }
+
+        messages.sort_by_key(|(tick, _)| *tick);
+        tempos.sort_by_key(|(tick, _)| *tick);
+
+        // Walks the tempo map alongside the events, accumulating seconds.
+        let ticks_per_beat = division as f64;
+        let mut tempos = tempos.into_iter().peekable();
+        let mut microseconds_per_beat = DEFAULT_MICROSECONDS_PER_BEAT;
+        let (mut last_tick, mut time) = (0, 0.0);
+
+        let events = messages
+            .into_iter()
+            .map(|(tick, message)| {
+                while let Some((change, tempo)) = tempos.next_if(|(change, _)| *change <= tick) {
+                    time += seconds(change - last_tick, microseconds_per_beat, ticks_per_beat);
+                    last_tick = change;
+                    microseconds_per_beat = tempo;
+                }
+
+                time += seconds(tick - last_tick, microseconds_per_beat, ticks_per_beat);
+                last_tick = tick;
+
+                MidiEvent { time, message }
+            })
+            .collect();
+
+        Ok(Self { events, cursor: 0 })
+    }
+
+    pub fn events(&self) -> &[MidiEvent] {
+        &self.events
+    }
+
+    ///
+    /// Time of the last event, in seconds.
+    ///
+    pub fn duration(&self) -> f64 {
+        self.events.last().map_or(0.0, |event| event.time)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.cursor == self.events.len()
+    }
+
+    ///
+    /// Starts playback over from the first event.
+    ///
+    pub fn rewind(&mut self) {
+        self.cursor = 0;
+    }
+}
+
+impl MidiSource for MidiFile {
+    fn poll(&mut self, time: f64) -> Option<MidiMessage> {
+        let event = self.events.get(self.cursor)?;
+
+        if event.time > time {
+            return None;
+        }
+
+        self.cursor += 1;
+
+        Some(event.message)
+    }
+}
+
+fn seconds(ticks: u64, microseconds_per_beat: u32, ticks_per_beat: f64) -> f64 {
+    ticks as f64 / ticks_per_beat * microseconds_per_beat as f64 / 1_000_000.0
+}
+
+fn parse_track(
+    body: &[u8],
+    messages: &mut Vec<(u64, MidiMessage)>,
+    tempos: &mut Vec<(u64, u32)>,
+) -> Result<(), String> {
+    let mut reader = Reader {
+        bytes: body,
+        offset: 0,
+    };
+    let mut tick = 0;
+    let mut running_status = None;
+
+    while reader.offset < body.len() {
+        tick += reader.variable_length()? as u64;
+
+        let status = match reader.peek()? {
+            byte if byte & 0x80 != 0 => {
+                reader.offset += 1;
+                byte
+            }
+            _ => running_status.ok_or("MIDI data byte without a status")?,
+        };
+
+        match status {
+            0xFF => {
+                let kind = reader.byte()?;
+                let length = reader.variable_length()?;
+                let data = reader.take(length)?;
+
+                running_status = None;
+
+                match kind {
+                    0x2F => break,
+                    0x51 if length == 3 => {
+                        tempos.push((tick, u32::from_be_bytes([0, data[0], data[1], data[2]])))
+                    }
+                    _ => {}
+                }
+            }
+            0xF0 | 0xF7 => {
+                let length = reader.variable_length()?;
+
+                reader.take(length)?;
+                running_status = None;
+            }
+            0x80..=0xEF => {
+                let length = data_length(status).unwrap();
+                let data = reader.take(length)?;
+
+                running_status = Some(status);
+
+                let mut bytes = [status, 0, 0];
+                bytes[1..=length].copy_from_slice(data);
+
+                if let Some(message) = MidiMessage::parse(&bytes[..=length]) {
+                    messages.push((tick, message));
+                }
+            }
+            _ => return Err(format!("Unexpected MIDI status {status:#04x} in track")),
+        }
+    }
+
+    Ok(())
+}
+
+impl Reader<'_> {
+    fn peek(&self) -> Result<u8, String> {
+        self.bytes
+            .get(self.offset)
+            .copied()
+            .ok_or_else(|| "Truncated MIDI track".to_string())
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = self.peek()?;
+
+        self.offset += 1;
+
+        Ok(byte)
+    }
+
+    fn take(&mut self, length: usize) -> Result<&[u8], String> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset + length)
+            .ok_or("Truncated MIDI track")?;
+
+        self.offset += length;
+
+        Ok(bytes)
+    }
+
+    ///
+    /// Big-endian base-128 quantity of at most four bytes, as used for delta
+    /// times and event lengths.
+    ///
+    fn variable_length(&mut self) -> Result<usize, String> {
+        let mut value = 0;
+
+        for _ in 0..4 {
+            let byte = self.byte()?;
+
+            value = value << 7 | (byte & 0x7F) as usize;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err("MIDI variable-length quantity longer than four bytes".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///
+    /// Builds a standard MIDI file from raw track bodies.
+    ///
+    fn smf(format: u16, division: u16, tracks: &[&[u8]]) -> Vec<u8> {
+        let mut bytes = b"MThd".to_vec();
+
+        bytes.extend_from_slice(&6u32.to_be_bytes());
+        bytes.extend_from_slice(&format.to_be_bytes());
+        bytes.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&division.to_be_bytes());
+
+        for track in tracks {
+            bytes.extend_from_slice(b"MTrk");
+            bytes.extend_from_slice(&(track.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(track);
+        }
+
+        bytes
+    }
+
+    #[test]
+    fn parses_channel_messages() {
+        assert!(
+            MidiMessage::parse(&[0x93, 60, 100])
+                == Some(MidiMessage::NoteOn {
+                    channel: 3,
+                    note: 60,
+                    velocity: 100
+                })
+        );
+        assert!(
+            MidiMessage::parse(&[0x90, 60, 0])
+                == Some(MidiMessage::NoteOff {
+                    channel: 0,
+                    note: 60
+                })
+        );
+        assert!(
+            MidiMessage::parse(&[0xE0, 0x00, 0x40])
+                == Some(MidiMessage::PitchBend {
+                    channel: 0,
+                    value: 8192
+                })
+        );
+        assert!(MidiMessage::parse(&[0xC0, 5]).is_none());
+        assert!(MidiMessage::parse(&[0x90, 60]).is_none());
+        assert!(MidiMessage::parse(&[0x90, 0x80, 1]).is_none());
+
+        for message in [
+            MidiMessage::ControlChange {
+                channel: 15,
+                controller: SUSTAIN_PEDAL,
+                value: 127,
+            },
+            MidiMessage::PitchBend {
+                channel: 1,
+                value: 16_383,
+            },
+        ] {
+            assert!(MidiMessage::parse(&message.to_bytes()) == Some(message));
+        }
+    }
+
+    #[test]
+    fn applies_tempo_map_across_tracks() {
+        // 96 ticks per beat, switching from 120 to 60 BPM after one beat.
+        let conductor: &[u8] = &[
+            0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, // 120 BPM
+            0x60, 0xFF, 0x51, 0x03, 0x0F, 0x42, 0x40, // 60 BPM at tick 96
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        // Running status carries the note-on into the second note.
+        let notes: &[u8] = &[
+            0x00, 0x90, 60, 100, // beat 0
+            0x81, 0x40, 64, 100, // beat 2, 192 ticks later
+            0x60, 0x80, 60, 0, // beat 3
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+
+        let file = MidiFile::parse(&smf(1, 96, &[conductor, notes])).unwrap();
+        let times: Vec<f64> = file.events().iter().map(|event| event.time).collect();
+
+        assert!(times == [0.0, 1.5, 2.5]);
+        assert!(
+            file.events()[1].message
+                == MidiMessage::NoteOn {
+                    channel: 0,
+                    note: 64,
+                    velocity: 100
+                }
+        );
+        assert!(file.duration() == 2.5);
+    }
+
+    #[test]
+    fn polls_events_when_due() {
+        let track: &[u8] = &[
+            0x00, 0x90, 60, 100, 0x60, 0x80, 60, 0, 0x00, 0xFF, 0x2F, 0x00,
+        ];
+        let mut file = MidiFile::parse(&smf(0, 96, &[track])).unwrap();
+
+        assert!(file.poll(0.0).is_some());
+        assert!(file.poll(0.49).is_none());
+        assert!(file.poll(0.5).is_some());
+        assert!(file.poll(10.0).is_none());
+
+        file.rewind();
+        assert!(file.poll(0.0).is_some());
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        assert!(MidiFile::parse(b"RIFF").is_err());
+        assert!(MidiFile::parse(&smf(2, 96, &[])).is_err());
+        assert!(MidiFile::parse(&smf(0, 0xE728, &[])).is_err());
+        assert!(MidiFile::parse(&smf(0, 96, &[&[0x00, 60, 100]])).is_err());
+        assert!(MidiFile::parse(&smf(0, 96, &[&[0x00, 0x90, 60]])).is_err());
+    }
+}
@@ -0,0 +1,222 @@
+pub const LFO_COUNT: usize = 2;
+
+///
+/// Routes live in a preallocated list so that adding one on the audio thread
+/// never allocates.
+///
+pub const MAX_ROUTES: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ModSource {
+    Lfo(usize),
+    /// The voice's amplitude envelope, `0.0..=1.0`.
+    Envelope,
+    /// Note-on velocity, `0.0..=1.0`.
+    Velocity,
+    /// Octaves from middle C (note 60).
+    KeyTrack,
+    /// MIDI controller 1, `0.0..=1.0`.
+    ModWheel,
+    /// MIDI pitch bend, `-1.0..=1.0`.
+    PitchBend,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ModDestination {
+    /// Semitones per unit.
+    Pitch,
+    /// Fraction of full gain per unit.
+    Amplitude,
+    /// Octaves per unit.
+    FilterCutoff,
+    /// Fraction of the bank per unit.
+    WavetablePosition,
+    /// Full left to centre per unit.
+    Pan,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ModRoute {
+    pub source: ModSource,
+    pub destination: ModDestination,
+    pub amount: f64,
+}
+
+///
+/// Current value of every source, for one voice.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ModSources {
+    pub lfos: [f64; LFO_COUNT],
+    pub envelope: f64,
+    pub velocity: f64,
+    pub key_track: f64,
+    pub mod_wheel: f64,
+    pub pitch_bend: f64,
+}
+
+///
+/// Summed modulation for every destination, in that destination's units.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ModOffsets {
+    pub pitch: f64,
+    pub amplitude: f64,
+    pub filter_cutoff: f64,
+    pub wavetable_position: f64,
+    pub pan: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct ModMatrix {
+    routes: Vec<ModRoute>,
+}
+
+impl ModSources {
+    fn get(&self, source: ModSource) -> f64 {
+        match source {
+            ModSource::Lfo(index) => self.lfos[index],
+            ModSource::Envelope => self.envelope,
+            ModSource::Velocity => self.velocity,
+            ModSource::KeyTrack => self.key_track,
+            ModSource::ModWheel => self.mod_wheel,
+            ModSource::PitchBend => self.pitch_bend,
+        }
+    }
+}
+
+impl Default for ModMatrix {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ModMatrix {
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::with_capacity(MAX_ROUTES),
+        }
+    }
+
+    pub fn add(&mut self, route: ModRoute) -> Result<(), String> {
+        if let ModSource::Lfo(index) = route.source {
+            if index >= LFO_COUNT {
+                return Err(format!("No LFO {index}, there are {LFO_COUNT}"));
+            }
+        }
+
+        if self.routes.len() == MAX_ROUTES {
+            return Err(format!("Modulation matrix full at {MAX_ROUTES} routes"));
+        }
+
+        self.routes.push(route);
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<ModRoute> {
+        (index < self.routes.len()).then(|| self.routes.remove(index))
+    }
+
+    pub fn clear(&mut self) {
+        self.routes.clear();
+    }
+
+    pub fn routes(&self) -> &[ModRoute] {
+        &self.routes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    pub fn evaluate(&self, sources: &ModSources) -> ModOffsets {
+        let mut offsets = ModOffsets::default();
+
+        for route in &self.routes {
+            let value = sources.get(route.source) * route.amount;
+
+            match route.destination {
+                ModDestination::Pitch => offsets.pitch += value,
+                ModDestination::Amplitude => offsets.amplitude += value,
+                ModDestination::FilterCutoff => offsets.filter_cutoff += value,
+                ModDestination::WavetablePosition => offsets.wavetable_position += value,
+                ModDestination::Pan => offsets.pan += value,
+            }
+        }
+
+        offsets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sums_routes_per_destination() {
+        let mut matrix = ModMatrix::new();
+
+        matrix
+            .add(ModRoute {
+                source: ModSource::Lfo(1),
+                destination: ModDestination::Pitch,
+                amount: 2.0,
+            })
+            .unwrap();
+        matrix
+            .add(ModRoute {
+                source: ModSource::Velocity,
+                destination: ModDestination::Pitch,
+                amount: -1.0,
+            })
+            .unwrap();
+        matrix
+            .add(ModRoute {
+                source: ModSource::Envelope,
+                destination: ModDestination::FilterCutoff,
+                amount: 3.0,
+            })
+            .unwrap();
+
+        let offsets = matrix.evaluate(&ModSources {
+            lfos: [0.0, 0.5],
+            envelope: 0.5,
+            velocity: 0.25,
+            ..ModSources::default()
+        });
+
+        assert!(offsets.pitch == 0.75);
+        assert!(offsets.filter_cutoff == 1.5);
+        assert!(offsets.amplitude == 0.0);
+        assert!(offsets.wavetable_position == 0.0);
+    }
+
+    #[test]
+    fn limits_routes() {
+        let mut matrix = ModMatrix::new();
+        let route = ModRoute {
+            source: ModSource::KeyTrack,
+            destination: ModDestination::Amplitude,
+            amount: 1.0,
+        };
+
+        assert!(matrix
+            .add(ModRoute {
+                source: ModSource::Lfo(LFO_COUNT),
+                ..route
+            })
+            .is_err());
+
+        for _ in 0..MAX_ROUTES {
+            matrix.add(route).unwrap();
+        }
+
+        assert!(matrix.add(route).is_err());
+        assert!(matrix.remove(0) == Some(route));
+        assert!(matrix.remove(MAX_ROUTES).is_none());
+
+        matrix.clear();
+        assert!(matrix.is_empty());
+    }
+}
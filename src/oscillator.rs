@@ -0,0 +1,290 @@
+use crate::tuning::Tuning;
+
+///
+/// A periodic source the voices can play, whether read from a wavetable or
+/// computed directly from a `Waveform`.
+///
+pub trait Oscillator {
+    fn frequency(&self) -> f64;
+
+    fn set_frequency(&mut self, frequency: f64);
+
+    ///
+    /// Tunes to MIDI `note` in `tuning`, bent by `cents`. Keys the tuning
+    /// leaves silent keep the current frequency.
+    ///
+    fn set_pitch(&mut self, tuning: &Tuning, note: u8, cents: f64) {
+        if let Some(frequency) = tuning.frequency(note, cents) {
+            self.set_frequency(frequency);
+        }
+    }
+
+    fn sample_rate(&self) -> f64;
+
+    fn set_sample_rate(&mut self, sample_rate: f64);
+
+    ///
+    /// Position in the current cycle, from `0.0` to `1.0`.
+    ///
+    fn phase(&self) -> f64;
+
+    ///
+    /// Hard-sync input, jumping straight to `phase` in the cycle.
+    ///
+    fn sync(&mut self, phase: f64);
+
+    fn reset_phase(&mut self) {
+        self.sync(0.0);
+    }
+
+    ///
+    /// Morph position across the frames of a bank. Oscillators with a single
+    /// shape ignore it.
+    ///
+    fn set_position(&mut self, _position: f64) {}
+
+    fn next_sample(&mut self) -> f64;
+
+    fn process_block(&mut self, block: &mut [f32]) {
+        for sample in block.iter_mut() {
+            *sample = self.next_sample() as f32;
+        }
+    }
+
+    ///
+    /// Copies the oscillator behind a box, so that voices holding different
+    /// types can still be cloned.
+    ///
+    fn clone_box(&self) -> Box<dyn Oscillator + Send>;
+}
+
+impl Clone for Box<dyn Oscillator + Send> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+impl Oscillator for Box<dyn Oscillator + Send> {
+    fn frequency(&self) -> f64 {
+        (**self).frequency()
+    }
+
+    fn set_frequency(&mut self, frequency: f64) {
+        (**self).set_frequency(frequency);
+    }
+
+    fn sample_rate(&self) -> f64 {
+        (**self).sample_rate()
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        (**self).set_sample_rate(sample_rate);
+    }
+
+    fn phase(&self) -> f64 {
+        (**self).phase()
+    }
+
+    fn sync(&mut self, phase: f64) {
+        (**self).sync(phase);
+    }
+
+    fn set_position(&mut self, position: f64) {
+        (**self).set_position(position);
+    }
+
+    fn next_sample(&mut self) -> f64 {
+        (**self).next_sample()
+    }
+
+    fn process_block(&mut self, block: &mut [f32]) {
+        (**self).process_block(block);
+    }
+
+    ///
+    /// Copies what is behind the box rather than boxing it again.
+    ///
+    fn clone_box(&self) -> Box<dyn Oscillator + Send> {
+        (**self).clone_box()
+    }
+}
+
+///
+/// Plays `slave` restarted by every cycle of the silent `master`, so that
+/// sweeping `ratio` sweeps the slave's harmonics while the pitch stays at
+/// the master's frequency.
+///
+#[derive(Clone, Debug)]
+pub struct HardSync<M, S> {
+    pub master: M,
+    pub slave: S,
+    /// Slave frequency as a multiple of the master's.
+    pub ratio: f64,
+}
+
+impl<M: Oscillator, S: Oscillator> HardSync<M, S> {
+    pub fn new(master: M, slave: S, ratio: f64) -> Self {
+        Self {
+            master,
+            slave,
+            ratio,
+        }
+    }
+}
+
+impl<M, S> Oscillator for HardSync<M, S>
+where
+    M: Oscillator + Clone + Send + 'static,
+    S: Oscillator + Clone + Send + 'static,
+{
+    fn frequency(&self) -> f64 {
+        self.master.frequency()
+    }
+
+    fn set_frequency(&mut self, frequency: f64) {
+        self.master.set_frequency(frequency);
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.master.sample_rate()
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.master.set_sample_rate(sample_rate);
+        self.slave.set_sample_rate(sample_rate);
+    }
+
+    fn phase(&self) -> f64 {
+        self.master.phase()
+    }
+
+    fn sync(&mut self, phase: f64) {
+        self.master.sync(phase);
+        self.slave.sync((phase * self.ratio).rem_euclid(1.0));
+    }
+
+    fn set_position(&mut self, position: f64) {
+        self.slave.set_position(position);
+    }
+
+    fn next_sample(&mut self) -> f64 {
+        self.slave
+            .set_frequency(self.master.frequency() * self.ratio);
+
+        let before = self.master.phase();
+
+        self.master.next_sample();
+
+        let sample = self.slave.next_sample();
+        let after = self.master.phase();
+
+        // The slave restarts where it would be had it started with the
+        // master's new cycle, part way through this sample.
+        if after < before {
+            self.slave.sync((after * self.ratio).rem_euclid(1.0));
+        }
+
+        sample
+    }
+
+    fn clone_box(&self) -> Box<dyn Oscillator + Send> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        tuning::{KeyboardMapping, Scale},
+        waveform::{self, BlepSawtooth, WaveformIter},
+        wavetable::{detect_cycle_length, Interpolation, MipmappedWavetable, PhaseAccumulator},
+    };
+
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 48_000.0;
+
+    #[test]
+    fn wavetable_and_waveform_oscillators_agree() {
+        let table = MipmappedWavetable::new(2048, waveform::sine);
+        let mut oscillators: Vec<Box<dyn Oscillator + Send>> = vec![
+            Box::new(table.iter(0.0, SAMPLE_RATE, Interpolation::CubicHermite)),
+            Box::new(WaveformIter::new(waveform::sine, 0.0, SAMPLE_RATE)),
+        ];
+
+        let blocks: Vec<Vec<f32>> = oscillators
+            .iter_mut()
+            .map(|oscillator| {
+                let mut block = vec![0.0; 256];
+
+                oscillator.set_frequency(440.0);
+                oscillator.process_block(&mut block);
+                oscillator.reset_phase();
+                assert!(oscillator.phase() == 0.0);
+
+                block
+            })
+            .collect();
+
+        for (a, b) in blocks[0].iter().zip(&blocks[1]) {
+            assert!((a - b).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn hard_sync_restarts_slave_each_master_cycle() {
+        let sawtooth = || WaveformIter::new(BlepSawtooth, 0.0, SAMPLE_RATE);
+        let mut oscillator = HardSync::new(sawtooth(), sawtooth(), 2.7);
+
+        // A master period of exactly 100 samples.
+        oscillator.set_frequency(480.0);
+
+        let mut block = [0.0; 1_000];
+
+        oscillator.process_block(&mut block);
+
+        // Every master cycle repeats the same slave fragment, even though
+        // 2.7 slave cycles don't fit a whole number of times.
+        for (a, b) in block[100..900].iter().zip(&block[200..]) {
+            assert!((a - b).abs() < 1e-4);
+        }
+
+        assert!(oscillator.frequency() == 480.0);
+        assert!((oscillator.slave.frequency() - 1_296.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn set_pitch_follows_tuning() {
+        let tuning = Tuning::new(
+            Scale::equal(19),
+            KeyboardMapping {
+                last_note: 100,
+                ..KeyboardMapping::default()
+            },
+        )
+        .unwrap();
+        let mut oscillator = MipmappedWavetable::new(2048, waveform::sine).iter(
+            0.0,
+            SAMPLE_RATE,
+            Interpolation::CubicHermite,
+        );
+
+        oscillator.set_accumulator(PhaseAccumulator::Fixed);
+        oscillator.set_pitch(&tuning, 72, 30.0);
+
+        // Three 19-tone steps above A4, then the bend.
+        let expected = 440.0 * 2.0_f64.powf((3.0 * 1_200.0 / 19.0 + 30.0) / 1_200.0);
+        let samples: Vec<f64> = (0..4_800).map(|_| oscillator.next_sample()).collect();
+
+        assert!((oscillator.frequency() - expected).abs() < 1e-9);
+        assert!(
+            (SAMPLE_RATE / detect_cycle_length(&samples).unwrap() / expected - 1.0).abs() < 0.001
+        );
+
+        // Keys outside the mapping are silent, so the pitch stays put.
+        let frequency = oscillator.frequency();
+
+        oscillator.set_pitch(&tuning, 101, 0.0);
+        assert!(oscillator.frequency() == frequency);
+    }
+}
@@ -0,0 +1,834 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::{
+    arpeggiator::ArpSettings,
+    chorus::ChorusSettings,
+    delay::DelaySettings,
+    effects::{EffectKind, SlotSettings, EFFECT_COUNT},
+    engine::{Command, EngineHandle},
+    envelope::Adsr,
+    filter::FilterSettings,
+    fm::{FmOscillator, FmSettings},
+    lfo::LfoSettings,
+    master::LimiterSettings,
+    modulation::{ModRoute, ModSource, LFO_COUNT, MAX_ROUTES},
+    oscillator::Oscillator,
+    reverb::ReverbSettings,
+    sequencer::{SequencerSettings, Step, MAX_STEPS},
+    synth::{Synth, DEFAULT_POLYPHONY},
+    tuning::Tuning,
+    unison::UnisonSettings,
+    wav::Wav,
+    waveform,
+    wavetable::{
+        Interpolation, MipmappedWavetable, PhaseAccumulator, WavetableBank, WavetableIter,
+    },
+};
+
+///
+/// Schema version written into every preset. Older presets are migrated up
+/// to it on load.
+///
+pub const PRESET_VERSION: u32 = 5;
+
+///
+/// Where presets are saved to and listed from, relative to the working
+/// directory.
+///
+pub const PRESET_DIR: &str = "presets";
+
+///
+/// Starts every binary preset, ahead of its MessagePack body. Field names
+/// are kept in the body so that old versions migrate just as JSON does.
+///
+const BINARY_MAGIC: &[u8; 4] = b"RPPB";
+
+///
+/// Rewrites a preset from one version into the next.
+///
+type Migration = fn(&mut Value) -> Result<(), String>;
+
+///
+/// Applied in turn to presets older than `PRESET_VERSION`, the first taking
+/// version 1 to 2.
+///
+const MIGRATIONS: [Migration; PRESET_VERSION as usize - 1] =
+    [add_note_sources, add_fm, add_unison, add_tuning];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Shape {
+    Sine,
+    Triangle,
+    #[default]
+    Sawtooth,
+    Square,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum WavetableSource {
+    Waveform(Shape),
+    /// Cycles sliced from a WAV file, as in `WavetableBank::from_wav`.
+    Wav {
+        path: PathBuf,
+        cycle_length: Option<usize>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct OscillatorSettings {
+    pub source: WavetableSource,
+    /// Samples per cycle of a waveform's table. WAV sources keep their own.
+    pub table_length: usize,
+    pub interpolation: Interpolation,
+    pub accumulator: PhaseAccumulator,
+    /// Plays the source through FM operators instead of straight.
+    pub fm: Option<FmSettings>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct EffectsPreset {
+    pub chorus: ChorusSettings,
+    pub delay: DelaySettings,
+    pub reverb: ReverbSettings,
+    /// How each effect is mixed in, in the order of `EffectKind::ALL`.
+    pub slots: [SlotSettings; EFFECT_COUNT],
+    pub order: [EffectKind; EFFECT_COUNT],
+}
+
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SequencerPreset {
+    pub settings: SequencerSettings,
+    /// The pattern up to its last note, rests as `None`.
+    pub steps: Vec<Option<Step>>,
+}
+
+///
+/// Everything that shapes the sound, as saved to and loaded from disk.
+/// Performance state (held notes, controllers, tempo) is left out.
+///
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Preset {
+    pub version: u32,
+    pub oscillator: OscillatorSettings,
+    pub unison: UnisonSettings,
+    pub tuning: Tuning,
+    pub envelope: Adsr,
+    pub filter: FilterSettings,
+    pub lfos: [LfoSettings; LFO_COUNT],
+    pub routes: Vec<ModRoute>,
+    pub polyphony: usize,
+    pub gain: f64,
+    pub pan: f64,
+    pub width: f64,
+    pub effects: EffectsPreset,
+    pub limiter: LimiterSettings,
+    pub sequencer: SequencerPreset,
+    pub arpeggiator: ArpSettings,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresetFormat {
+    Json,
+    /// MessagePack behind `BINARY_MAGIC`.
+    Binary,
+}
+
+impl Default for OscillatorSettings {
+    fn default() -> Self {
+        Self {
+            source: WavetableSource::Waveform(Shape::default()),
+            table_length: 2048,
+            interpolation: Interpolation::CubicHermite,
+            accumulator: PhaseAccumulator::default(),
+            fm: None,
+        }
+    }
+}
+
+impl Default for EffectsPreset {
+    fn default() -> Self {
+        Self {
+            chorus: ChorusSettings::default(),
+            delay: DelaySettings::default(),
+            reverb: ReverbSettings::default(),
+            slots: EffectKind::ALL.map(SlotSettings::default_for),
+            order: EffectKind::ALL,
+        }
+    }
+}
+
+///
+/// Matches a fresh `Synth`.
+///
+impl Default for Preset {
+    fn default() -> Self {
+        Self {
+            version: PRESET_VERSION,
+            oscillator: OscillatorSettings::default(),
+            unison: UnisonSettings::default(),
+            tuning: Tuning::default(),
+            envelope: Adsr::default(),
+            filter: FilterSettings::default(),
+            lfos: [LfoSettings::default(); LFO_COUNT],
+            routes: Vec::new(),
+            polyphony: DEFAULT_POLYPHONY,
+            gain: 0.1,
+            pan: 0.0,
+            width: 1.0,
+            effects: EffectsPreset::default(),
+            limiter: LimiterSettings::default(),
+            sequencer: SequencerPreset::default(),
+            arpeggiator: ArpSettings::default(),
+        }
+    }
+}
+
+impl Shape {
+    fn waveform(&self) -> fn(f64) -> f64 {
+        match self {
+            Shape::Sine => waveform::sine,
+            Shape::Triangle => waveform::triangle,
+            Shape::Sawtooth => waveform::sawtooth,
+            Shape::Square => waveform::square,
+        }
+    }
+}
+
+impl OscillatorSettings {
+    ///
+    /// Builds the tables, reading the WAV file if there is one.
+    ///
+    pub fn build(&self, sample_rate: f64) -> Result<Box<dyn Oscillator + Send>, String> {
+        let table = self.build_table(sample_rate)?;
+
+        Ok(match self.fm {
+            Some(settings) => Box::new(FmOscillator::new(table, settings)),
+            None => Box::new(table),
+        })
+    }
+
+    ///
+    /// Catches settings the tables can't be built from, without reading
+    /// any WAV file.
+    ///
+    pub fn check(&self) -> Result<(), String> {
+        let length = match &self.source {
+            WavetableSource::Waveform(_) => Some(self.table_length),
+            WavetableSource::Wav { cycle_length, .. } => *cycle_length,
+        };
+
+        match length {
+            Some(length) if length < 4 => Err("Wavetables need at least 4 samples".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    fn build_table(&self, sample_rate: f64) -> Result<WavetableIter, String> {
+        self.check()?;
+
+        let mut table = match &self.source {
+            WavetableSource::Waveform(shape) => {
+                let table = MipmappedWavetable::new(self.table_length, shape.waveform());
+
+                table.iter(0.0, sample_rate, self.interpolation)
+            }
+            WavetableSource::Wav { path, cycle_length } => {
+                let bank = WavetableBank::from_wav(&Wav::read(path)?, *cycle_length)?;
+
+                bank.iter(0.0, sample_rate, self.interpolation)
+            }
+        };
+
+        table.set_accumulator(self.accumulator);
+
+        Ok(table)
+    }
+}
+
+impl PresetFormat {
+    ///
+    /// Chosen by extension: `.json` for JSON, `.bin` for binary.
+    ///
+    pub fn for_path<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        match path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("json") => Ok(PresetFormat::Json),
+            Some("bin") => Ok(PresetFormat::Binary),
+            _ => Err(format!(
+                "Preset '{}' needs a .json or .bin extension",
+                path.as_ref().display()
+            )),
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            PresetFormat::Json => "json",
+            PresetFormat::Binary => "bin",
+        }
+    }
+}
+
+impl Preset {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let bytes = std::fs::read(&path)
+            .map_err(|e| format!("Could not read '{}': {e}", path.as_ref().display()))?;
+
+        Self::decode(&bytes)
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let bytes = self.encode(PresetFormat::for_path(&path)?)?;
+
+        std::fs::write(&path, bytes)
+            .map_err(|e| format!("Could not write '{}': {e}", path.as_ref().display()))
+    }
+
+    pub fn encode(&self, format: PresetFormat) -> Result<Vec<u8>, String> {
+        match format {
+            PresetFormat::Json => serde_json::to_vec_pretty(self).map_err(|e| e.to_string()),
+            PresetFormat::Binary => {
+                let body = rmp_serde::to_vec_named(self).map_err(|e| e.to_string())?;
+
+                Ok([BINARY_MAGIC.as_slice(), &body].concat())
+            }
+        }
+    }
+
+    ///
+    /// Reads either format, telling them apart by the binary magic, and
+    /// migrates older versions.
+    ///
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let value: Value = match bytes.strip_prefix(BINARY_MAGIC) {
+            Some(body) => rmp_serde::from_slice(body).map_err(|e| e.to_string())?,
+            None => serde_json::from_slice(bytes).map_err(|e| e.to_string())?,
+        };
+
+        let preset: Self =
+            serde_json::from_value(migrate(value)?).map_err(|e| format!("Invalid preset: {e}"))?;
+
+        // @note: Checked here so that a bad preset can't panic when it is played
+        preset
+            .oscillator
+            .check()
+            .map_err(|e| format!("Invalid oscillator: {e}"))?;
+        Tuning::new(preset.tuning.scale.clone(), preset.tuning.mapping.clone())
+            .map_err(|e| format!("Invalid tuning: {e}"))?;
+
+        Ok(preset)
+    }
+
+    ///
+    /// Sets up `synth` to play this preset straight away, without gliding.
+    /// Building the oscillator allocates, so it belongs on the control side.
+    ///
+    pub fn apply(&self, synth: &mut Synth) -> Result<(), String> {
+        let oscillator = self.oscillator.build(synth.sample_rate())?;
+
+        synth.voices.set_oscillator(oscillator);
+
+        for command in self.commands() {
+            synth.apply(command);
+        }
+
+        synth.gain.reset(self.gain);
+        synth.width.reset(self.width);
+        synth.voices.pan.reset(self.pan);
+
+        Ok(())
+    }
+
+    ///
+    /// Switches a running engine over to this preset, cutting any notes
+    /// playing.
+    ///
+    pub fn send(&self, engine: &EngineHandle) -> Result<(), String> {
+        // @note: The engine retunes it to the device's rate when it arrives
+        let oscillator = self.oscillator.build(48_000.0)?;
+
+        for command in self.commands() {
+            engine.send(command)?;
+        }
+
+        engine.set_oscillator(oscillator)
+    }
+
+    ///
+    /// Follows a command sent to the engine, so that the preset keeps up
+    /// with changes made live. Commands that don't touch the sound are
+    /// ignored, as are note frequencies, which can't be traced back to a
+    /// scale, so the tuning is set directly.
+    ///
+    pub fn record(&mut self, command: Command) {
+        match command {
+            Command::SetGain(gain) => self.gain = gain,
+            Command::SetPan(pan) => self.pan = pan,
+            Command::SetWidth(width) => self.width = width,
+            Command::SetPolyphony(polyphony) => self.polyphony = polyphony,
+            Command::SetEnvelope(settings) => self.envelope = settings,
+            Command::SetFilter(settings) => self.filter = settings,
+            Command::SetLfo { index, settings } => {
+                if let Some(lfo) = self.lfos.get_mut(index) {
+                    *lfo = settings;
+                }
+            }
+            Command::AddRoute(route) => {
+                let valid = match route.source {
+                    ModSource::Lfo(index) => index < LFO_COUNT,
+                    _ => true,
+                };
+
+                if valid && self.routes.len() < MAX_ROUTES {
+                    self.routes.push(route);
+                }
+            }
+            Command::ClearRoutes => self.routes.clear(),
+            Command::SetChorus(settings) => self.effects.chorus = settings,
+            Command::SetDelay(settings) => self.effects.delay = settings,
+            Command::SetReverb(settings) => self.effects.reverb = settings,
+            Command::SetEffect { effect, settings } => {
+                self.effects.slots[effect as usize] = settings;
+            }
+            Command::SetEffectOrder(order) => self.effects.order = order,
+            Command::SetLimiter(settings) => self.limiter = settings,
+            Command::SetSequencer(settings) => self.sequencer.settings = settings,
+            Command::SetStep { index, step } if index < MAX_STEPS => {
+                let steps = &mut self.sequencer.steps;
+
+                if index >= steps.len() {
+                    steps.resize(index + 1, None);
+                }
+
+                steps[index] = step;
+
+                while steps.last() == Some(&None) {
+                    steps.pop();
+                }
+            }
+            Command::ClearSteps => self.sequencer.steps.clear(),
+            Command::SetArpeggiator(settings) => self.arpeggiator = settings,
+            Command::SetUnison(settings) => self.unison = settings,
+            _ => {}
+        }
+    }
+
+    ///
+    /// Every setting but the oscillator, as engine commands. The tuning goes
+    /// as one frequency per note.
+    ///
+    pub fn commands(&self) -> Vec<Command> {
+        let mut commands = vec![
+            Command::SetUnison(self.unison),
+            Command::SetGain(self.gain),
+            Command::SetPan(self.pan),
+            Command::SetWidth(self.width),
+            Command::SetPolyphony(self.polyphony),
+            Command::SetEnvelope(self.envelope),
+            Command::SetFilter(self.filter),
+            Command::ClearRoutes,
+        ];
+
+        commands.extend(
+            self.lfos
+                .iter()
+                .enumerate()
+                .map(|(index, settings)| Command::SetLfo {
+                    index,
+                    settings: *settings,
+                }),
+        );
+        commands.extend(self.routes.iter().copied().map(Command::AddRoute));
+        commands.extend([
+            Command::SetChorus(self.effects.chorus),
+            Command::SetDelay(self.effects.delay),
+            Command::SetReverb(self.effects.reverb),
+            Command::SetEffectOrder(self.effects.order),
+            Command::SetLimiter(self.limiter),
+        ]);
+        commands.extend(
+            EffectKind::ALL
+                .into_iter()
+                .zip(self.effects.slots)
+                .map(|(effect, settings)| Command::SetEffect { effect, settings }),
+        );
+        commands.extend(self.tuning.frequencies().into_iter().enumerate().map(
+            |(note, frequency)| Command::SetNoteFrequency {
+                note: note as u8,
+                frequency,
+            },
+        ));
+        commands.extend([
+            Command::SetSequencer(self.sequencer.settings),
+            Command::SetArpeggiator(self.arpeggiator),
+            Command::ClearSteps,
+        ]);
+        commands.extend(
+            self.sequencer
+                .steps
+                .iter()
+                .enumerate()
+                .filter(|(_, step)| step.is_some())
+                .map(|(index, step)| Command::SetStep { index, step: *step }),
+        );
+
+        commands
+    }
+
+    ///
+    /// One line per setting that differs, as `path: ours -> theirs`.
+    ///
+    pub fn diff(&self, other: &Preset) -> Result<Vec<String>, String> {
+        let ours = serde_json::to_value(self).map_err(|e| e.to_string())?;
+        let theirs = serde_json::to_value(other).map_err(|e| e.to_string())?;
+        let mut lines = Vec::new();
+
+        diff_values("", &ours, &theirs, &mut lines);
+
+        Ok(lines)
+    }
+}
+
+///
+/// Finds a preset by path, or else in `PRESET_DIR` by file name or by name
+/// alone, trying JSON before binary.
+///
+pub fn resolve(name: &str) -> Result<PathBuf, String> {
+    let mut candidates = vec![PathBuf::from(name), Path::new(PRESET_DIR).join(name)];
+
+    candidates
+        .extend([PresetFormat::Json, PresetFormat::Binary].map(|format| path_for(name, format)));
+
+    candidates
+        .into_iter()
+        .find(|path| path.is_file())
+        .ok_or_else(|| format!("No preset '{name}'"))
+}
+
+///
+/// Where `save` puts a preset called `name`.
+///
+pub fn path_for(name: &str, format: PresetFormat) -> PathBuf {
+    Path::new(PRESET_DIR).join(format!("{name}.{}", format.extension()))
+}
+
+///
+/// Preset file names in `dir`, sorted.
+///
+pub fn list<P: AsRef<Path>>(dir: P) -> Result<Vec<String>, String> {
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && PresetFormat::for_path(path).is_ok())
+        .filter_map(|path| Some(path.file_name()?.to_str()?.to_string()))
+        .collect();
+
+    names.sort();
+
+    Ok(names)
+}
+
+///
+/// Brings an older preset up to `PRESET_VERSION`, one version at a time.
+///
+fn migrate(mut value: Value) -> Result<Value, String> {
+    let version = value
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or("Preset has no version")?;
+
+    if version == 0 || version > PRESET_VERSION as u64 {
+        return Err(format!(
+            "Preset version {version} is not supported, expected 1 to {PRESET_VERSION}"
+        ));
+    }
+
+    for migration in &MIGRATIONS[version as usize - 1..] {
+        migration(&mut value)?;
+    }
+
+    value["version"] = Value::from(PRESET_VERSION);
+
+    Ok(value)
+}
+
+///
+/// Version 2 added the step sequencer and the arpeggiator, both off.
+///
+fn add_note_sources(value: &mut Value) -> Result<(), String> {
+    let preset = value.as_object_mut().ok_or("Preset is not an object")?;
+    let defaults = serde_json::to_value(Preset::default()).map_err(|e| e.to_string())?;
+
+    for key in ["sequencer", "arpeggiator"] {
+        preset.insert(key.to_string(), defaults[key].clone());
+    }
+
+    Ok(())
+}
+
+///
+/// Version 3 added FM to the oscillator, off.
+///
+fn add_fm(value: &mut Value) -> Result<(), String> {
+    value
+        .get_mut("oscillator")
+        .and_then(Value::as_object_mut)
+        .ok_or("Preset has no oscillator")?
+        .insert("fm".to_string(), Value::Null);
+
+    Ok(())
+}
+
+///
+/// Version 4 added unison, with a single copy.
+///
+fn add_unison(value: &mut Value) -> Result<(), String> {
+    let defaults = serde_json::to_value(UnisonSettings::default()).map_err(|e| e.to_string())?;
+
+    value
+        .as_object_mut()
+        .ok_or("Preset is not an object")?
+        .insert("unison".to_string(), defaults);
+
+    Ok(())
+}
+
+///
+/// Version 5 added tuning tables, in twelve-tone equal temperament, and the
+/// choice of phase accumulator, in floating point.
+///
+fn add_tuning(value: &mut Value) -> Result<(), String> {
+    let defaults = serde_json::to_value(Tuning::default()).map_err(|e| e.to_string())?;
+
+    value
+        .get_mut("oscillator")
+        .and_then(Value::as_object_mut)
+        .ok_or("Preset has no oscillator")?
+        .insert(
+            "accumulator".to_string(),
+            serde_json::to_value(PhaseAccumulator::Float).map_err(|e| e.to_string())?,
+        );
+    value
+        .as_object_mut()
+        .ok_or("Preset is not an object")?
+        .insert("tuning".to_string(), defaults);
+
+    Ok(())
+}
+
+fn diff_values(path: &str, ours: &Value, theirs: &Value, lines: &mut Vec<String>) {
+    match (ours, theirs) {
+        (Value::Object(ours), Value::Object(theirs)) => {
+            for (key, value) in ours {
+                let path = match path.is_empty() {
+                    true => key.clone(),
+                    false => format!("{path}.{key}"),
+                };
+
+                diff_values(&path, value, theirs.get(key).unwrap_or(&Value::Null), lines);
+            }
+
+            for (key, value) in theirs.iter().filter(|(key, _)| !ours.contains_key(*key)) {
+                diff_values(&format!("{path}.{key}"), &Value::Null, value, lines);
+            }
+        }
+        (Value::Array(ours), Value::Array(theirs)) if ours.len() == theirs.len() => {
+            for (index, (ours, theirs)) in ours.iter().zip(theirs).enumerate() {
+                diff_values(&format!("{path}[{index}]"), ours, theirs, lines);
+            }
+        }
+        _ if ours != theirs => lines.push(format!("{path}: {ours} -> {theirs}")),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        delay::DelayTime,
+        effects::Routing,
+        lfo::{LfoRate, LfoShape},
+        modulation::ModDestination,
+    };
+
+    use super::*;
+
+    fn patch() -> Preset {
+        let mut preset = Preset {
+            oscillator: OscillatorSettings {
+                source: WavetableSource::Waveform(Shape::Square),
+                table_length: 512,
+                interpolation: Interpolation::Linear,
+                accumulator: PhaseAccumulator::Fixed,
+                fm: Some(FmSettings {
+                    algorithm: 5,
+                    feedback: 0.3,
+                    ..FmSettings::default()
+                }),
+            },
+            tuning: Tuning::just_intonation(62),
+            gain: 0.3,
+            ..Preset::default()
+        };
+
+        preset.lfos[1] = LfoSettings {
+            shape: LfoShape::Triangle,
+            rate: LfoRate::Beats(0.5),
+            ..LfoSettings::default()
+        };
+        preset.routes.push(ModRoute {
+            source: ModSource::Lfo(1),
+            destination: ModDestination::FilterCutoff,
+            amount: 2.0,
+        });
+        preset.effects.delay.time = DelayTime::Beats(0.75);
+        preset.sequencer.steps = vec![Some(Step::default()), None, Some(Step::default())];
+        preset.arpeggiator.octaves = 2;
+        preset.unison.count = 7;
+        preset.effects.slots[EffectKind::Reverb as usize] = SlotSettings {
+            routing: Routing::Send,
+            mix: 0.4,
+            bypass: false,
+        };
+
+        preset
+    }
+
+    #[test]
+    fn round_trips_both_formats() {
+        let preset = patch();
+
+        for format in [PresetFormat::Json, PresetFormat::Binary] {
+            let bytes = preset.encode(format).unwrap();
+
+            assert!(Preset::decode(&bytes).unwrap() == preset);
+        }
+
+        let binary = preset.encode(PresetFormat::Binary).unwrap();
+        let compact = serde_json::to_vec(&preset).unwrap();
+
+        assert!(binary.starts_with(BINARY_MAGIC));
+        assert!(binary.len() < compact.len());
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let mut value = serde_json::to_value(Preset::default()).unwrap();
+
+        value["version"] = Value::from(PRESET_VERSION + 1);
+        assert!(Preset::decode(value.to_string().as_bytes()).is_err());
+
+        value.as_object_mut().unwrap().remove("version");
+        assert!(Preset::decode(value.to_string().as_bytes()).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_tunings() {
+        let mut value = serde_json::to_value(Preset::default()).unwrap();
+
+        value["tuning"]["scale"]["degrees"] = Value::Array(Vec::new());
+        assert!(Preset::decode(value.to_string().as_bytes()).is_err());
+    }
+
+    #[test]
+    fn rejects_short_wavetables() {
+        let mut value = serde_json::to_value(Preset::default()).unwrap();
+
+        value["oscillator"]["table_length"] = Value::from(3);
+        assert!(Preset::decode(value.to_string().as_bytes()).is_err());
+
+        let settings = OscillatorSettings {
+            table_length: 3,
+            ..OscillatorSettings::default()
+        };
+
+        assert!(settings.build(48_000.0).is_err());
+
+        value["oscillator"]["table_length"] = Value::from(4);
+        assert!(Preset::decode(value.to_string().as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn migrates_version_1() {
+        let mut value = serde_json::to_value(patch()).unwrap();
+        let preset = value.as_object_mut().unwrap();
+
+        preset.remove("sequencer");
+        preset.remove("arpeggiator");
+        preset.remove("unison");
+        preset.remove("tuning");
+        value["oscillator"].as_object_mut().unwrap().remove("fm");
+        value["oscillator"]
+            .as_object_mut()
+            .unwrap()
+            .remove("accumulator");
+        value["version"] = Value::from(1);
+
+        let migrated = Preset::decode(value.to_string().as_bytes()).unwrap();
+
+        assert!(migrated.version == PRESET_VERSION);
+        assert!(migrated.sequencer == SequencerPreset::default());
+        assert!(migrated.arpeggiator == ArpSettings::default());
+        assert!(migrated.oscillator.fm.is_none());
+        assert!(migrated.unison == UnisonSettings::default());
+        assert!(migrated.tuning == Tuning::default());
+        assert!(migrated.oscillator.accumulator == PhaseAccumulator::Float);
+        assert!(migrated.gain == patch().gain);
+    }
+
+    #[test]
+    fn records_what_it_sends() {
+        let preset = patch();
+        let mut recorded = Preset {
+            oscillator: preset.oscillator.clone(),
+            tuning: preset.tuning.clone(),
+            ..Preset::default()
+        };
+
+        for command in preset.commands() {
+            recorded.record(command);
+        }
+
+        assert!(recorded == preset);
+    }
+
+    #[test]
+    fn default_matches_fresh_synth() {
+        let render = |synth: &mut Synth| {
+            let mut buffer = vec![[0.0; 2]; 4_800];
+
+            synth.note_on(57, 127);
+            synth.render(&mut buffer);
+            buffer
+        };
+
+        let mut synth = Synth::new(48_000.0);
+
+        Preset::default().apply(&mut synth).unwrap();
+        assert!(render(&mut synth) == render(&mut Synth::new(48_000.0)));
+    }
+
+    #[test]
+    fn diffs_changed_settings() {
+        let lines = Preset::default().diff(&patch()).unwrap();
+
+        assert!(lines.contains(&"gain: 0.1 -> 0.3".to_string()));
+        assert!(lines.contains(&"lfos[1].shape: \"Sine\" -> \"Triangle\"".to_string()));
+        assert!(lines.contains(&"effects.slots[2].bypass: true -> false".to_string()));
+        assert!(Preset::default()
+            .diff(&Preset::default())
+            .unwrap()
+            .is_empty());
+    }
+}
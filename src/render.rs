@@ -0,0 +1,297 @@
+use std::path::Path;
+
+use crate::{
+    dither::{Dither, Quantizer},
+    midi::MidiSource,
+    preset::Preset,
+    synth::Synth,
+    wav::{SampleFormat, Wav},
+};
+
+///
+/// A3, the 220 Hz drone the engine played before it was polyphonic.
+///
+const RENDER_NOTE: u8 = 57;
+
+///
+/// Runs a fresh `Synth` holding `RENDER_NOTE` for `seconds` without an audio
+/// device.
+///
+pub fn render(seconds: f64, sample_rate: u32) -> Vec<[f64; 2]> {
+    play(Synth::new(sample_rate as f64), seconds)
+}
+
+///
+/// Runs a fresh `Synth` for `seconds`, playing whatever `source` has due
+/// before each sample.
+///
+pub fn render_midi<S: MidiSource>(source: &mut S, seconds: f64, sample_rate: u32) -> Vec<[f64; 2]> {
+    play_midi(Synth::new(sample_rate as f64), source, seconds)
+}
+
+pub fn render_to_wav<P: AsRef<Path>>(
+    path: P,
+    preset: &Preset,
+    seconds: f64,
+    sample_rate: u32,
+    format: SampleFormat,
+    dither: Dither,
+) -> Result<(), String> {
+    check_seconds(seconds)?;
+
+    let synth = load(preset, sample_rate)?;
+
+    write(path, play(synth, seconds), sample_rate, format, dither)
+}
+
+pub fn render_midi_to_wav<P: AsRef<Path>, S: MidiSource>(
+    path: P,
+    preset: &Preset,
+    source: &mut S,
+    seconds: f64,
+    sample_rate: u32,
+    format: SampleFormat,
+    dither: Dither,
+) -> Result<(), String> {
+    check_seconds(seconds)?;
+
+    let synth = load(preset, sample_rate)?;
+
+    write(
+        path,
+        play_midi(synth, source, seconds),
+        sample_rate,
+        format,
+        dither,
+    )
+}
+
+fn check_seconds(seconds: f64) -> Result<(), String> {
+    match seconds.is_finite() && seconds >= 0.0 {
+        true => Ok(()),
+        false => Err(format!("Cannot render {seconds} seconds")),
+    }
+}
+
+fn load(preset: &Preset, sample_rate: u32) -> Result<Synth, String> {
+    let mut synth = Synth::new(sample_rate as f64);
+
+    preset.apply(&mut synth)?;
+
+    Ok(synth)
+}
+
+fn play(mut synth: Synth, seconds: f64) -> Vec<[f64; 2]> {
+    assert!(seconds >= 0.0);
+
+    // @note: A running pattern plays itself, so the drone would only get in its way
+    if !synth.sequencer.settings.running {
+        synth.note_on(RENDER_NOTE, 127);
+    }
+
+    let mut buffer = vec![[0.0; 2]; (seconds * synth.sample_rate()).round() as usize];
+
+    synth.render(&mut buffer);
+
+    buffer
+}
+
+fn play_midi<S: MidiSource>(mut synth: Synth, source: &mut S, seconds: f64) -> Vec<[f64; 2]> {
+    assert!(seconds >= 0.0);
+
+    let sample_rate = synth.sample_rate();
+    let mut buffer = vec![[0.0; 2]; (seconds * sample_rate).round() as usize];
+
+    // Renders up to each sample with messages due, so that notes still
+    // land on the exact sample.
+    let mut start = 0;
+
+    for n in 0..buffer.len() {
+        let time = n as f64 / sample_rate;
+
+        if let Some(message) = source.poll(time) {
+            synth.render(&mut buffer[start..n]);
+            start = n;
+
+            synth.handle_midi(message);
+
+            while let Some(message) = source.poll(time) {
+                synth.handle_midi(message);
+            }
+        }
+    }
+
+    synth.render(&mut buffer[start..]);
+
+    buffer
+}
+
+///
+/// Writes a stereo WAV, dithering integer formats narrower than 24 bits.
+///
+fn write<P: AsRef<Path>>(
+    path: P,
+    mut frames: Vec<[f64; 2]>,
+    sample_rate: u32,
+    format: SampleFormat,
+    dither: Dither,
+) -> Result<(), String> {
+    if matches!(format, SampleFormat::Pcm8 | SampleFormat::Pcm16) {
+        let mut quantizers = [(); 2].map(|_| Quantizer::new(format.bits() as u32, dither));
+
+        for frame in &mut frames {
+            for (sample, quantizer) in frame.iter_mut().zip(&mut quantizers) {
+                *sample = quantizer.quantize(*sample);
+            }
+        }
+    }
+
+    let wav = Wav {
+        sample_rate,
+        channels: 2,
+        format,
+        samples: frames.into_iter().flatten().collect(),
+        cycle_length: None,
+    };
+
+    wav.write(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        master::LOOKAHEAD, midi::MidiFile, sequencer::Step, voice::note_to_frequency,
+        wavetable::detect_cycle_length,
+    };
+
+    use super::*;
+
+    #[test]
+    fn renders_requested_duration() {
+        let buffer = render(0.5, 48_000);
+
+        assert!(buffer.len() == 24_000);
+        assert!(buffer.iter().any(|[left, _]| left.abs() > 0.05));
+        assert!(buffer.iter().flatten().all(|sample| sample.abs() <= 0.12));
+
+        // A centred voice is identical on both sides.
+        assert!(buffer
+            .iter()
+            .all(|[left, right]| (left - right).abs() < 1e-12));
+    }
+
+    #[test]
+    fn renders_deterministically() {
+        assert!(render(0.1, 44_100) == render(0.1, 44_100));
+    }
+
+    #[test]
+    fn writes_wav() {
+        let path = std::env::temp_dir().join(format!(
+            "rust_playground_render_test_{}.wav",
+            std::process::id()
+        ));
+
+        render_to_wav(
+            &path,
+            &Preset::default(),
+            0.25,
+            44_100,
+            SampleFormat::Pcm24,
+            Dither::default(),
+        )
+        .unwrap();
+
+        let wav = Wav::read(&path).unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+
+        for seconds in [-1.0, f64::NAN] {
+            let preset = Preset::default();
+
+            assert!(render_to_wav(
+                &path,
+                &preset,
+                seconds,
+                44_100,
+                SampleFormat::Pcm24,
+                Dither::default()
+            )
+            .is_err());
+        }
+
+        assert!(wav.format == SampleFormat::Pcm24);
+        assert!(wav.sample_rate == 44_100);
+        assert!(wav.channels == 2);
+        assert!(wav.frames() == 11_025);
+
+        for (a, b) in wav.samples.iter().zip(render(0.25, 44_100).concat()) {
+            assert!((a - b).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn replays_midi_file() {
+        // C, E and G as quarter notes at 120 BPM.
+        let mut file = MidiFile::read(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/assets/arpeggio_c_major.mid"
+        ))
+        .unwrap();
+
+        assert!(file.duration() == 1.5);
+
+        let buffer: Vec<f64> = render_midi(&mut file, 2.0, 48_000)
+            .iter()
+            .map(|[left, _]| *left)
+            .collect();
+
+        for (start, note) in [(0, 60), (24_000, 64), (48_000, 67)] {
+            let period = detect_cycle_length(&buffer[start + 2_400..start + 21_600]).unwrap();
+            let frequency = 48_000.0 / period;
+
+            assert!((frequency / note_to_frequency(note) - 1.0).abs() < 0.005);
+        }
+
+        // Silent once the last release has finished, bar the DC blocker's
+        // tail dying away below -120 dBFS.
+        assert!(buffer[84_000..].iter().all(|sample| sample.abs() < 1e-6));
+    }
+
+    #[test]
+    fn plays_sequencer_pattern() {
+        let mut preset = Preset::default();
+        let step = |note| {
+            Some(Step {
+                note,
+                ..Step::default()
+            })
+        };
+
+        // Quarter notes at 120 BPM, C, a rest, C again then G.
+        preset.sequencer.settings.running = true;
+        preset.sequencer.settings.length = 4;
+        preset.sequencer.settings.division = 1.0;
+        preset.sequencer.steps = vec![step(60), None, step(60), step(67)];
+
+        let buffer: Vec<f64> = play(load(&preset, 48_000).unwrap(), 2.0)
+            .iter()
+            .map(|[left, _]| *left)
+            .collect();
+        let onset =
+            |from: usize| from + buffer[from..].iter().position(|x| x.abs() > 1e-3).unwrap();
+
+        // Both notes come through the limiter's lookahead late by the same amount.
+        assert!(onset(0) < 2 * (LOOKAHEAD * 48_000.0) as usize);
+        assert!(buffer[36_000..48_000]
+            .iter()
+            .all(|sample| sample.abs() < 1e-6));
+        assert!(onset(36_000) - onset(0) == 48_000);
+
+        for (start, note) in [(0, 60), (72_000, 67)] {
+            let period = detect_cycle_length(&buffer[start + 2_400..start + 9_600]).unwrap();
+
+            assert!((48_000.0 / period / note_to_frequency(note) - 1.0).abs() < 0.005);
+        }
+    }
+}
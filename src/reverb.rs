@@ -0,0 +1,275 @@
+use crate::{
+    effects::Effect,
+    smoothing::{Ramp, Smoothed},
+};
+
+///
+/// Freeverb's comb and allpass lengths, in samples at 44.1 kHz.
+///
+const COMB_TUNING: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNING: [usize; 4] = [556, 441, 341, 225];
+
+///
+/// Extra samples on every right-hand filter, which decorrelates the sides.
+///
+const STEREO_SPREAD: usize = 23;
+
+const TUNING_RATE: f64 = 44_100.0;
+const INPUT_GAIN: f64 = 0.015;
+const WET_GAIN: f64 = 3.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ReverbSettings {
+    /// From `0.0` to `1.0`, setting how long the tail rings.
+    pub room_size: f64,
+    /// From `0.0` to `1.0`, how fast high frequencies die away.
+    pub damping: f64,
+    /// From `0.0` (mono) to `1.0` (fully decorrelated sides).
+    pub width: f64,
+}
+
+impl Default for ReverbSettings {
+    fn default() -> Self {
+        Self {
+            room_size: 0.5,
+            damping: 0.5,
+            width: 1.0,
+        }
+    }
+}
+
+///
+/// A feedback comb with a one-pole low-pass in its loop.
+///
+#[derive(Clone, Debug)]
+struct Comb {
+    buffer: Vec<f64>,
+    position: usize,
+    filtered: f64,
+}
+
+#[derive(Clone, Debug)]
+struct Allpass {
+    buffer: Vec<f64>,
+    position: usize,
+}
+
+///
+/// Schroeder-Moorer reverb as tuned in Jezar's Freeverb: eight parallel
+/// damped combs into four series allpasses, per side.
+///
+#[derive(Clone, Debug)]
+pub struct Reverb {
+    pub settings: ReverbSettings,
+    combs: [Vec<Comb>; 2],
+    allpasses: [Vec<Allpass>; 2],
+    /// Comb feedback, damping and width, gliding so that turning the room
+    /// or damping mid-tail doesn't step the decay.
+    smoothed: [Smoothed; 3],
+}
+
+impl Comb {
+    fn new(length: usize) -> Self {
+        Self {
+            buffer: vec![0.0; length.max(1)],
+            position: 0,
+            filtered: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f64, feedback: f64, damping: f64) -> f64 {
+        let output = self.buffer[self.position];
+
+        self.filtered = output * (1.0 - damping) + self.filtered * damping;
+        self.buffer[self.position] = input + self.filtered * feedback;
+        self.position = (self.position + 1) % self.buffer.len();
+
+        output
+    }
+
+    fn clear(&mut self) {
+        self.buffer.fill(0.0);
+        self.filtered = 0.0;
+    }
+}
+
+impl Allpass {
+    fn new(length: usize) -> Self {
+        Self {
+            buffer: vec![0.0; length.max(1)],
+            position: 0,
+        }
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let delayed = self.buffer[self.position];
+
+        self.buffer[self.position] = input + delayed * 0.5;
+        self.position = (self.position + 1) % self.buffer.len();
+
+        delayed - input
+    }
+
+    fn clear(&mut self) {
+        self.buffer.fill(0.0);
+    }
+}
+
+impl Reverb {
+    pub fn new(settings: ReverbSettings, sample_rate: f64) -> Self {
+        let scaled = |length: usize, side: usize| {
+            ((length + side * STEREO_SPREAD) as f64 * sample_rate / TUNING_RATE).round() as usize
+        };
+
+        Self {
+            settings,
+            combs: std::array::from_fn(|side| {
+                COMB_TUNING
+                    .iter()
+                    .map(|length| Comb::new(scaled(*length, side)))
+                    .collect()
+            }),
+            allpasses: std::array::from_fn(|side| {
+                ALLPASS_TUNING
+                    .iter()
+                    .map(|length| Allpass::new(scaled(*length, side)))
+                    .collect()
+            }),
+            smoothed: Self::targets(settings).map(|value| Smoothed::new(value, sample_rate)),
+        }
+    }
+
+    ///
+    /// How changes to the settings are smoothed.
+    ///
+    pub fn set_smoothing(&mut self, time: f64, ramp: Ramp) {
+        for smoothed in &mut self.smoothed {
+            smoothed.configure(time, ramp);
+        }
+    }
+
+    fn targets(settings: ReverbSettings) -> [f64; 3] {
+        [
+            0.7 + 0.28 * settings.room_size.clamp(0.0, 1.0),
+            0.4 * settings.damping.clamp(0.0, 1.0),
+            settings.width.clamp(0.0, 1.0),
+        ]
+    }
+}
+
+impl Effect for Reverb {
+    fn process(&mut self, block: &mut [[f64; 2]]) {
+        for (smoothed, target) in self.smoothed.iter_mut().zip(Self::targets(self.settings)) {
+            smoothed.set_target(target);
+        }
+
+        for frame in block.iter_mut() {
+            let [feedback, damping, width] = self.smoothed.each_mut().map(Smoothed::next_sample);
+            let (direct, crossed) = ((1.0 + width) / 2.0, (1.0 - width) / 2.0);
+            let input = (frame[0] + frame[1]) * INPUT_GAIN;
+
+            let [left, right] = std::array::from_fn(|side| {
+                let sum = self.combs[side]
+                    .iter_mut()
+                    .map(|comb| comb.process(input, feedback, damping))
+                    .sum::<f64>();
+
+                self.allpasses[side]
+                    .iter_mut()
+                    .fold(sum, |sample, allpass| allpass.process(sample))
+            });
+
+            *frame = [
+                (left * direct + right * crossed) * WET_GAIN,
+                (right * direct + left * crossed) * WET_GAIN,
+            ];
+        }
+    }
+
+    fn reset(&mut self) {
+        self.combs.iter_mut().flatten().for_each(Comb::clear);
+        self.allpasses.iter_mut().flatten().for_each(Allpass::clear);
+
+        for (smoothed, target) in self.smoothed.iter_mut().zip(Self::targets(self.settings)) {
+            smoothed.reset(target);
+        }
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        let [smoothed, ..] = &self.smoothed;
+        let (time, ramp) = (smoothed.time(), smoothed.ramp());
+
+        *self = Reverb::new(self.settings, sample_rate);
+        self.set_smoothing(time, ramp);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 48_000.0;
+
+    fn impulse_response(settings: ReverbSettings) -> Vec<[f64; 2]> {
+        let mut reverb = Reverb::new(settings, SAMPLE_RATE);
+        let mut block = vec![[0.0; 2]; 4 * SAMPLE_RATE as usize];
+
+        block[0] = [1.0; 2];
+        reverb.process(&mut block);
+        block
+    }
+
+    ///
+    /// Energy left after `seconds`, as a fraction of the whole tail's.
+    ///
+    fn remaining(response: &[[f64; 2]], seconds: f64) -> f64 {
+        let energy = |frames: &[[f64; 2]]| {
+            frames
+                .iter()
+                .map(|[left, right]| left * left + right * right)
+                .sum::<f64>()
+        };
+
+        energy(&response[(seconds * SAMPLE_RATE) as usize..]) / energy(response)
+    }
+
+    #[test]
+    fn bigger_rooms_ring_longer() {
+        let small = impulse_response(ReverbSettings {
+            room_size: 0.2,
+            ..ReverbSettings::default()
+        });
+        let large = impulse_response(ReverbSettings {
+            room_size: 0.9,
+            ..ReverbSettings::default()
+        });
+
+        assert!(remaining(&small, 1.0) < 1e-3);
+        assert!(remaining(&large, 1.0) > 1e-2);
+        assert!(small.iter().flatten().all(|sample| sample.is_finite()));
+        assert!(large[..SAMPLE_RATE as usize / 40]
+            .iter()
+            .flatten()
+            .all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn width_decorrelates_sides() {
+        let correlation = |response: Vec<[f64; 2]>| {
+            let product = response.iter().map(|[l, r]| l * r).sum::<f64>();
+            let left = response.iter().map(|[l, _]| l * l).sum::<f64>();
+            let right = response.iter().map(|[_, r]| r * r).sum::<f64>();
+
+            product / (left * right).sqrt()
+        };
+
+        let mono = impulse_response(ReverbSettings {
+            width: 0.0,
+            ..ReverbSettings::default()
+        });
+        let wide = impulse_response(ReverbSettings::default());
+
+        assert!((correlation(mono) - 1.0).abs() < 1e-9);
+        assert!(correlation(wide).abs() < 0.2);
+    }
+}
@@ -0,0 +1,246 @@
+use crate::{
+    clock::{Clock, Gates, NoteEvent},
+    waveform::WhiteNoise,
+};
+
+///
+/// Steps are preallocated up to this limit so that editing the pattern on
+/// the audio thread never allocates.
+///
+pub const MAX_STEPS: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Step {
+    pub note: u8,
+    pub velocity: u8,
+    /// Fraction of the step the note is held for, up to `1.0`.
+    pub gate: f64,
+    /// Chance of the step playing each time round, from `0.0` to `1.0`.
+    pub probability: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SequencerSettings {
+    pub running: bool,
+    /// Steps before the pattern repeats, up to `MAX_STEPS`.
+    pub length: usize,
+    /// Steps per quarter-note beat, so `4.0` plays sixteenths.
+    pub division: f64,
+    /// Fraction of a step every second step is delayed by, up to `0.5`.
+    pub swing: f64,
+}
+
+///
+/// Plays a looping pattern of steps at the tempo, each a note or a rest.
+///
+#[derive(Clone, Debug)]
+pub struct Sequencer {
+    pub settings: SequencerSettings,
+    steps: [Option<Step>; MAX_STEPS],
+    clock: Clock,
+    gates: Gates,
+    random: WhiteNoise,
+}
+
+impl Default for Step {
+    fn default() -> Self {
+        Self {
+            note: 60,
+            velocity: 100,
+            gate: 0.5,
+            probability: 1.0,
+        }
+    }
+}
+
+impl Default for SequencerSettings {
+    fn default() -> Self {
+        Self {
+            running: false,
+            length: 16,
+            division: 4.0,
+            swing: 0.0,
+        }
+    }
+}
+
+impl Sequencer {
+    pub fn new(settings: SequencerSettings, sample_rate: f64) -> Self {
+        Self {
+            settings,
+            steps: [None; MAX_STEPS],
+            clock: Clock::new(sample_rate),
+            gates: Gates::new(),
+            random: WhiteNoise::default(),
+        }
+    }
+
+    pub fn steps(&self) -> &[Option<Step>] {
+        &self.steps[..self.settings.length.clamp(1, MAX_STEPS)]
+    }
+
+    ///
+    /// Sets the step at `index`, or a rest for `None`. Indices past
+    /// `MAX_STEPS` are ignored.
+    ///
+    pub fn set_step(&mut self, index: usize, step: Option<Step>) {
+        if let Some(slot) = self.steps.get_mut(index) {
+            *slot = step;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.steps = [None; MAX_STEPS];
+    }
+
+    pub fn set_tempo(&mut self, tempo: f64) {
+        self.clock.set_tempo(tempo);
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.clock.set_sample_rate(sample_rate);
+    }
+
+    ///
+    /// Moves on a sample, passing any notes starting or ending on it to
+    /// `play`. Stopping releases whatever is still held, and starting again
+    /// begins from the first step.
+    ///
+    pub fn tick(&mut self, play: &mut impl FnMut(NoteEvent)) {
+        let SequencerSettings {
+            running,
+            length,
+            division,
+            swing,
+        } = self.settings;
+
+        if !running {
+            self.gates.close_all(play);
+            self.clock.reset();
+            self.random = WhiteNoise::default();
+            return;
+        }
+
+        self.gates.tick(play);
+
+        let Some(step) = self.clock.tick(division, swing) else {
+            return;
+        };
+
+        let Some(step) = self.steps[step as usize % length.clamp(1, MAX_STEPS)] else {
+            return;
+        };
+
+        // @note: Drawn for every step played, so each run of a pattern comes out the same
+        let chance = (self.random.next_sample() + 1.0) / 2.0;
+
+        if chance < step.probability {
+            let samples = step.gate.clamp(0.0, 1.0) * self.clock.step_length(division);
+
+            self.gates
+                .open(step.note, step.velocity, samples.round() as usize, play);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///
+    /// Every event over `samples`, with the sample it landed on.
+    ///
+    fn run(sequencer: &mut Sequencer, samples: usize) -> Vec<(usize, NoteEvent)> {
+        let mut events = Vec::new();
+
+        for n in 0..samples {
+            sequencer.tick(&mut |event| events.push((n, event)));
+        }
+
+        events
+    }
+
+    fn sequencer(length: usize) -> Sequencer {
+        Sequencer::new(
+            SequencerSettings {
+                running: true,
+                length,
+                ..SequencerSettings::default()
+            },
+            1_000.0,
+        )
+    }
+
+    #[test]
+    fn loops_pattern_with_gates() {
+        let mut sequencer = sequencer(3);
+
+        sequencer.set_step(0, Some(Step::default()));
+        sequencer.set_step(
+            2,
+            Some(Step {
+                note: 67,
+                gate: 1.0,
+                ..Step::default()
+            }),
+        );
+
+        // Sixteenths at 120 BPM are 125 samples at 1 kHz.
+        let events = run(&mut sequencer, 400);
+        let on = |note| NoteEvent::On {
+            note,
+            velocity: 100,
+        };
+
+        assert!(
+            events
+                == [
+                    (0, on(60)),
+                    (63, NoteEvent::Off { note: 60 }),
+                    (250, on(67)),
+                    (375, NoteEvent::Off { note: 67 }),
+                    (375, on(60)),
+                ]
+        );
+    }
+
+    #[test]
+    fn stopping_releases_notes() {
+        let mut sequencer = sequencer(1);
+
+        sequencer.set_step(0, Some(Step::default()));
+        run(&mut sequencer, 10);
+        sequencer.settings.running = false;
+
+        assert!(run(&mut sequencer, 10) == [(0, NoteEvent::Off { note: 60 })]);
+
+        sequencer.settings.running = true;
+        assert!(
+            run(&mut sequencer, 1)[0].1
+                == NoteEvent::On {
+                    note: 60,
+                    velocity: 100
+                }
+        );
+    }
+
+    #[test]
+    fn skips_steps_by_probability() {
+        let mut sequencer = sequencer(1);
+
+        sequencer.set_step(
+            0,
+            Some(Step {
+                probability: 0.25,
+                ..Step::default()
+            }),
+        );
+
+        let played = run(&mut sequencer, 125 * 1_000)
+            .iter()
+            .filter(|(_, event)| matches!(event, NoteEvent::On { .. }))
+            .count();
+
+        assert!(played.abs_diff(250) < 40);
+    }
+}
@@ -0,0 +1,219 @@
+///
+/// Seconds a parameter takes to settle on a new value, short enough to feel
+/// immediate but long enough not to click.
+///
+pub const DEFAULT_TIME: f64 = 0.01;
+
+///
+/// Below this distance from its target a ramp snaps onto it, so that a
+/// settled parameter holds the exact value it was given.
+///
+const SETTLED: f64 = 1e-9;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Ramp {
+    /// An exponential approach, moving fast at first and within 0.1% of
+    /// the target after the smoothing time.
+    #[default]
+    OnePole,
+    /// A straight line, reaching the target after exactly the smoothing time.
+    Linear,
+}
+
+///
+/// A parameter that glides to each new target instead of jumping, one call
+/// to `next_sample` per sample. Nothing allocates, so targets can be set
+/// from the audio thread.
+///
+#[derive(Clone, Debug)]
+pub struct Smoothed {
+    ramp: Ramp,
+    time: f64,
+    sample_rate: f64,
+    value: f64,
+    target: f64,
+    /// Per-sample feedback of the one-pole.
+    coefficient: f64,
+    /// Per-sample increment of the linear ramp.
+    step: f64,
+    /// Samples left on the linear ramp.
+    remaining: usize,
+}
+
+impl Smoothed {
+    pub fn new(value: f64, sample_rate: f64) -> Self {
+        let mut smoothed = Self {
+            ramp: Ramp::default(),
+            time: DEFAULT_TIME,
+            sample_rate,
+            value,
+            target: value,
+            coefficient: 0.0,
+            step: 0.0,
+            remaining: 0,
+        };
+
+        smoothed.configure(DEFAULT_TIME, Ramp::default());
+        smoothed
+    }
+
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    pub fn target(&self) -> f64 {
+        self.target
+    }
+
+    pub fn is_settled(&self) -> bool {
+        self.value == self.target
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn ramp(&self) -> Ramp {
+        self.ramp
+    }
+
+    ///
+    /// Changes the smoothing time (in seconds) and curve. A ramp under way
+    /// carries on from where it is with the new ones.
+    ///
+    pub fn configure(&mut self, time: f64, ramp: Ramp) {
+        self.time = time.max(0.0);
+        self.ramp = ramp;
+
+        // 0.1% is ln(1000) time constants away.
+        let samples = self.time * self.sample_rate / 1_000.0_f64.ln();
+
+        self.coefficient = (-1.0 / samples).exp();
+        self.restart();
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.configure(self.time, self.ramp);
+    }
+
+    ///
+    /// Starts gliding from the current value. Setting the target already
+    /// being approached leaves the ramp alone, so owners can pass their
+    /// setting in every sample.
+    ///
+    pub fn set_target(&mut self, target: f64) {
+        if target != self.target {
+            self.target = target;
+            self.restart();
+        }
+    }
+
+    ///
+    /// Jumps straight to `value`, for when nothing is sounding.
+    ///
+    pub fn reset(&mut self, value: f64) {
+        self.value = value;
+        self.target = value;
+        self.remaining = 0;
+    }
+
+    pub fn next_sample(&mut self) -> f64 {
+        if self.is_settled() {
+            return self.value;
+        }
+
+        match self.ramp {
+            Ramp::OnePole => {
+                self.value = self.target + (self.value - self.target) * self.coefficient;
+            }
+            Ramp::Linear => {
+                self.remaining = self.remaining.saturating_sub(1);
+                self.value += self.step;
+
+                if self.remaining == 0 {
+                    self.value = self.target;
+                }
+            }
+        }
+
+        if (self.value - self.target).abs() < SETTLED {
+            self.value = self.target;
+        }
+
+        self.value
+    }
+
+    fn restart(&mut self) {
+        self.remaining = ((self.time * self.sample_rate).round() as usize).max(1);
+        self.step = (self.target - self.value) / self.remaining as f64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 1_000.0;
+
+    fn ramp(ramp: Ramp, time: f64) -> Smoothed {
+        let mut smoothed = Smoothed::new(0.0, SAMPLE_RATE);
+
+        smoothed.configure(time, ramp);
+        smoothed.set_target(1.0);
+        smoothed
+    }
+
+    #[test]
+    fn one_pole_settles_within_time() {
+        let mut smoothed = ramp(Ramp::OnePole, 0.1);
+        let samples: Vec<f64> = (0..100).map(|_| smoothed.next_sample()).collect();
+
+        assert!(samples.windows(2).all(|w| w[1] > w[0]));
+        assert!((1.0 - samples[99] - 1e-3).abs() < 1e-9);
+
+        (0..1_000).for_each(|_| {
+            smoothed.next_sample();
+        });
+        assert!(smoothed.is_settled() && smoothed.value() == 1.0);
+    }
+
+    #[test]
+    fn linear_reaches_target_exactly_on_time() {
+        let mut smoothed = ramp(Ramp::Linear, 0.1);
+        let samples: Vec<f64> = (0..100).map(|_| smoothed.next_sample()).collect();
+
+        assert!(samples
+            .windows(2)
+            .all(|w| (w[1] - w[0] - 0.01).abs() < 1e-12));
+        assert!(samples[99] == 1.0 && smoothed.is_settled());
+    }
+
+    #[test]
+    fn retargets_from_current_value() {
+        for kind in [Ramp::OnePole, Ramp::Linear] {
+            let mut smoothed = ramp(kind, 0.1);
+
+            (0..50).for_each(|_| {
+                smoothed.next_sample();
+            });
+
+            let halfway = smoothed.value();
+
+            smoothed.set_target(-1.0);
+
+            // Heads back down from where it was, without jumping.
+            assert!(smoothed.next_sample() < halfway);
+            assert!(halfway - smoothed.value() < 0.2);
+        }
+    }
+
+    #[test]
+    fn zero_time_jumps() {
+        for kind in [Ramp::OnePole, Ramp::Linear] {
+            let mut smoothed = ramp(kind, 0.0);
+
+            assert!(smoothed.next_sample() == 1.0);
+        }
+    }
+}
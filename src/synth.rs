@@ -0,0 +1,271 @@
+use crate::{
+    arpeggiator::{ArpSettings, Arpeggiator},
+    channel_layout,
+    clock::NoteEvent,
+    effects::EffectChain,
+    engine::Command,
+    envelope::{Adsr, Envelope},
+    filter::FilterSettings,
+    lfo::{Lfo, LfoSettings},
+    master::MasterBus,
+    midi::{self, MidiMessage},
+    modulation::{ModSources, LFO_COUNT},
+    sequencer::{Sequencer, SequencerSettings},
+    smoothing::{Ramp, Smoothed},
+    voice::VoiceAllocator,
+    waveform,
+    wavetable::{Interpolation, MipmappedWavetable},
+};
+
+pub const DEFAULT_POLYPHONY: usize = 8;
+
+///
+/// The instrument the engine plays, shared by the live `cpal` stream and the
+/// offline renderer so both produce identical audio.
+///
+pub struct Synth {
+    pub voices: VoiceAllocator,
+    pub lfos: [Lfo; LFO_COUNT],
+    pub effects: EffectChain,
+    pub master: MasterBus,
+    pub sequencer: Sequencer,
+    /// Takes over the keys while enabled, playing them as a pattern.
+    pub arpeggiator: Arpeggiator,
+    pub gain: Smoothed,
+    /// Stereo width, from `0.0` (mono) to `2.0`.
+    pub width: Smoothed,
+    /// Last MIDI mod wheel position, `0.0..=1.0`.
+    pub mod_wheel: Smoothed,
+    /// Last MIDI pitch bend, `-1.0..=1.0`.
+    pub pitch_bend: Smoothed,
+    sample_rate: f64,
+}
+
+impl Synth {
+    pub fn new(sample_rate: f64) -> Self {
+        let saw_table = MipmappedWavetable::new(2048, waveform::sawtooth);
+
+        Self {
+            voices: VoiceAllocator::new(
+                saw_table.iter(0.0, sample_rate, Interpolation::CubicHermite),
+                Envelope::new(Adsr::default(), sample_rate),
+                FilterSettings::default(),
+                DEFAULT_POLYPHONY,
+            ),
+            lfos: std::array::from_fn(|_| Lfo::new(LfoSettings::default(), sample_rate)),
+            effects: EffectChain::new(sample_rate),
+            master: MasterBus::new(sample_rate),
+            sequencer: Sequencer::new(SequencerSettings::default(), sample_rate),
+            arpeggiator: Arpeggiator::new(ArpSettings::default(), sample_rate),
+            gain: Smoothed::new(0.1, sample_rate),
+            width: Smoothed::new(1.0, sample_rate),
+            mod_wheel: Smoothed::new(0.0, sample_rate),
+            pitch_bend: Smoothed::new(0.0, sample_rate),
+            sample_rate,
+        }
+    }
+
+    pub fn note_on(&mut self, note: u8, velocity: u8) {
+        match self.arpeggiator.settings.enabled {
+            true => self.arpeggiator.note_on(note, velocity),
+            false => self.voices.note_on(note, velocity),
+        }
+    }
+
+    pub fn note_off(&mut self, note: u8) {
+        // @note: Both, as the arpeggiator may have been switched while the key was down
+        self.arpeggiator.note_off(note);
+        self.voices.note_off(note);
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    ///
+    /// Follows the output device to a new rate without losing any settings.
+    ///
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+        self.voices.set_sample_rate(sample_rate);
+        self.effects.set_sample_rate(sample_rate);
+        self.master.set_sample_rate(sample_rate);
+        self.sequencer.set_sample_rate(sample_rate);
+        self.arpeggiator.set_sample_rate(sample_rate);
+
+        for lfo in &mut self.lfos {
+            lfo.set_sample_rate(sample_rate);
+        }
+
+        for smoothed in self.smoothed() {
+            smoothed.set_sample_rate(sample_rate);
+        }
+    }
+
+    ///
+    /// How every continuous parameter glides to a new setting, over `time`
+    /// seconds.
+    ///
+    pub fn set_smoothing(&mut self, time: f64, ramp: Ramp) {
+        self.voices.set_smoothing(time, ramp);
+        self.effects.set_smoothing(time, ramp);
+
+        for lfo in &mut self.lfos {
+            lfo.set_smoothing(time, ramp);
+        }
+
+        for smoothed in self.smoothed() {
+            smoothed.configure(time, ramp);
+        }
+    }
+
+    ///
+    /// Responds to messages on every channel. Controllers other than the mod
+    /// wheel, pan, the sustain pedal and the channel mode messages are
+    /// ignored.
+    ///
+    pub fn handle_midi(&mut self, message: MidiMessage) {
+        match message {
+            MidiMessage::NoteOn { note, velocity, .. } => self.note_on(note, velocity),
+            MidiMessage::NoteOff { note, .. } => self.note_off(note),
+            MidiMessage::PitchBend { value, .. } => {
+                self.pitch_bend
+                    .set_target(((value as f64 - 8192.0) / 8192.0).max(-1.0));
+            }
+            MidiMessage::ControlChange {
+                controller, value, ..
+            } => match controller {
+                midi::MOD_WHEEL => self.mod_wheel.set_target(value as f64 / 127.0),
+                midi::PAN => self
+                    .voices
+                    .pan
+                    .set_target(((value as f64 - 64.0) / 63.0).max(-1.0)),
+                midi::SUSTAIN_PEDAL => self.voices.set_sustain(value >= 64),
+                midi::ALL_SOUND_OFF => {
+                    self.arpeggiator.release_all();
+                    self.voices.all_sound_off();
+                }
+                midi::ALL_NOTES_OFF => {
+                    self.arpeggiator.release_all();
+                    self.voices.all_notes_off();
+                }
+                _ => {}
+            },
+        }
+    }
+
+    ///
+    /// Beats per minute for tempo-synced LFOs, the delay, the sequencer and
+    /// the arpeggiator.
+    ///
+    pub fn set_tempo(&mut self, tempo: f64) {
+        for lfo in &mut self.lfos {
+            lfo.set_tempo(tempo);
+        }
+
+        self.effects.set_tempo(tempo);
+        self.sequencer.set_tempo(tempo);
+        self.arpeggiator.set_tempo(tempo);
+    }
+
+    ///
+    /// Applies an engine command straight away, as the engine does at the
+    /// start of a block.
+    ///
+    pub fn apply(&mut self, command: Command) {
+        match command {
+            Command::NoteOn { note, velocity } => self.note_on(note, velocity),
+            Command::NoteOff { note } => self.note_off(note),
+            Command::Midi(message) => self.handle_midi(message),
+            Command::SetGain(gain) => self.gain.set_target(gain),
+            // @note: Dither applies after the synth, so the engine handles it
+            Command::SetDither(_) => {}
+            Command::SetPan(pan) => self.voices.pan.set_target(pan),
+            Command::SetWidth(width) => self.width.set_target(width),
+            Command::SetSmoothing { time, ramp } => self.set_smoothing(time, ramp),
+            Command::SetPolyphony(polyphony) => self.voices.set_polyphony(polyphony),
+            Command::SetEnvelope(settings) => self.voices.set_envelope(settings),
+            Command::SetFilter(settings) => self.voices.set_filter(settings),
+            Command::SetLfo { index, settings } => {
+                if let Some(lfo) = self.lfos.get_mut(index) {
+                    lfo.settings = settings;
+                }
+            }
+            Command::SetTempo(tempo) => self.set_tempo(tempo),
+            // @note: A full matrix drops the route, as the audio thread has no one to tell
+            Command::AddRoute(route) => {
+                let _ = self.voices.matrix.add(route);
+            }
+            Command::ClearRoutes => self.voices.matrix.clear(),
+            Command::SetChorus(settings) => self.effects.chorus.settings = settings,
+            Command::SetDelay(settings) => self.effects.delay.settings = settings,
+            Command::SetReverb(settings) => self.effects.reverb.settings = settings,
+            Command::SetEffect { effect, settings } => {
+                self.effects.set_slot(effect, settings);
+            }
+            // @note: Handles check the order before sending, see `effects::check_order`
+            Command::SetEffectOrder(order) => {
+                let _ = self.effects.set_order(order);
+            }
+            Command::SetLimiter(settings) => self.master.limiter.settings = settings,
+            Command::SetSequencer(settings) => self.sequencer.settings = settings,
+            Command::SetStep { index, step } => self.sequencer.set_step(index, step),
+            Command::ClearSteps => self.sequencer.clear(),
+            Command::SetArpeggiator(settings) => self.arpeggiator.settings = settings,
+            Command::SetUnison(settings) => self.voices.set_unison(settings),
+            Command::SetNoteFrequency { note, frequency } => {
+                self.voices.set_note_frequency(note, frequency);
+            }
+        }
+    }
+
+    fn next_sample(&mut self) -> [f64; 2] {
+        let voices = &mut self.voices;
+        let mut play = |event| match event {
+            NoteEvent::On { note, velocity } => voices.note_on(note, velocity),
+            NoteEvent::Off { note } => voices.note_off(note),
+        };
+
+        self.sequencer.tick(&mut play);
+        self.arpeggiator.tick(&mut play);
+
+        let sources = ModSources {
+            lfos: std::array::from_fn(|index| self.lfos[index].next_sample()),
+            mod_wheel: self.mod_wheel.next_sample(),
+            pitch_bend: self.pitch_bend.next_sample(),
+            ..ModSources::default()
+        };
+
+        let gain = self.gain.next_sample();
+
+        channel_layout::widen(self.voices.next_sample(&sources), self.width.next_sample())
+            .map(|sample| sample * gain)
+    }
+
+    fn smoothed(&mut self) -> [&mut Smoothed; 4] {
+        [
+            &mut self.gain,
+            &mut self.width,
+            &mut self.mod_wheel,
+            &mut self.pitch_bend,
+        ]
+    }
+
+    ///
+    /// Plays the voices into `output` and runs it through the effects and
+    /// the master bus. Should anything turn non-finite, every voice and
+    /// effect is cut so that the bus can come back from its mute.
+    ///
+    pub fn render(&mut self, output: &mut [[f64; 2]]) {
+        for sample in output.iter_mut() {
+            *sample = self.next_sample();
+        }
+
+        self.effects.process(output);
+
+        if self.master.process(output) {
+            self.voices.all_sound_off();
+            self.effects.reset();
+        }
+    }
+}
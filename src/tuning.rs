@@ -0,0 +1,467 @@
+use std::path::Path;
+
+pub const NOTE_COUNT: usize = 128;
+
+///
+/// Ratios of five-limit just intonation over the twelve keys of an octave.
+///
+const JUST_RATIOS: [(u32, u32); 12] = [
+    (16, 15),
+    (9, 8),
+    (6, 5),
+    (5, 4),
+    (4, 3),
+    (45, 32),
+    (3, 2),
+    (8, 5),
+    (5, 3),
+    (9, 5),
+    (15, 8),
+    (2, 1),
+];
+
+///
+/// The pitches of a scale, as in a Scala `.scl` file.
+///
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Scale {
+    pub description: String,
+    /// Cents above the root of every degree but the root itself, the last
+    /// being the period the scale repeats at.
+    pub degrees: Vec<f64>,
+}
+
+///
+/// Which keys play which scale degrees, as in a Scala `.kbm` file.
+///
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct KeyboardMapping {
+    /// Keys outside `first_note..=last_note` are silent.
+    pub first_note: u8,
+    pub last_note: u8,
+    /// The key that plays the scale's root.
+    pub middle_note: u8,
+    pub reference_note: u8,
+    pub reference_frequency: f64,
+    /// The degree the mapping repeats at, or `0` for the scale's period.
+    /// Linear mappings always repeat at the period.
+    pub octave_degree: usize,
+    /// The degree each key plays, counting up from `middle_note` and
+    /// repeating. `None` leaves a key silent, and an empty map plays every
+    /// degree in turn.
+    pub keys: Vec<Option<usize>>,
+}
+
+///
+/// A scale laid out on the keyboard, giving every MIDI note its frequency.
+///
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Tuning {
+    pub scale: Scale,
+    pub mapping: KeyboardMapping,
+}
+
+impl Scale {
+    ///
+    /// `divisions` equal steps to the octave.
+    ///
+    pub fn equal(divisions: usize) -> Self {
+        Self {
+            description: format!("{divisions}-tone equal temperament"),
+            degrees: (1..=divisions)
+                .map(|degree| 1_200.0 * degree as f64 / divisions as f64)
+                .collect(),
+        }
+    }
+
+    pub fn just_intonation() -> Self {
+        Self {
+            description: "5-limit just intonation".to_string(),
+            degrees: JUST_RATIOS
+                .iter()
+                .map(|(numerator, denominator)| {
+                    ratio_to_cents(*numerator as f64 / *denominator as f64)
+                })
+                .collect(),
+        }
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        Self::parse(&read(path)?)
+    }
+
+    ///
+    /// Reads the text of a `.scl` file. Pitches with a `.` are in cents,
+    /// the rest are ratios such as `3/2`, or whole numbers.
+    ///
+    pub fn parse(text: &str) -> Result<Self, String> {
+        // @note: Only the description may be blank, so it is taken before blank lines are skipped
+        let mut lines = text.lines().filter(|line| !line.starts_with('!'));
+        let description = lines.next().ok_or("Scale has no description")?.trim();
+        let mut lines = lines.map(str::trim).filter(|line| !line.is_empty());
+
+        let count: usize = first_word(lines.next().ok_or("Scale has no note count")?)
+            .parse()
+            .map_err(|e| format!("Invalid note count: {e}"))?;
+
+        let degrees = lines
+            .take(count)
+            .map(|line| parse_pitch(first_word(line)))
+            .collect::<Result<Vec<f64>, String>>()?;
+
+        if degrees.len() != count {
+            return Err(format!(
+                "Scale lists {} of its {count} notes",
+                degrees.len()
+            ));
+        }
+
+        let scale = Self {
+            description: description.to_string(),
+            degrees,
+        };
+
+        scale.check()?;
+
+        Ok(scale)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        match self.degrees.last() {
+            Some(period) if *period > 0.0 => Ok(()),
+            Some(_) => Err("Scale must repeat at a rising interval".to_string()),
+            None => Err("Scale has no notes".to_string()),
+        }
+    }
+
+    ///
+    /// Cents above the root of `degree`, counting on through later periods
+    /// and back through earlier ones, or `None` for a scale with no notes.
+    ///
+    pub fn cents(&self, degree: i64) -> Option<f64> {
+        let count = self.degrees.len() as i64;
+        let period = *self.degrees.last()?;
+
+        let step = match degree.rem_euclid(count) {
+            0 => 0.0,
+            step => self.degrees[step as usize - 1],
+        };
+
+        Some(degree.div_euclid(count) as f64 * period + step)
+    }
+}
+
+///
+/// Every key plays the next degree, with A4 (note 69) at 440 Hz and the
+/// root on middle C.
+///
+impl Default for KeyboardMapping {
+    fn default() -> Self {
+        Self {
+            first_note: 0,
+            last_note: (NOTE_COUNT - 1) as u8,
+            middle_note: 60,
+            reference_note: 69,
+            reference_frequency: 440.0,
+            octave_degree: 0,
+            keys: Vec::new(),
+        }
+    }
+}
+
+impl KeyboardMapping {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        Self::parse(&read(path)?)
+    }
+
+    ///
+    /// Reads the text of a `.kbm` file, where `x` marks a silent key.
+    ///
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('!'))
+            .map(first_word);
+
+        let mut field = |name: &str| -> Result<&str, String> {
+            lines
+                .next()
+                .ok_or(format!("Keyboard mapping has no {name}"))
+        };
+
+        let size: usize = parse_field(field("map size")?, "map size")?;
+
+        if size > NOTE_COUNT {
+            return Err(format!(
+                "Keyboard mapping size {size} is over the {NOTE_COUNT} MIDI notes"
+            ));
+        }
+
+        let first_note = parse_field(field("first note")?, "first note")?;
+        let last_note = parse_field(field("last note")?, "last note")?;
+        let middle_note = parse_field(field("middle note")?, "middle note")?;
+        let reference_note = parse_field(field("reference note")?, "reference note")?;
+        let reference_frequency =
+            parse_field(field("reference frequency")?, "reference frequency")?;
+        let octave_degree = parse_field(field("octave degree")?, "octave degree")?;
+
+        let mut keys = lines
+            .take(size)
+            .map(|key| match key {
+                "x" => Ok(None),
+                degree => parse_field(degree, "key").map(Some),
+            })
+            .collect::<Result<Vec<Option<usize>>, String>>()?;
+
+        // Keys the file leaves off the end of the map are silent.
+        keys.resize(size, None);
+
+        Ok(Self {
+            first_note,
+            last_note,
+            middle_note,
+            reference_note,
+            reference_frequency,
+            octave_degree,
+            keys,
+        })
+    }
+}
+
+///
+/// Twelve-tone equal temperament with A4 (note 69) at 440 Hz.
+///
+impl Default for Tuning {
+    fn default() -> Self {
+        Self {
+            scale: Scale::equal(12),
+            mapping: KeyboardMapping::default(),
+        }
+    }
+}
+
+impl Tuning {
+    pub fn new(scale: Scale, mapping: KeyboardMapping) -> Result<Self, String> {
+        scale.check()?;
+
+        if mapping.reference_frequency.is_nan() || mapping.reference_frequency <= 0.0 {
+            return Err("Reference frequency must be above 0 Hz".to_string());
+        }
+
+        let tuning = Self { scale, mapping };
+
+        if tuning.key_cents(tuning.mapping.reference_note).is_none() {
+            return Err(format!(
+                "Reference note {} is not mapped",
+                tuning.mapping.reference_note
+            ));
+        }
+
+        Ok(tuning)
+    }
+
+    ///
+    /// Just intonation on `root`, keeping A4 at 440 Hz.
+    ///
+    pub fn just_intonation(root: u8) -> Self {
+        Self {
+            scale: Scale::just_intonation(),
+            mapping: KeyboardMapping {
+                middle_note: root,
+                ..KeyboardMapping::default()
+            },
+        }
+    }
+
+    ///
+    /// Reads a `.scl` scale, laid out by a `.kbm` mapping if there is one.
+    ///
+    pub fn read_scala<P: AsRef<Path>>(scale: P, mapping: Option<P>) -> Result<Self, String> {
+        let mapping = match mapping {
+            Some(path) => KeyboardMapping::read(path)?,
+            None => KeyboardMapping::default(),
+        };
+
+        Self::new(Scale::read(scale)?, mapping)
+    }
+
+    ///
+    /// Cents of `note` above the key playing the root, or `None` for a
+    /// silent key.
+    ///
+    pub fn key_cents(&self, note: u8) -> Option<f64> {
+        let mapping = &self.mapping;
+
+        if !(mapping.first_note..=mapping.last_note).contains(&note) {
+            return None;
+        }
+
+        let offset = note as i64 - mapping.middle_note as i64;
+
+        if mapping.keys.is_empty() {
+            return self.scale.cents(offset);
+        }
+
+        let size = mapping.keys.len() as i64;
+        let degree = mapping.keys[offset.rem_euclid(size) as usize]?;
+        let octave = match mapping.octave_degree {
+            0 => self.scale.degrees.len(),
+            degree => degree,
+        };
+
+        Some(
+            offset.div_euclid(size) as f64 * self.scale.cents(octave as i64)?
+                + self.scale.cents(degree as i64)?,
+        )
+    }
+
+    ///
+    /// The frequency of `note` bent by `cents`, or `None` for a silent key.
+    ///
+    pub fn frequency(&self, note: u8, cents: f64) -> Option<f64> {
+        let reference = self.key_cents(self.mapping.reference_note).unwrap_or(0.0);
+        let cents = self.key_cents(note)? - reference + cents;
+
+        Some(self.mapping.reference_frequency * 2.0_f64.powf(cents / 1_200.0))
+    }
+
+    ///
+    /// Every MIDI note's frequency, as the voices look them up.
+    ///
+    pub fn frequencies(&self) -> [Option<f64>; NOTE_COUNT] {
+        std::array::from_fn(|note| self.frequency(note as u8, 0.0))
+    }
+}
+
+pub fn ratio_to_cents(ratio: f64) -> f64 {
+    1_200.0 * ratio.log2()
+}
+
+fn read<P: AsRef<Path>>(path: P) -> Result<String, String> {
+    std::fs::read_to_string(&path)
+        .map_err(|e| format!("Could not read '{}': {e}", path.as_ref().display()))
+}
+
+///
+/// Scala files allow anything after the value on a line, as a comment.
+///
+fn first_word(line: &str) -> &str {
+    line.split_whitespace().next().unwrap_or("")
+}
+
+fn parse_field<T: std::str::FromStr>(value: &str, name: &str) -> Result<T, String>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|e| format!("Invalid {name} '{value}': {e}"))
+}
+
+fn parse_pitch(pitch: &str) -> Result<f64, String> {
+    if pitch.contains('.') {
+        return parse_field(pitch, "pitch");
+    }
+
+    let (numerator, denominator) = pitch.split_once('/').unwrap_or((pitch, "1"));
+    let numerator: f64 = parse_field::<u64>(numerator, "ratio")? as f64;
+    let denominator: f64 = parse_field::<u64>(denominator, "ratio")? as f64;
+
+    if numerator == 0.0 || denominator == 0.0 {
+        return Err(format!("Invalid ratio '{pitch}'"));
+    }
+
+    Ok(ratio_to_cents(numerator / denominator))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::voice::note_to_frequency;
+
+    use super::*;
+
+    #[test]
+    fn equal_temperament_matches_voices() {
+        let tuning = Tuning::default();
+
+        assert!((0..=127).all(|note| tuning.frequency(note, 0.0) == Some(note_to_frequency(note))));
+        assert!(
+            (tuning.frequency(69, 50.0).unwrap() - 440.0 * 2.0_f64.powf(1.0 / 24.0)).abs() < 1e-9
+        );
+        assert!(
+            (Tuning::new(Scale::equal(19), KeyboardMapping::default())
+                .unwrap()
+                .frequency(70, 0.0)
+                .unwrap()
+                - 440.0 * 2.0_f64.powf(1.0 / 19.0))
+            .abs()
+                < 1e-9
+        );
+    }
+
+    #[test]
+    fn just_intonation_keeps_pure_ratios() {
+        let tuning = Tuning::just_intonation(60);
+        let c = tuning.frequency(60, 0.0).unwrap();
+
+        assert!((c - 264.0).abs() < 1e-9);
+        assert!((tuning.frequency(64, 0.0).unwrap() / c - 5.0 / 4.0).abs() < 1e-12);
+        assert!((tuning.frequency(67, 0.0).unwrap() / c - 3.0 / 2.0).abs() < 1e-12);
+        assert!((tuning.frequency(72 + 7, 0.0).unwrap() / c - 3.0).abs() < 1e-12);
+        assert!((tuning.frequency(60 - 12, 0.0).unwrap() / c - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn reads_scala_files() {
+        let scale = Scale::parse(
+            "! pentatonic.scl\n\
+             !\n\
+             Pentatonic in ratios and cents\n \
+             5\n\
+             !\n \
+             9/8\n \
+             5/4 a major third\n \
+             700.0\n \
+             5/3\n \
+             2\n",
+        )
+        .unwrap();
+
+        assert!(scale.description == "Pentatonic in ratios and cents");
+        assert!(scale.degrees.len() == 5);
+        assert!((scale.degrees[2] - 700.0).abs() < 1e-12);
+        assert!((scale.degrees[4] - 1_200.0).abs() < 1e-12);
+
+        // Five white keys from C, with the black keys silent and A at 440 Hz.
+        let mapping = KeyboardMapping::parse(
+            "! white keys\n\
+             12\n0\n127\n60\n69\n440.0\n5\n\
+             0\nx\n1\nx\n2\nx\nx\n3\nx\n4\nx\n",
+        )
+        .unwrap();
+
+        assert!(mapping.keys.len() == 12);
+        assert!(mapping.keys[11].is_none());
+
+        let tuning = Tuning::new(scale, mapping).unwrap();
+        let c = tuning.frequency(60, 0.0).unwrap();
+
+        assert!((c - 264.0).abs() < 1e-9);
+        assert!((tuning.frequency(62, 0.0).unwrap() / c - 9.0 / 8.0).abs() < 1e-12);
+        assert!((tuning.frequency(72 + 9, 0.0).unwrap() - 880.0).abs() < 1e-9);
+        assert!(tuning.frequency(61, 0.0).is_none());
+
+        assert!(Scale::parse("Bad\n2\n100.0\n").is_err());
+        assert!(Scale::parse("Bad\n1\n0/1\n").is_err());
+        assert!(KeyboardMapping::parse("100000000\n0\n127\n60\n69\n440.0\n0\n").is_err());
+
+        let empty = Scale {
+            description: String::new(),
+            degrees: Vec::new(),
+        };
+
+        assert!(empty.cents(3).is_none());
+        assert!(Tuning::new(empty, KeyboardMapping::default()).is_err());
+    }
+}
@@ -0,0 +1,439 @@
+use crate::{
+    channel_layout,
+    oscillator::Oscillator,
+    smoothing::{Ramp, Smoothed},
+    waveform::WhiteNoise,
+};
+
+///
+/// Copies are preallocated up to this limit so that changing the unison
+/// count on the audio thread never allocates.
+///
+pub const MAX_UNISON: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct UnisonSettings {
+    /// Copies stacked on every note, from `1` to `MAX_UNISON`.
+    pub count: usize,
+    /// Cents the outermost copies are tuned away from the note.
+    pub detune: f64,
+    /// Shapes the detune across the copies: `1.0` spaces them evenly, and
+    /// higher values bunch them towards the note.
+    pub curve: f64,
+    /// How far the outermost copies are panned, from `0.0` (all centred) to
+    /// `1.0` (hard left and right).
+    pub spread: f64,
+    /// Starts each note with the copies at random phases rather than in
+    /// step, so that stacks don't all begin with the same sweep.
+    pub random_phase: bool,
+    /// From `0.0` (only the centre copies) through `0.5` (all equal) to
+    /// `1.0` (only the side copies).
+    pub blend: f64,
+}
+
+///
+/// Stacks detuned copies of an oscillator across the stereo field.
+///
+#[derive(Clone)]
+pub struct Unison {
+    settings: UnisonSettings,
+    /// Always `MAX_UNISON` long, with only the first `count` playing.
+    oscillators: Vec<Box<dyn Oscillator + Send>>,
+    frequency: f64,
+    /// Each copy's frequency as a multiple of the note's.
+    ratios: [f64; MAX_UNISON],
+    gains: [[f64; 2]; MAX_UNISON],
+    random: WhiteNoise,
+    /// Detune, curve, spread and blend, gliding so that the stack retunes
+    /// and repans smoothly. The count and random phases are steps by nature
+    /// and apply at once.
+    smoothed: [Smoothed; 4],
+}
+
+impl Default for UnisonSettings {
+    fn default() -> Self {
+        Self {
+            count: 1,
+            detune: 20.0,
+            curve: 1.0,
+            spread: 1.0,
+            random_phase: true,
+            blend: 0.5,
+        }
+    }
+}
+
+impl Unison {
+    ///
+    /// Copies `oscillator` up to `MAX_UNISON` times, so it belongs on the
+    /// control side.
+    ///
+    pub fn new(oscillator: Box<dyn Oscillator + Send>, settings: UnisonSettings) -> Self {
+        let sample_rate = oscillator.sample_rate();
+        let mut unison = Self {
+            settings,
+            frequency: oscillator.frequency(),
+            oscillators: vec![oscillator; MAX_UNISON],
+            ratios: [1.0; MAX_UNISON],
+            gains: [[1.0; 2]; MAX_UNISON],
+            random: WhiteNoise::default(),
+            smoothed: Self::targets(settings).map(|value| Smoothed::new(value, sample_rate)),
+        };
+
+        unison.restack();
+        unison
+    }
+
+    ///
+    /// Reseeds the random phases, so that stacks cloned from one template
+    /// don't all start alike.
+    ///
+    pub fn set_seed(&mut self, seed: u32) {
+        self.random = WhiteNoise::new(seed);
+    }
+
+    pub fn settings(&self) -> &UnisonSettings {
+        &self.settings
+    }
+
+    ///
+    /// Retunes and repans the copies, keeping their phases.
+    ///
+    pub fn set_settings(&mut self, settings: UnisonSettings) {
+        self.settings = settings;
+
+        for (smoothed, target) in self.smoothed.iter_mut().zip(Self::targets(settings)) {
+            smoothed.set_target(target);
+        }
+
+        self.restack();
+    }
+
+    ///
+    /// How changes to the detune, curve, spread and blend are smoothed.
+    ///
+    pub fn set_smoothing(&mut self, time: f64, ramp: Ramp) {
+        for smoothed in &mut self.smoothed {
+            smoothed.configure(time, ramp);
+        }
+    }
+
+    fn targets(settings: UnisonSettings) -> [f64; 4] {
+        [
+            settings.detune,
+            settings.curve.max(0.0),
+            settings.spread,
+            settings.blend.clamp(0.0, 1.0),
+        ]
+    }
+
+    ///
+    /// Works out every copy's ratio and gains from where the smoothed
+    /// settings have got to.
+    ///
+    fn restack(&mut self) {
+        let count = self.count();
+        let [detune, curve, spread, blend] = self.smoothed.each_ref().map(Smoothed::value);
+        let stereo = self.is_stereo();
+
+        // Copies lie evenly from -1 (left, flat) to 1 (right, sharp), and
+        // the middle one or two count as the centre. A pair is all centre
+        // and all sides at once, so blending leaves it alone.
+        let offset = |index: usize| match count {
+            1 => 0.0,
+            _ => 2.0 * index as f64 / (count - 1) as f64 - 1.0,
+        };
+        let weight = |index: usize| match count {
+            1 | 2 => 1.0,
+            _ if index == count / 2 || index == (count - 1) / 2 => 1.0 - blend,
+            _ => blend,
+        };
+
+        // Detuned copies add up by power rather than amplitude.
+        let power: f64 = (0..count).map(|index| weight(index).powi(2)).sum();
+        let scale = match power > 0.0 {
+            true => power.sqrt().recip(),
+            false => 0.0,
+        };
+
+        for index in 0..count {
+            let offset = offset(index);
+            // A curve of `0.0` would otherwise raise the centre to `0^0 = 1`.
+            let cents = match offset == 0.0 {
+                true => 0.0,
+                false => detune * offset.signum() * offset.abs().powf(curve),
+            };
+            let gain = weight(index) * scale;
+
+            self.ratios[index] = 2.0_f64.powf(cents / 1_200.0);
+            self.gains[index] = match stereo {
+                true => channel_layout::pan_gains(spread * offset).map(|pan| pan * gain),
+                false => [gain; 2],
+            };
+        }
+
+        self.set_frequency(self.frequency);
+    }
+
+    pub fn count(&self) -> usize {
+        self.settings.count.clamp(1, MAX_UNISON)
+    }
+
+    ///
+    /// Whether the copies are spread apart, so that the two sides differ.
+    ///
+    pub fn is_stereo(&self) -> bool {
+        let spread = &self.smoothed[2];
+
+        self.count() > 1 && (spread.value() != 0.0 || spread.target() != 0.0)
+    }
+
+    ///
+    /// Gives every copy its own copy of `oscillator`. This allocates, so it
+    /// belongs on the control side.
+    ///
+    pub fn set_oscillator(&mut self, oscillator: &dyn Oscillator) {
+        for copy in &mut self.oscillators {
+            *copy = oscillator.clone_box();
+        }
+
+        self.set_frequency(self.frequency);
+    }
+
+    ///
+    /// Trades the copies for `oscillators`, leaving the old ones in their
+    /// place.
+    ///
+    pub fn swap_oscillators(&mut self, oscillators: &mut [Box<dyn Oscillator + Send>]) {
+        let sample_rate = self.sample_rate();
+
+        for (copy, oscillator) in self.oscillators.iter_mut().zip(oscillators) {
+            oscillator.set_sample_rate(sample_rate);
+            std::mem::swap(copy, oscillator);
+        }
+
+        self.set_frequency(self.frequency);
+    }
+
+    pub fn frequency(&self) -> f64 {
+        self.frequency
+    }
+
+    pub fn set_frequency(&mut self, frequency: f64) {
+        let count = self.count();
+
+        self.frequency = frequency;
+
+        for (oscillator, ratio) in self.oscillators[..count].iter_mut().zip(self.ratios) {
+            oscillator.set_frequency(frequency * ratio);
+        }
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.oscillators[0].sample_rate()
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        for oscillator in &mut self.oscillators {
+            oscillator.set_sample_rate(sample_rate);
+        }
+
+        for smoothed in &mut self.smoothed {
+            smoothed.set_sample_rate(sample_rate);
+        }
+    }
+
+    pub fn set_position(&mut self, position: f64) {
+        let count = self.count();
+
+        for oscillator in &mut self.oscillators[..count] {
+            oscillator.set_position(position);
+        }
+    }
+
+    ///
+    /// Restarts every copy for a new note.
+    ///
+    pub fn reset_phase(&mut self) {
+        let count = self.count();
+
+        for oscillator in &mut self.oscillators[..count] {
+            match self.settings.random_phase && count > 1 {
+                true => oscillator.sync((self.random.next_sample() + 1.0) / 2.0),
+                false => oscillator.reset_phase(),
+            }
+        }
+    }
+
+    pub fn next_sample(&mut self) -> [f64; 2] {
+        // @note: Restacking costs a powf per copy, so it only runs mid-glide
+        if !self.smoothed.iter().all(Smoothed::is_settled) {
+            self.smoothed.iter_mut().for_each(|smoothed| {
+                smoothed.next_sample();
+            });
+            self.restack();
+        }
+
+        let count = self.count();
+
+        self.oscillators[..count].iter_mut().zip(&self.gains).fold(
+            [0.0; 2],
+            |[left, right], (oscillator, [l, r])| {
+                let sample = oscillator.next_sample();
+
+                [left + sample * l, right + sample * r]
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        waveform,
+        wavetable::{Interpolation, MipmappedWavetable},
+    };
+
+    use super::*;
+
+    fn unison(settings: UnisonSettings) -> Unison {
+        let table = MipmappedWavetable::new(256, waveform::sine);
+
+        Unison::new(
+            Box::new(table.iter(0.0, 48_000.0, Interpolation::Linear)),
+            settings,
+        )
+    }
+
+    #[test]
+    fn single_copy_plays_oscillator_unchanged() {
+        let table = MipmappedWavetable::new(256, waveform::sine);
+        let mut plain = table.iter(0.0, 48_000.0, Interpolation::Linear);
+        let mut unison = unison(UnisonSettings::default());
+
+        plain.set_frequency(440.0);
+        unison.set_frequency(440.0);
+        unison.reset_phase();
+
+        assert!(!unison.is_stereo());
+        assert!((0..1_000).all(|_| unison.next_sample() == [plain.next_sample(); 2]));
+    }
+
+    #[test]
+    fn detunes_copies_along_the_curve() {
+        let mut unison = unison(UnisonSettings {
+            count: 5,
+            detune: 100.0,
+            curve: 2.0,
+            ..UnisonSettings::default()
+        });
+
+        unison.set_frequency(440.0);
+
+        let frequencies: Vec<f64> = unison.oscillators[..5]
+            .iter()
+            .map(|oscillator| oscillator.frequency())
+            .collect();
+        let expected =
+            [-100.0, -25.0, 0.0, 25.0, 100.0].map(|cents| 440.0 * 2.0_f64.powf(cents / 1_200.0));
+
+        for (frequency, expected) in frequencies.iter().zip(expected) {
+            assert!((frequency - expected).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn flat_curve_keeps_centre_in_tune() {
+        let mut unison = unison(UnisonSettings {
+            count: 3,
+            detune: 100.0,
+            curve: 0.0,
+            ..UnisonSettings::default()
+        });
+
+        unison.set_frequency(440.0);
+
+        let frequencies: Vec<f64> = unison.oscillators[..3]
+            .iter()
+            .map(|oscillator| oscillator.frequency())
+            .collect();
+
+        assert!(frequencies[1] == 440.0);
+        assert!((frequencies[0] - 440.0 * 2.0_f64.powf(-100.0 / 1_200.0)).abs() < 1e-9);
+        assert!((frequencies[2] - 440.0 * 2.0_f64.powf(100.0 / 1_200.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn spreads_and_blends_copies() {
+        let settings = UnisonSettings {
+            count: 3,
+            detune: 10.0,
+            random_phase: false,
+            ..UnisonSettings::default()
+        };
+        let sides = |blend| {
+            let mut unison = unison(UnisonSettings { blend, ..settings });
+
+            unison.set_frequency(440.0);
+            unison.reset_phase();
+
+            let frames: Vec<[f64; 2]> = (0..1_000).map(|_| unison.next_sample()).collect();
+
+            frames
+                .iter()
+                .map(|[left, right]| (left - right).abs())
+                .sum::<f64>()
+        };
+
+        // The centre copy alone is the same on both sides.
+        assert!(sides(0.0) < 1e-9);
+        assert!(sides(0.5) > 1.0);
+
+        let mut stacked = unison(UnisonSettings {
+            count: 8,
+            ..settings
+        });
+
+        assert!(stacked.is_stereo());
+        stacked.set_settings(UnisonSettings {
+            spread: 0.0,
+            ..settings
+        });
+
+        // The sides stay apart until the spread has glided to nothing.
+        assert!(stacked.is_stereo());
+        (0..4_800).for_each(|_| {
+            stacked.next_sample();
+        });
+        assert!(!stacked.is_stereo());
+    }
+
+    #[test]
+    fn glides_to_new_detunes() {
+        let mut unison = unison(UnisonSettings {
+            count: 2,
+            detune: 0.0,
+            ..UnisonSettings::default()
+        });
+        let sharpest = |unison: &Unison| unison.oscillators[1].frequency();
+
+        unison.set_frequency(440.0);
+        unison.set_settings(UnisonSettings {
+            count: 2,
+            detune: 100.0,
+            ..UnisonSettings::default()
+        });
+
+        assert!(sharpest(&unison) == 440.0);
+
+        unison.next_sample();
+        let first = sharpest(&unison);
+
+        assert!(first > 440.0 && first < 450.0);
+
+        (0..4_800).for_each(|_| {
+            unison.next_sample();
+        });
+        assert!((sharpest(&unison) - 440.0 * 2.0_f64.powf(100.0 / 1_200.0)).abs() < 1e-9);
+    }
+}
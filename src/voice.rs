@@ -0,0 +1,768 @@
+use crate::{
+    channel_layout,
+    envelope::{Adsr, Envelope},
+    filter::{Filter, FilterSettings},
+    modulation::{ModMatrix, ModSources},
+    oscillator::Oscillator,
+    smoothing::{Ramp, Smoothed},
+    tuning::{Tuning, NOTE_COUNT},
+    unison::{Unison, UnisonSettings, MAX_UNISON},
+};
+
+///
+/// Voices are preallocated up to this limit so that changing polyphony on
+/// the audio thread never allocates.
+///
+pub const MAX_POLYPHONY: usize = 32;
+
+///
+/// Which voice a note-on takes over once every voice is busy.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StealPolicy {
+    #[default]
+    Oldest,
+    Quietest,
+    /// Retriggers a voice already playing the same note, even if others are
+    /// free, and otherwise steals the oldest.
+    SameNote,
+}
+
+#[derive(Clone)]
+pub struct Voice {
+    oscillator: Unison,
+    envelope: Envelope,
+    /// Left and right, the right only running while the unison is stereo.
+    filters: [Filter; 2],
+    note: u8,
+    frequency: f64,
+    velocity: f64,
+    started: u64,
+    /// Released by its key but held by the sustain pedal.
+    sustained: bool,
+}
+
+pub struct VoiceAllocator {
+    voices: Vec<Voice>,
+    polyphony: usize,
+    pub policy: StealPolicy,
+    pub matrix: ModMatrix,
+    /// Semitones at full pitch bend.
+    pub bend_range: f64,
+    /// From `-1.0` (left) to `1.0` (right), before per-voice modulation.
+    pub pan: Smoothed,
+    /// Shared by every voice, in octaves (log2 Hz) so that sweeps sound even.
+    cutoff: Smoothed,
+    resonance: Smoothed,
+    sustain: bool,
+    clock: u64,
+    /// Each MIDI note's frequency, or `None` for a key the tuning leaves
+    /// silent.
+    frequencies: [Option<f64>; NOTE_COUNT],
+}
+
+///
+/// Twelve-tone equal temperament with A4 (note 69) at 440 Hz.
+///
+pub fn note_to_frequency(note: u8) -> f64 {
+    440.0 * 2.0_f64.powf((note as f64 - 69.0) / 12.0)
+}
+
+impl Voice {
+    fn new(oscillator: Unison, envelope: Envelope, filter: FilterSettings) -> Self {
+        Self {
+            filters: [(); 2].map(|_| Filter::new(filter, oscillator.sample_rate())),
+            oscillator,
+            envelope,
+            note: 0,
+            frequency: 0.0,
+            velocity: 0.0,
+            started: 0,
+            sustained: false,
+        }
+    }
+
+    ///
+    /// The note this voice is sounding, including while it releases.
+    ///
+    pub fn note(&self) -> Option<u8> {
+        self.is_active().then_some(self.note)
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.envelope.is_idle()
+    }
+
+    pub fn envelope(&self) -> &Envelope {
+        &self.envelope
+    }
+
+    fn level(&self) -> f64 {
+        self.velocity * self.envelope.level()
+    }
+
+    fn start(&mut self, note: u8, frequency: f64, velocity: u8, clock: u64) {
+        // @note: A sounding voice keeps its phase so that stealing it doesn't click
+        if !self.is_active() {
+            self.oscillator.reset_phase();
+
+            for filter in &mut self.filters {
+                filter.reset();
+            }
+        }
+
+        self.frequency = frequency;
+        self.oscillator.set_frequency(self.frequency);
+        self.note = note;
+        self.velocity = velocity as f64 / 127.0;
+        self.started = clock;
+        self.sustained = false;
+        self.envelope.gate_on();
+    }
+
+    fn next_sample(
+        &mut self,
+        matrix: &ModMatrix,
+        sources: &ModSources,
+        bend_range: f64,
+        pan: f64,
+        cutoff: f64,
+    ) -> [f64; 2] {
+        if !self.is_active() {
+            return [0.0; 2];
+        }
+
+        let offsets = matrix.evaluate(&ModSources {
+            envelope: self.envelope.level(),
+            velocity: self.velocity,
+            key_track: (self.note as f64 - 60.0) / 12.0,
+            ..*sources
+        });
+
+        let pitch = offsets.pitch + sources.pitch_bend * bend_range;
+
+        self.oscillator
+            .set_frequency(self.frequency * 2.0_f64.powf(pitch / 12.0));
+        self.oscillator.set_position(offsets.wavetable_position);
+        let cutoff = cutoff * 2.0_f64.powf(offsets.filter_cutoff);
+
+        for filter in &mut self.filters {
+            filter.set_cutoff(cutoff);
+        }
+
+        let amplitude =
+            self.envelope.next_sample() * self.velocity * (1.0 + offsets.amplitude).max(0.0);
+
+        let [left, right] = self.oscillator.next_sample();
+        let left = self.filters[0].process(left);
+        let right = match self.oscillator.is_stereo() {
+            true => self.filters[1].process(right),
+            false => left,
+        };
+        let gains = channel_layout::pan_gains(pan + offsets.pan);
+
+        [left * amplitude * gains[0], right * amplitude * gains[1]]
+    }
+}
+
+impl VoiceAllocator {
+    ///
+    /// Every voice plays its own copy of `oscillator`, retuned per note, and
+    /// shaped by its own copy of `envelope` and a filter built from `filter`.
+    ///
+    pub fn new<O: Oscillator + Send + 'static>(
+        oscillator: O,
+        envelope: Envelope,
+        filter: FilterSettings,
+        polyphony: usize,
+    ) -> Self {
+        let sample_rate = oscillator.sample_rate();
+        let mut voices = vec![
+            Voice::new(
+                Unison::new(Box::new(oscillator), UnisonSettings::default()),
+                envelope,
+                filter
+            );
+            MAX_POLYPHONY
+        ];
+
+        // @note: Scattered by the golden ratio, as xorshift starts out alike from nearby seeds
+        for (index, voice) in voices.iter_mut().enumerate() {
+            voice
+                .oscillator
+                .set_seed((index as u32 + 1).wrapping_mul(0x9E37_79B9));
+        }
+
+        Self {
+            voices,
+            polyphony: polyphony.clamp(1, MAX_POLYPHONY),
+            policy: StealPolicy::default(),
+            matrix: ModMatrix::new(),
+            bend_range: 2.0,
+            pan: Smoothed::new(0.0, sample_rate),
+            cutoff: Smoothed::new(filter.cutoff.log2(), sample_rate),
+            resonance: Smoothed::new(filter.resonance, sample_rate),
+            sustain: false,
+            clock: 0,
+            frequencies: Tuning::default().frequencies(),
+        }
+    }
+
+    pub fn polyphony(&self) -> usize {
+        self.polyphony
+    }
+
+    ///
+    /// Notes playing on voices beyond the new limit are cut.
+    ///
+    pub fn set_polyphony(&mut self, polyphony: usize) {
+        self.polyphony = polyphony.clamp(1, MAX_POLYPHONY);
+
+        for voice in &mut self.voices[self.polyphony..] {
+            voice.envelope.reset();
+        }
+    }
+
+    ///
+    /// Gives every voice its own copy of `oscillator`, cutting any notes
+    /// playing. This allocates, so it belongs on the control side.
+    ///
+    pub fn set_oscillator<O: Oscillator + Send + 'static>(&mut self, oscillator: O) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_oscillator(&oscillator);
+            voice.envelope.reset();
+        }
+    }
+
+    ///
+    /// Trades each voice's unison copies for the next `MAX_UNISON` of
+    /// `oscillators`, cutting any notes playing. The replaced oscillators are
+    /// left in `oscillators`, so the audio thread can hand them back to be
+    /// freed elsewhere.
+    ///
+    pub fn swap_oscillators(&mut self, oscillators: &mut [Box<dyn Oscillator + Send>]) {
+        for (voice, oscillators) in self
+            .voices
+            .iter_mut()
+            .zip(oscillators.chunks_mut(MAX_UNISON))
+        {
+            voice.oscillator.swap_oscillators(oscillators);
+            voice.envelope.reset();
+        }
+    }
+
+    ///
+    /// Restacks every voice, keeping what is playing.
+    ///
+    pub fn set_unison(&mut self, settings: UnisonSettings) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_settings(settings);
+        }
+    }
+
+    pub fn unison(&self) -> &UnisonSettings {
+        self.voices[0].oscillator.settings()
+    }
+
+    ///
+    /// Retunes every voice for a new output rate, keeping what is playing.
+    ///
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_sample_rate(sample_rate);
+            voice.envelope.set_sample_rate(sample_rate);
+            for filter in &mut voice.filters {
+                filter.set_sample_rate(sample_rate);
+            }
+        }
+
+        for smoothed in [&mut self.pan, &mut self.cutoff, &mut self.resonance] {
+            smoothed.set_sample_rate(sample_rate);
+        }
+    }
+
+    ///
+    /// How changes to pan, the filter, the unison stack and the envelope
+    /// sustain level are smoothed.
+    ///
+    pub fn set_smoothing(&mut self, time: f64, ramp: Ramp) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_smoothing(time, ramp);
+            voice.envelope.set_smoothing(time, ramp);
+        }
+
+        for smoothed in [&mut self.pan, &mut self.cutoff, &mut self.resonance] {
+            smoothed.configure(time, ramp);
+        }
+    }
+
+    pub fn set_envelope(&mut self, settings: Adsr) {
+        for voice in &mut self.voices {
+            voice.envelope.settings = settings;
+        }
+    }
+
+    ///
+    /// Glides to the new cutoff and resonance. Changing the filter type
+    /// still switches at once.
+    ///
+    pub fn set_filter(&mut self, settings: FilterSettings) {
+        let current = FilterSettings {
+            cutoff: self.cutoff.value().exp2(),
+            resonance: self.resonance.value(),
+            ..settings
+        };
+
+        for filter in self.voices.iter_mut().flat_map(|voice| &mut voice.filters) {
+            filter.configure(current);
+        }
+
+        self.cutoff.set_target(settings.cutoff.max(1.0).log2());
+        self.resonance.set_target(settings.resonance);
+    }
+
+    pub fn voices(&self) -> &[Voice] {
+        &self.voices[..self.polyphony]
+    }
+
+    pub fn active_voices(&self) -> usize {
+        self.voices()
+            .iter()
+            .filter(|voice| voice.is_active())
+            .count()
+    }
+
+    ///
+    /// A velocity of zero is a note-off, as in MIDI.
+    ///
+    pub fn note_on(&mut self, note: u8, velocity: u8) {
+        if velocity == 0 {
+            return self.note_off(note);
+        }
+
+        let Some(frequency) = self.frequencies[note as usize % NOTE_COUNT] else {
+            return;
+        };
+
+        self.clock += 1;
+
+        let index = self.allocate(note);
+
+        self.voices[index].start(note, frequency, velocity, self.clock);
+    }
+
+    ///
+    /// Retunes `note` from its next note-on, or silences it for `None`.
+    ///
+    pub fn set_note_frequency(&mut self, note: u8, frequency: Option<f64>) {
+        if let Some(slot) = self.frequencies.get_mut(note as usize) {
+            *slot = frequency;
+        }
+    }
+
+    ///
+    /// Moves every held voice playing `note` into its release, or leaves it
+    /// sounding until the sustain pedal comes up.
+    ///
+    pub fn note_off(&mut self, note: u8) {
+        for voice in &mut self.voices[..self.polyphony] {
+            if voice.note == note && voice.envelope.is_gated() {
+                if self.sustain {
+                    voice.sustained = true;
+                } else {
+                    voice.envelope.gate_off();
+                }
+            }
+        }
+    }
+
+    pub fn sustain(&self) -> bool {
+        self.sustain
+    }
+
+    ///
+    /// Lifting the pedal releases every voice whose key is already up.
+    ///
+    pub fn set_sustain(&mut self, sustain: bool) {
+        self.sustain = sustain;
+
+        if sustain {
+            return;
+        }
+
+        for voice in &mut self.voices {
+            if voice.sustained {
+                voice.sustained = false;
+                voice.envelope.gate_off();
+            }
+        }
+    }
+
+    pub fn all_notes_off(&mut self) {
+        for voice in &mut self.voices {
+            voice.sustained = false;
+            voice.envelope.gate_off();
+        }
+    }
+
+    ///
+    /// Silences every voice immediately, skipping their release.
+    ///
+    pub fn all_sound_off(&mut self) {
+        for voice in &mut self.voices {
+            voice.sustained = false;
+            voice.envelope.reset();
+        }
+    }
+
+    ///
+    /// Mixes every voice into a stereo frame, with the global sources (the
+    /// LFOs) in `sources`.
+    ///
+    pub fn next_sample(&mut self, sources: &ModSources) -> [f64; 2] {
+        let pan = self.pan.next_sample();
+        let cutoff = self.cutoff.next_sample().exp2();
+
+        if !self.resonance.is_settled() {
+            let resonance = self.resonance.next_sample();
+
+            for voice in &mut self.voices[..self.polyphony] {
+                for filter in &mut voice.filters {
+                    filter.set_resonance(resonance);
+                }
+            }
+        }
+
+        self.voices[..self.polyphony]
+            .iter_mut()
+            .map(|voice| voice.next_sample(&self.matrix, sources, self.bend_range, pan, cutoff))
+            .fold([0.0; 2], |[left, right], [l, r]| [left + l, right + r])
+    }
+
+    fn allocate(&self, note: u8) -> usize {
+        let voices = self.voices().iter().enumerate();
+
+        if self.policy == StealPolicy::SameNote {
+            if let Some((index, _)) = voices.clone().find(|(_, voice)| voice.note() == Some(note)) {
+                return index;
+            }
+        }
+
+        if let Some((index, _)) = voices.clone().find(|(_, voice)| !voice.is_active()) {
+            return index;
+        }
+
+        let (index, _) = match self.policy {
+            StealPolicy::Quietest => voices.min_by(|(_, a), (_, b)| {
+                a.level()
+                    .total_cmp(&b.level())
+                    .then(a.started.cmp(&b.started))
+            }),
+            StealPolicy::Oldest | StealPolicy::SameNote => {
+                voices.min_by_key(|(_, voice)| voice.started)
+            }
+        }
+        .unwrap();
+
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        envelope::Curve,
+        modulation::{ModDestination, ModRoute, ModSource},
+        waveform::{self, WaveformIter},
+        wavetable::{Interpolation, MipmappedWavetable},
+    };
+
+    use super::*;
+
+    fn allocator(polyphony: usize) -> VoiceAllocator {
+        let table = MipmappedWavetable::new(64, waveform::sine);
+        let gate = Adsr {
+            attack: 0.0,
+            decay: 0.0,
+            sustain: 1.0,
+            release: 0.0,
+            ..Adsr::default()
+        };
+
+        VoiceAllocator::new(
+            table.iter(0.0, 48_000.0, Interpolation::Linear),
+            Envelope::new(gate, 48_000.0),
+            FilterSettings::default(),
+            polyphony,
+        )
+    }
+
+    fn notes(allocator: &VoiceAllocator) -> Vec<Option<u8>> {
+        allocator.voices().iter().map(Voice::note).collect()
+    }
+
+    #[test]
+    fn tunes_equal_temperament() {
+        assert!(note_to_frequency(69) == 440.0);
+        assert!((note_to_frequency(57) - 220.0).abs() < 1e-9);
+        assert!((note_to_frequency(60) - 261.625_565).abs() < 1e-6);
+    }
+
+    #[test]
+    fn plays_and_releases_notes() {
+        let mut allocator = allocator(4);
+
+        allocator.note_on(60, 100);
+        allocator.note_on(64, 100);
+        allocator.note_on(67, 100);
+
+        assert!(allocator.active_voices() == 3);
+        assert!((0..64).any(|_| allocator.next_sample(&ModSources::default()) != [0.0; 2]));
+
+        allocator.note_off(64);
+        allocator.next_sample(&ModSources::default());
+        assert!(notes(&allocator) == vec![Some(60), None, Some(67), None]);
+
+        allocator.note_on(60, 0);
+        allocator.note_off(67);
+        allocator.next_sample(&ModSources::default());
+        assert!(allocator.active_voices() == 0);
+        assert!((0..64).all(|_| allocator.next_sample(&ModSources::default()) == [0.0; 2]));
+    }
+
+    #[test]
+    fn voices_draw_their_own_unison_phases() {
+        let mut allocator = allocator(2);
+
+        allocator.set_unison(UnisonSettings {
+            count: 4,
+            ..UnisonSettings::default()
+        });
+        allocator.note_on(60, 100);
+        allocator.note_on(64, 100);
+
+        // Both stacks read at their starting phases, before pitch tells them apart.
+        let [first, second] = [0, 1].map(|index| allocator.voices[index].oscillator.next_sample());
+
+        assert!(first != second);
+    }
+
+    #[test]
+    fn steals_oldest() {
+        let mut allocator = allocator(2);
+
+        allocator.note_on(60, 100);
+        allocator.note_on(62, 100);
+        allocator.note_on(64, 100);
+
+        assert!(notes(&allocator) == vec![Some(64), Some(62)]);
+    }
+
+    #[test]
+    fn steals_quietest() {
+        let mut allocator = allocator(2);
+
+        allocator.policy = StealPolicy::Quietest;
+        allocator.note_on(60, 100);
+        allocator.note_on(62, 20);
+        allocator.next_sample(&ModSources::default());
+        allocator.note_on(64, 100);
+
+        assert!(notes(&allocator) == vec![Some(60), Some(64)]);
+    }
+
+    #[test]
+    fn retriggers_same_note() {
+        let mut allocator = allocator(3);
+
+        allocator.policy = StealPolicy::SameNote;
+        allocator.note_on(60, 100);
+        allocator.note_on(62, 100);
+        allocator.note_on(60, 50);
+
+        assert!(notes(&allocator) == vec![Some(60), Some(62), None]);
+        assert!(allocator.voices()[0].velocity == 50.0 / 127.0);
+    }
+
+    #[test]
+    fn limits_polyphony() {
+        let mut allocator = allocator(4);
+
+        for note in 60..64 {
+            allocator.note_on(note, 100);
+        }
+
+        allocator.set_polyphony(2);
+        assert!(allocator.active_voices() == 2);
+
+        allocator.set_polyphony(100);
+        assert!(allocator.polyphony() == MAX_POLYPHONY);
+    }
+
+    #[test]
+    fn releases_through_envelope() {
+        let mut allocator = allocator(2);
+
+        allocator.set_envelope(Adsr {
+            release: 0.01,
+            curve: Curve::Linear,
+            ..allocator.voices()[0].envelope().settings
+        });
+
+        allocator.note_on(60, 127);
+        allocator.next_sample(&ModSources::default());
+        allocator.note_off(60);
+
+        assert!(allocator.voices()[0].note() == Some(60));
+
+        for _ in 0..=480 {
+            allocator.next_sample(&ModSources::default());
+        }
+
+        assert!(allocator.active_voices() == 0);
+    }
+
+    #[test]
+    fn routes_modulation_per_voice() {
+        let mut allocator = allocator(2);
+
+        allocator
+            .matrix
+            .add(ModRoute {
+                source: ModSource::Lfo(0),
+                destination: ModDestination::Pitch,
+                amount: 12.0,
+            })
+            .unwrap();
+
+        allocator.note_on(57, 127);
+
+        let sources = ModSources {
+            lfos: [1.0, 0.0],
+            ..ModSources::default()
+        };
+
+        allocator.next_sample(&sources);
+
+        assert!((allocator.voices[0].oscillator.frequency() - 440.0).abs() < 1e-9);
+
+        allocator.next_sample(&ModSources::default());
+
+        assert!((allocator.voices[0].oscillator.frequency() - 220.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn modulates_amplitude_to_silence() {
+        let mut allocator = allocator(1);
+
+        allocator
+            .matrix
+            .add(ModRoute {
+                source: ModSource::Velocity,
+                destination: ModDestination::Amplitude,
+                amount: -1.0,
+            })
+            .unwrap();
+
+        allocator.note_on(60, 127);
+
+        assert!((0..64).all(|_| allocator.next_sample(&ModSources::default()) == [0.0; 2]));
+    }
+
+    #[test]
+    fn sustain_pedal_holds_released_notes() {
+        let mut allocator = allocator(4);
+
+        allocator.note_on(60, 100);
+        allocator.set_sustain(true);
+        allocator.note_on(64, 100);
+        allocator.note_off(60);
+        allocator.note_off(64);
+        allocator.next_sample(&ModSources::default());
+
+        assert!(allocator
+            .voices()
+            .iter()
+            .all(|voice| !voice.is_active() || voice.envelope().is_gated()));
+        assert!(allocator.active_voices() == 2);
+
+        allocator.set_sustain(false);
+        allocator.next_sample(&ModSources::default());
+        allocator.next_sample(&ModSources::default());
+
+        assert!(allocator.active_voices() == 0);
+    }
+
+    #[test]
+    fn pitch_bend_spans_bend_range() {
+        let mut allocator = allocator(1);
+
+        allocator.bend_range = 12.0;
+        allocator.note_on(57, 100);
+        allocator.next_sample(&ModSources {
+            pitch_bend: 1.0,
+            ..ModSources::default()
+        });
+
+        assert!((allocator.voices()[0].oscillator.frequency() - 440.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn swaps_oscillator_type() {
+        let mut allocator = allocator(2);
+
+        allocator.note_on(60, 100);
+        allocator.set_oscillator(WaveformIter::new(waveform::square, 0.0, 48_000.0));
+        assert!(allocator.active_voices() == 0);
+
+        allocator.note_on(69, 127);
+
+        // Half a cycle high then half low, at 440 Hz.
+        let samples: Vec<f64> = (0..109)
+            .map(|_| allocator.next_sample(&ModSources::default())[0])
+            .collect();
+
+        assert!(samples[4..50].iter().all(|sample| *sample > 0.9));
+        assert!(samples[59..105].iter().all(|sample| *sample < -0.9));
+    }
+
+    #[test]
+    fn follows_sample_rate_change() {
+        let mut allocator = allocator(1);
+
+        allocator.set_oscillator(WaveformIter::new(waveform::square, 0.0, 48_000.0));
+        allocator.set_sample_rate(96_000.0);
+        allocator.note_on(69, 127);
+
+        // Still 440 Hz, so each half cycle now spans twice the samples.
+        let samples: Vec<f64> = (0..218)
+            .map(|_| allocator.next_sample(&ModSources::default())[0])
+            .collect();
+
+        assert!(samples[8..100].iter().all(|sample| *sample > 0.9));
+        assert!(samples[118..210].iter().all(|sample| *sample < -0.9));
+    }
+
+    #[test]
+    fn pans_each_voice() {
+        let mut allocator = allocator(2);
+
+        allocator.pan.reset(-1.0);
+        allocator
+            .matrix
+            .add(ModRoute {
+                source: ModSource::KeyTrack,
+                destination: ModDestination::Pan,
+                amount: 2.0,
+            })
+            .unwrap();
+
+        // Middle C stays hard left, while an octave up is pushed hard right.
+        allocator.note_on(60, 127);
+        assert!((0..64).all(|_| allocator.next_sample(&ModSources::default())[1].abs() < 1e-12));
+
+        allocator.all_sound_off();
+        allocator.note_on(72, 127);
+        assert!((0..64).all(|_| allocator.next_sample(&ModSources::default())[0].abs() < 1e-12));
+    }
+}
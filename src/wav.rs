@@ -0,0 +1,380 @@
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleFormat {
+    Pcm8,
+    Pcm16,
+    Pcm24,
+    Pcm32,
+    Float32,
+    Float64,
+}
+
+///
+/// A decoded RIFF/WAVE file with interleaved samples normalised to `-1.0..=1.0`.
+///
+#[derive(Clone, Debug)]
+pub struct Wav {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub format: SampleFormat,
+    pub samples: Vec<f64>,
+    /// Single-cycle length declared by a Serum-style `clm ` chunk.
+    pub cycle_length: Option<usize>,
+}
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+impl SampleFormat {
+    pub fn bits(&self) -> u16 {
+        match self {
+            SampleFormat::Pcm8 => 8,
+            SampleFormat::Pcm16 => 16,
+            SampleFormat::Pcm24 => 24,
+            SampleFormat::Pcm32 | SampleFormat::Float32 => 32,
+            SampleFormat::Float64 => 64,
+        }
+    }
+
+    fn bytes(&self) -> usize {
+        self.bits() as usize / 8
+    }
+
+    fn decode(&self, bytes: &[u8]) -> f64 {
+        match self {
+            SampleFormat::Pcm8 => (bytes[0] as f64 - 128.0) / 128.0,
+            SampleFormat::Pcm16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64 / 32_768.0,
+            SampleFormat::Pcm24 => {
+                // Shift into the top of an i32 so the sign bit is extended.
+                let value = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8;
+                value as f64 / 8_388_608.0
+            }
+            SampleFormat::Pcm32 => {
+                i32::from_le_bytes(bytes.try_into().unwrap()) as f64 / 2_147_483_648.0
+            }
+            SampleFormat::Float32 => f32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+            SampleFormat::Float64 => f64::from_le_bytes(bytes.try_into().unwrap()),
+        }
+    }
+
+    fn encode(&self, sample: f64, bytes: &mut Vec<u8>) {
+        let scale = |max: f64| (sample * max).round().clamp(-max, max - 1.0);
+
+        match self {
+            SampleFormat::Pcm8 => bytes.push((scale(128.0) + 128.0) as u8),
+            SampleFormat::Pcm16 => bytes.extend((scale(32_768.0) as i16).to_le_bytes()),
+            SampleFormat::Pcm24 => {
+                bytes.extend(&(scale(8_388_608.0) as i32).to_le_bytes()[..3]);
+            }
+            SampleFormat::Pcm32 => bytes.extend((scale(2_147_483_648.0) as i32).to_le_bytes()),
+            SampleFormat::Float32 => bytes.extend((sample as f32).to_le_bytes()),
+            SampleFormat::Float64 => bytes.extend(sample.to_le_bytes()),
+        }
+    }
+
+    fn tag(&self) -> u16 {
+        match self {
+            SampleFormat::Float32 | SampleFormat::Float64 => WAVE_FORMAT_IEEE_FLOAT,
+            _ => WAVE_FORMAT_PCM,
+        }
+    }
+}
+
+impl Wav {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let bytes = std::fs::read(path.as_ref())
+            .map_err(|e| format!("{}: {e}", path.as_ref().display()))?;
+
+        Self::parse(&bytes)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err("Not a RIFF/WAVE file".to_string());
+        }
+
+        let mut format = None;
+        let mut data = None;
+        let mut cycle_length = None;
+        let mut offset = 12;
+
+        while offset + 8 <= bytes.len() {
+            let id = &bytes[offset..offset + 4];
+            let size =
+                u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().unwrap()) as usize;
+            let body = &bytes[offset + 8..(offset + 8 + size).min(bytes.len())];
+
+            match id {
+                b"fmt " => format = Some(parse_format(body)?),
+                b"data" => data = Some(body),
+                b"clm " => cycle_length = parse_clm(body),
+                _ => {}
+            }
+
+            // Chunks are word-aligned, with a pad byte after odd sizes.
+            offset += 8 + size + size % 2;
+        }
+
+        let (channels, sample_rate, format) = format.ok_or("Missing fmt chunk")?;
+        let data = data.ok_or("Missing data chunk")?;
+
+        let samples = data
+            .chunks_exact(format.bytes())
+            .map(|bytes| format.decode(bytes))
+            .collect();
+
+        Ok(Self {
+            sample_rate,
+            channels,
+            format,
+            samples,
+            cycle_length,
+        })
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        std::fs::write(path.as_ref(), self.to_bytes())
+            .map_err(|e| format!("{}: {e}", path.as_ref().display()))
+    }
+
+    ///
+    /// Encodes as a canonical 44-byte-header WAV, clipping to `-1.0..=1.0`
+    /// for integer formats.
+    ///
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let block_align = self.channels * self.format.bits() / 8;
+
+        let mut data = Vec::with_capacity(self.samples.len() * self.format.bytes());
+
+        for sample in &self.samples {
+            self.format.encode(*sample, &mut data);
+        }
+
+        let mut bytes = Vec::with_capacity(44 + data.len() + 1);
+
+        bytes.extend(b"RIFF");
+        bytes.extend(((36 + data.len() + data.len() % 2) as u32).to_le_bytes());
+        bytes.extend(b"WAVE");
+        bytes.extend(b"fmt ");
+        bytes.extend(16_u32.to_le_bytes());
+        bytes.extend(self.format.tag().to_le_bytes());
+        bytes.extend(self.channels.to_le_bytes());
+        bytes.extend(self.sample_rate.to_le_bytes());
+        bytes.extend((self.sample_rate * block_align as u32).to_le_bytes());
+        bytes.extend(block_align.to_le_bytes());
+        bytes.extend(self.format.bits().to_le_bytes());
+        bytes.extend(b"data");
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes.extend(&data);
+
+        if data.len() % 2 == 1 {
+            bytes.push(0);
+        }
+
+        bytes
+    }
+
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels as usize
+    }
+
+    ///
+    /// Averages all channels down to one.
+    ///
+    pub fn mono(&self) -> Vec<f64> {
+        self.samples
+            .chunks_exact(self.channels as usize)
+            .map(|frame| frame.iter().sum::<f64>() / frame.len() as f64)
+            .collect()
+    }
+}
+
+fn parse_format(body: &[u8]) -> Result<(u16, u32, SampleFormat), String> {
+    if body.len() < 16 {
+        return Err("Truncated fmt chunk".to_string());
+    }
+
+    let read_u16 = |at: usize| u16::from_le_bytes([body[at], body[at + 1]]);
+
+    let mut tag = read_u16(0);
+    let channels = read_u16(2);
+    let sample_rate = u32::from_le_bytes(body[4..8].try_into().unwrap());
+    let bits = read_u16(14);
+
+    if tag == WAVE_FORMAT_EXTENSIBLE {
+        if body.len() < 26 {
+            return Err("Truncated extensible fmt chunk".to_string());
+        }
+
+        // The sub-format GUID starts with the plain format tag.
+        tag = read_u16(24);
+    }
+
+    if channels == 0 {
+        return Err("WAV file has no channels".to_string());
+    }
+
+    let format = match (tag, bits) {
+        (WAVE_FORMAT_PCM, 8) => SampleFormat::Pcm8,
+        (WAVE_FORMAT_PCM, 16) => SampleFormat::Pcm16,
+        (WAVE_FORMAT_PCM, 24) => SampleFormat::Pcm24,
+        (WAVE_FORMAT_PCM, 32) => SampleFormat::Pcm32,
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => SampleFormat::Float32,
+        (WAVE_FORMAT_IEEE_FLOAT, 64) => SampleFormat::Float64,
+        (tag, bits) => return Err(format!("Unsupported WAV format {tag:#06x} at {bits} bits")),
+    };
+
+    Ok((channels, sample_rate, format))
+}
+
+///
+/// Serum writes e.g. `<!>2048 10000000 wavetable (www.xferrecords.com)`.
+///
+fn parse_clm(body: &[u8]) -> Option<usize> {
+    let text = std::str::from_utf8(body).ok()?;
+    let digits: String = text
+        .strip_prefix("<!>")?
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+
+    digits.parse().ok().filter(|length| *length > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wav_bytes(tag: u16, channels: u16, bits: u16, data: &[u8], extra: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let block_align = channels * bits / 8;
+
+        bytes.extend(b"RIFF");
+        bytes.extend(((4 + 24 + 8 + data.len() + extra.len()) as u32).to_le_bytes());
+        bytes.extend(b"WAVE");
+        bytes.extend(b"fmt ");
+        bytes.extend(16_u32.to_le_bytes());
+        bytes.extend(tag.to_le_bytes());
+        bytes.extend(channels.to_le_bytes());
+        bytes.extend(48_000_u32.to_le_bytes());
+        bytes.extend((48_000 * block_align as u32).to_le_bytes());
+        bytes.extend(block_align.to_le_bytes());
+        bytes.extend(bits.to_le_bytes());
+        bytes.extend(extra);
+        bytes.extend(b"data");
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes.extend(data);
+
+        bytes
+    }
+
+    #[test]
+    fn reads_asset() {
+        let wav = Wav::read(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/assets/sine_261hz.wav"
+        ))
+        .unwrap();
+
+        assert!(wav.sample_rate == 48_000);
+        assert!(wav.channels == 2);
+        assert!(wav.format == SampleFormat::Pcm16);
+        assert!(wav.frames() == 329_143);
+        assert!(wav.samples.iter().all(|sample| sample.abs() <= 1.0));
+    }
+
+    #[test]
+    fn decodes_pcm24() {
+        let data = [0xFF, 0xFF, 0x7F, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00];
+        let wav = Wav::parse(&wav_bytes(WAVE_FORMAT_PCM, 1, 24, &data, &[])).unwrap();
+
+        assert!(wav.format == SampleFormat::Pcm24);
+        assert!((wav.samples[0] - 1.0).abs() < 1e-6);
+        assert!(wav.samples[1] == -1.0);
+        assert!(wav.samples[2] == 0.0);
+    }
+
+    #[test]
+    fn decodes_float_and_mixes_to_mono() {
+        let data: Vec<u8> = [0.5_f32, -0.25, 1.0, 0.0]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+
+        let wav = Wav::parse(&wav_bytes(WAVE_FORMAT_IEEE_FLOAT, 2, 32, &data, &[])).unwrap();
+
+        assert!(wav.format == SampleFormat::Float32);
+        assert!(wav.mono() == vec![0.125, 0.5]);
+    }
+
+    #[test]
+    fn reads_clm_cycle_length() {
+        let clm = b"<!>2048 10000000 wavetable (www.xferrecords.com)";
+        let mut extra = Vec::new();
+
+        extra.extend(b"clm ");
+        extra.extend((clm.len() as u32).to_le_bytes());
+        extra.extend(clm);
+        extra.resize(extra.len() + clm.len() % 2, 0);
+
+        let wav = Wav::parse(&wav_bytes(WAVE_FORMAT_PCM, 1, 16, &[0, 0], &extra)).unwrap();
+
+        assert!(wav.cycle_length == Some(2048));
+    }
+
+    #[test]
+    fn round_trips_every_format() {
+        let samples = vec![0.0, 0.5, -0.5, 0.999, -1.0, 0.123];
+
+        for (format, tolerance) in [
+            (SampleFormat::Pcm8, 1.0 / 128.0),
+            (SampleFormat::Pcm16, 1.0 / 32_768.0),
+            (SampleFormat::Pcm24, 1.0 / 8_388_608.0),
+            (SampleFormat::Pcm32, 1e-9),
+            (SampleFormat::Float32, 1e-7),
+            (SampleFormat::Float64, 0.0),
+        ] {
+            let wav = Wav {
+                sample_rate: 44_100,
+                channels: 2,
+                format,
+                samples: samples.clone(),
+                cycle_length: None,
+            };
+
+            let decoded = Wav::parse(&wav.to_bytes()).unwrap();
+
+            assert!(decoded.format == format);
+            assert!(decoded.sample_rate == 44_100);
+            assert!(decoded.channels == 2);
+
+            for (a, b) in decoded.samples.iter().zip(&samples) {
+                assert!((a - b).abs() <= tolerance, "{format:?} {a} {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn clips_integer_formats() {
+        let wav = Wav {
+            sample_rate: 44_100,
+            channels: 1,
+            format: SampleFormat::Pcm16,
+            samples: vec![1.5, -1.5],
+            cycle_length: None,
+        };
+
+        let decoded = Wav::parse(&wav.to_bytes()).unwrap();
+
+        assert!(decoded.samples[0] == 32_767.0 / 32_768.0);
+        assert!(decoded.samples[1] == -1.0);
+    }
+
+    #[test]
+    fn rejects_unsupported() {
+        assert!(Wav::parse(b"RIFF\0\0\0\0WAVE").is_err());
+        assert!(Wav::parse(&wav_bytes(0x0055, 1, 16, &[], &[])).is_err());
+    }
+}
@@ -1,5 +1,27 @@
 use std::f64::consts::PI;
 
+use crate::oscillator::Oscillator;
+
+///
+/// One cycle of a periodic shape every `2π` of phase. `increment` is the
+/// phase advanced per sample, which the band-limited shapes use to smooth
+/// their discontinuities and the naive ones ignore.
+///
+/// Any `Fn(f64) -> f64`, such as `sine`, is a waveform.
+///
+pub trait Waveform {
+    fn sample(&mut self, phase: f64, increment: f64) -> f64;
+}
+
+impl<F> Waveform for F
+where
+    F: FnMut(f64) -> f64,
+{
+    fn sample(&mut self, phase: f64, _increment: f64) -> f64 {
+        self(phase)
+    }
+}
+
 pub fn sine(phase: f64) -> f64 {
     phase.sin()
 }
@@ -7,3 +29,423 @@ pub fn sine(phase: f64) -> f64 {
 pub fn sawtooth(phase: f64) -> f64 {
     ((phase + PI) / PI) % 2.0 - 1.0
 }
+
+pub fn square(phase: f64) -> f64 {
+    pulse(phase, 0.5)
+}
+
+///
+/// High for the first `width` of the cycle, then low.
+///
+pub fn pulse(phase: f64, width: f64) -> f64 {
+    if phase.rem_euclid(2.0 * PI) < 2.0 * PI * width {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+pub fn triangle(phase: f64) -> f64 {
+    2.0 / PI * phase.sin().asin()
+}
+
+///
+/// Naive pulse wave with an adjustable duty cycle.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pulse {
+    /// From `0.0` to `1.0`, where `0.5` is a square wave.
+    pub width: f64,
+}
+
+///
+/// Sawtooth with its reset smoothed by a polynomial band-limited step
+/// (PolyBLEP), in phase with `sawtooth`.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BlepSawtooth;
+
+///
+/// Pulse with both edges smoothed by PolyBLEP, in phase with `pulse`.
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlepPulse {
+    pub width: f64,
+}
+
+///
+/// Triangle with its corners smoothed by a polynomial band-limited ramp
+/// (PolyBLAMP), in phase with `triangle`.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BlampTriangle;
+
+///
+/// Flat spectrum noise in `-1.0..=1.0`.
+///
+#[derive(Clone, Debug)]
+pub struct WhiteNoise {
+    seed: u32,
+}
+
+///
+/// Noise falling 3 dB per octave, from white noise through Paul Kellet's
+/// economy filter.
+///
+#[derive(Clone, Debug)]
+pub struct PinkNoise {
+    white: WhiteNoise,
+    state: [f64; 3],
+}
+
+///
+/// Noise falling 6 dB per octave, from leakily integrated white noise.
+///
+#[derive(Clone, Debug)]
+pub struct BrownNoise {
+    white: WhiteNoise,
+    level: f64,
+}
+
+///
+/// Plays a waveform directly as an oscillator, without a table.
+///
+#[derive(Clone, Debug)]
+pub struct WaveformIter<W> {
+    pub waveform: W,
+    pub frequency: f64,
+    sample_rate: f64,
+    /// Position in the current cycle, from `0.0` to `1.0`.
+    phase: f64,
+}
+
+///
+/// Residual between a band-limited and a naive unit step at `t = 0`, over
+/// one sample either side. `t` is the position in the cycle and `dt` the
+/// cycle fraction per sample.
+///
+fn poly_blep(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let t = t / dt;
+
+        2.0 * t - t * t - 1.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt;
+
+        t * t + 2.0 * t + 1.0
+    } else {
+        0.0
+    }
+}
+
+///
+/// Residual between a band-limited and a naive ramp at `t = 0`, the
+/// integral of `poly_blep` over samples.
+///
+fn poly_blamp(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let t = t / dt - 1.0;
+
+        -t * t * t / 3.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt + 1.0;
+
+        t * t * t / 3.0
+    } else {
+        0.0
+    }
+}
+
+///
+/// Converts radians into the cycle position and the per-sample step that
+/// the polynomial corrections work in.
+///
+fn normalise(phase: f64, increment: f64) -> (f64, f64) {
+    (
+        (phase / (2.0 * PI)).rem_euclid(1.0),
+        (increment / (2.0 * PI)).abs().min(0.5),
+    )
+}
+
+impl Waveform for Pulse {
+    fn sample(&mut self, phase: f64, _increment: f64) -> f64 {
+        pulse(phase, self.width)
+    }
+}
+
+impl Waveform for BlepSawtooth {
+    fn sample(&mut self, phase: f64, increment: f64) -> f64 {
+        let (t, dt) = normalise(phase, increment);
+
+        // The naive sawtooth resets half way through the cycle.
+        let t = (t + 0.5).fract();
+
+        2.0 * t - 1.0 - poly_blep(t, dt)
+    }
+}
+
+impl Waveform for BlepPulse {
+    fn sample(&mut self, phase: f64, increment: f64) -> f64 {
+        let (t, dt) = normalise(phase, increment);
+        let width = self.width.clamp(0.0, 1.0);
+
+        pulse(phase, width) + poly_blep(t, dt) - poly_blep((t - width).rem_euclid(1.0), dt)
+    }
+}
+
+impl Waveform for BlampTriangle {
+    fn sample(&mut self, phase: f64, increment: f64) -> f64 {
+        let (t, dt) = normalise(phase, increment);
+
+        // The slope swings by 8 per cycle at the peak (t = 0.25) and the
+        // trough (t = 0.75), and `poly_blamp` is twice the unit residual.
+        let naive = 1.0 - 4.0 * ((t + 0.25).fract() - 0.5).abs();
+
+        naive
+            + 4.0
+                * dt
+                * (poly_blamp((t - 0.75).rem_euclid(1.0), dt)
+                    - poly_blamp((t - 0.25).rem_euclid(1.0), dt))
+    }
+}
+
+impl Default for WhiteNoise {
+    fn default() -> Self {
+        Self::new(0x9E37_79B9)
+    }
+}
+
+impl WhiteNoise {
+    pub fn new(seed: u32) -> Self {
+        Self { seed: seed.max(1) }
+    }
+
+    ///
+    /// Xorshift, so noise stays deterministic and allocation-free.
+    ///
+    pub fn next_sample(&mut self) -> f64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+
+        self.seed as f64 / u32::MAX as f64 * 2.0 - 1.0
+    }
+}
+
+impl Waveform for WhiteNoise {
+    fn sample(&mut self, _phase: f64, _increment: f64) -> f64 {
+        self.next_sample()
+    }
+}
+
+impl Default for PinkNoise {
+    fn default() -> Self {
+        Self::new(0x9E37_79B9)
+    }
+}
+
+impl PinkNoise {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            white: WhiteNoise::new(seed),
+            state: [0.0; 3],
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f64 {
+        let white = self.white.next_sample();
+
+        self.state[0] = 0.99765 * self.state[0] + white * 0.0990460;
+        self.state[1] = 0.96300 * self.state[1] + white * 0.2965164;
+        self.state[2] = 0.57000 * self.state[2] + white * 1.0526913;
+
+        // @note: Scaled so that peaks rarely reach the clamp
+        ((self.state.iter().sum::<f64>() + white * 0.1848) * 0.11).clamp(-1.0, 1.0)
+    }
+}
+
+impl Waveform for PinkNoise {
+    fn sample(&mut self, _phase: f64, _increment: f64) -> f64 {
+        self.next_sample()
+    }
+}
+
+impl Default for BrownNoise {
+    fn default() -> Self {
+        Self::new(0x9E37_79B9)
+    }
+}
+
+impl BrownNoise {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            white: WhiteNoise::new(seed),
+            level: 0.0,
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f64 {
+        // The leak keeps the walk from drifting away from zero.
+        self.level = (0.995 * self.level + 0.03 * self.white.next_sample()).clamp(-1.0, 1.0);
+        self.level
+    }
+}
+
+impl Waveform for BrownNoise {
+    fn sample(&mut self, _phase: f64, _increment: f64) -> f64 {
+        self.next_sample()
+    }
+}
+
+impl<W: Waveform> WaveformIter<W> {
+    pub fn new(waveform: W, frequency: f64, sample_rate: f64) -> Self {
+        Self {
+            waveform,
+            frequency,
+            sample_rate,
+            phase: 0.0,
+        }
+    }
+}
+
+impl<W: Waveform> Iterator for WaveformIter<W> {
+    type Item = f64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let increment = self.frequency / self.sample_rate;
+        let sample = self
+            .waveform
+            .sample(2.0 * PI * self.phase, 2.0 * PI * increment);
+
+        self.phase = (self.phase + increment).rem_euclid(1.0);
+
+        Some(sample)
+    }
+}
+
+impl<W> Oscillator for WaveformIter<W>
+where
+    W: Waveform + Clone + Send + 'static,
+{
+    fn frequency(&self) -> f64 {
+        self.frequency
+    }
+
+    fn set_frequency(&mut self, frequency: f64) {
+        self.frequency = frequency;
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+    }
+
+    fn phase(&self) -> f64 {
+        self.phase
+    }
+
+    fn sync(&mut self, phase: f64) {
+        self.phase = phase.rem_euclid(1.0);
+    }
+
+    fn next_sample(&mut self) -> f64 {
+        self.next().unwrap()
+    }
+
+    fn clone_box(&self) -> Box<dyn Oscillator + Send> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fft;
+
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 48_000.0;
+
+    ///
+    /// Harmonic to alias power in dB. The oscillator runs at exactly
+    /// `FUNDAMENTAL_BIN` cycles per transform, and as that is coprime with the
+    /// transform length every alias lands between the harmonics.
+    ///
+    fn alias_ratio<W: Waveform>(waveform: W) -> f64 {
+        const LENGTH: usize = 4_096;
+        const FUNDAMENTAL_BIN: usize = 103;
+
+        let frequency = FUNDAMENTAL_BIN as f64 * SAMPLE_RATE / LENGTH as f64;
+        let mut re: Vec<f64> = WaveformIter::new(waveform, frequency, SAMPLE_RATE)
+            .take(LENGTH)
+            .collect();
+        let mut im = vec![0.0; LENGTH];
+
+        fft::fft(&mut re, &mut im);
+
+        let (harmonics, aliases) = (1..LENGTH / 2).fold((0.0, 0.0), |(h, a), bin| {
+            let power = re[bin] * re[bin] + im[bin] * im[bin];
+
+            if bin % FUNDAMENTAL_BIN == 0 {
+                (h + power, a)
+            } else {
+                (h, a + power)
+            }
+        });
+
+        10.0 * (harmonics / aliases).log10()
+    }
+
+    #[test]
+    fn shapes_match_naive_functions() {
+        for i in 0..64 {
+            let phase = 2.0 * PI * (i as f64 + 0.5) / 64.0;
+
+            assert!(Pulse { width: 0.5 }.sample(phase, 0.0) == square(phase));
+            assert!((BlepSawtooth.sample(phase, 0.0) - sawtooth(phase)).abs() < 1e-12);
+            assert!(
+                (BlepPulse { width: 0.3 }.sample(phase, 0.0) - pulse(phase, 0.3)).abs() < 1e-12
+            );
+            assert!((BlampTriangle.sample(phase, 0.0) - triangle(phase)).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn band_limited_shapes_alias_less() {
+        let naive = alias_ratio(sawtooth);
+        let blep = alias_ratio(BlepSawtooth);
+        assert!(blep > naive + 10.0, "{naive} {blep}");
+
+        let naive = alias_ratio(Pulse { width: 0.25 });
+        let blep = alias_ratio(BlepPulse { width: 0.25 });
+        assert!(blep > naive + 10.0, "{naive} {blep}");
+
+        let naive = alias_ratio(triangle);
+        let blamp = alias_ratio(BlampTriangle);
+        assert!(blamp > naive + 10.0, "{naive} {blamp}");
+    }
+
+    #[test]
+    fn noise_is_bounded_and_coloured() {
+        let mut white = WhiteNoise::default();
+        let mut pink = PinkNoise::default();
+        let mut brown = BrownNoise::default();
+
+        // Mean absolute difference between neighbours, which falls as the
+        // spectrum tilts towards the low end.
+        let roughness = |samples: Vec<f64>| {
+            assert!(samples.iter().all(|sample| sample.abs() <= 1.0));
+
+            samples.windows(2).map(|w| (w[1] - w[0]).abs()).sum::<f64>()
+                / samples.iter().map(|sample| sample.abs()).sum::<f64>()
+        };
+
+        let white = roughness((0..48_000).map(|_| white.next_sample()).collect());
+        let pink = roughness((0..48_000).map(|_| pink.next_sample()).collect());
+        let brown = roughness((0..48_000).map(|_| brown.next_sample()).collect());
+
+        assert!(white > pink && pink > brown, "{white} {pink} {brown}");
+    }
+}
@@ -1,14 +1,81 @@
-use std::f64::consts::PI;
+use std::{f64::consts::PI, sync::Arc};
 
+use crate::{fft, oscillator::Oscillator, wav::Wav, waveform::Waveform};
+
+///
+/// A whole cycle of a fixed-point phase, `2^64`.
+///
+const CYCLE: f64 = 18_446_744_073_709_551_616.0;
+
+#[derive(Clone)]
 pub struct Wavetable {
     samples: Vec<f64>,
 }
 
-pub struct WavetableIter<'a> {
+///
+/// One band-limited copy of a waveform per octave, so that playback never
+/// produces harmonics above the Nyquist frequency of the output.
+///
+pub struct MipmappedWavetable {
+    levels: Arc<[Wavetable]>,
+}
+
+///
+/// A sequence of mipmapped single-cycle frames that playback can morph
+/// between, stored frame-major as `frames * levels` tables.
+///
+pub struct WavetableBank {
+    tables: Arc<[Wavetable]>,
+    levels: usize,
+}
+
+///
+/// One partial of a periodic waveform, `amplitude * sin(k * phase + phase)`
+/// for harmonic `k`. Harmonic `0` is the DC offset and ignores its phase.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Harmonic {
+    pub amplitude: f64,
+    pub phase: f64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Interpolation {
+    #[default]
+    None,
+    Linear,
+    CubicHermite,
+    WindowedSinc,
+}
+
+///
+/// How a `WavetableIter` keeps its place in the cycle.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum PhaseAccumulator {
+    /// A fractional table index, wrapped with `%` every sample, so rounding
+    /// builds up and depends on the table's length.
+    #[default]
+    Float,
+    /// A fixed-point fraction of a cycle that wraps exactly by overflowing,
+    /// the same whatever the table's length.
+    Fixed,
+}
+
+#[derive(Clone)]
+pub struct WavetableIter {
     pub frequency: f64,
+    /// Morph position across the frames of a bank, from `0.0` to `1.0`.
+    pub position: f64,
     index: f64,
+    /// The place in the cycle under `PhaseAccumulator::Fixed`, where `2^64`
+    /// is a whole cycle.
+    phase: u64,
+    accumulator: PhaseAccumulator,
     sample_rate: f64,
-    wavetable: &'a Wavetable,
+    interpolation: Interpolation,
+    tables: Arc<[Wavetable]>,
+    levels: usize,
 }
 
 impl Wavetable {
@@ -20,45 +87,635 @@ impl Wavetable {
         }
     }
 
-    pub fn fill<F>(&mut self, waveform: F)
-    where
-        F: Fn(f64) -> f64 + 'static,
-    {
+    ///
+    /// Samples one cycle of `waveform`, stepping by one table slot at a time.
+    ///
+    pub fn fill<W: Waveform>(&mut self, mut waveform: W) {
+        let increment = 2.0 * PI / self.samples.capacity() as f64;
+
         for i in 0..self.samples.capacity() {
-            let phase = 2.0 * PI * i as f64 / self.samples.capacity() as f64;
-            let sample = waveform(phase);
+            let sample = waveform.sample(increment * i as f64, increment);
             self.samples.push(sample);
         }
     }
 
-    pub fn iter(&self, frequency: f64, sample_rate: f64) -> WavetableIter {
+    ///
+    /// Additive synthesis, where `harmonics[k]` is the `k`th harmonic.
+    /// Harmonics at or above `length / 2` would alias and are dropped.
+    ///
+    pub fn from_harmonics(length: usize, harmonics: &[Harmonic]) -> Self {
+        assert!(length > 0);
+
+        let dc = harmonics.first().map_or(0.0, |dc| dc.amplitude);
+
+        let samples = (0..length)
+            .map(|i| {
+                let phase = 2.0 * PI * i as f64 / length as f64;
+
+                harmonics
+                    .iter()
+                    .enumerate()
+                    .take(length.div_ceil(2))
+                    .skip(1)
+                    .fold(dc, |sample, (k, harmonic)| {
+                        sample + harmonic.amplitude * (k as f64 * phase + harmonic.phase).sin()
+                    })
+            })
+            .collect();
+
+        Self { samples }
+    }
+
+    ///
+    /// Same result as `from_harmonics`, via an inverse FFT. `length` must be a
+    /// power of two.
+    ///
+    pub fn from_harmonics_fft(length: usize, harmonics: &[Harmonic]) -> Self {
+        assert!(length.is_power_of_two());
+
+        let mut re = vec![0.0; length];
+        let mut im = vec![0.0; length];
+
+        for (k, harmonic) in harmonics.iter().enumerate().take(length.div_ceil(2)) {
+            if k == 0 {
+                re[0] = harmonic.amplitude * length as f64;
+                continue;
+            }
+
+            let scale = harmonic.amplitude * length as f64 / 2.0;
+            let (sin, cos) = harmonic.phase.sin_cos();
+
+            re[k] = scale * sin;
+            im[k] = -scale * cos;
+            re[length - k] = re[k];
+            im[length - k] = -im[k];
+        }
+
+        fft::ifft(&mut re, &mut im);
+
+        Self { samples: re }
+    }
+
+    ///
+    /// Analyses the table into harmonics `0..=len / 2`, the inverse of
+    /// `from_harmonics`.
+    ///
+    pub fn harmonics(&self) -> Vec<Harmonic> {
+        let length = self.samples.len();
+
+        let bins: Vec<(f64, f64)> = if length.is_power_of_two() {
+            let mut re = self.samples.clone();
+            let mut im = vec![0.0; length];
+
+            fft::fft(&mut re, &mut im);
+
+            re.into_iter().zip(im).take(length / 2 + 1).collect()
+        } else {
+            (0..=length / 2)
+                .map(|k| {
+                    self.samples
+                        .iter()
+                        .enumerate()
+                        .fold((0.0, 0.0), |(re, im), (i, sample)| {
+                            let phase = 2.0 * PI * (k * i) as f64 / length as f64;
+                            (re + sample * phase.cos(), im - sample * phase.sin())
+                        })
+                })
+                .collect()
+        };
+
+        bins.into_iter()
+            .enumerate()
+            .map(|(k, (re, im))| {
+                if k == 0 {
+                    return Harmonic {
+                        amplitude: re / length as f64,
+                        phase: 0.0,
+                    };
+                }
+
+                let scale = if 2 * k == length { 1.0 } else { 2.0 } / length as f64;
+
+                Harmonic {
+                    amplitude: scale * re.hypot(im),
+                    phase: re.atan2(-im),
+                }
+            })
+            .collect()
+    }
+
+    ///
+    /// The iterator keeps its own copy of the table, so it can outlive `self`.
+    ///
+    pub fn iter(
+        &self,
+        frequency: f64,
+        sample_rate: f64,
+        interpolation: Interpolation,
+    ) -> WavetableIter {
         WavetableIter {
             frequency,
+            position: 0.0,
             index: 0.0,
+            phase: 0,
+            accumulator: PhaseAccumulator::default(),
             sample_rate,
-            wavetable: &self,
+            interpolation,
+            tables: Arc::new([self.clone()]),
+            levels: 1,
+        }
+    }
+
+    ///
+    /// Reads the table at a fractional `index`, wrapping around both ends.
+    ///
+    pub fn sample(&self, index: f64, interpolation: Interpolation) -> f64 {
+        let whole = index.floor();
+        let fraction = index - whole;
+        let i = whole as isize;
+
+        match interpolation {
+            Interpolation::None => self.at(i),
+            Interpolation::Linear => {
+                let (a, b) = (self.at(i), self.at(i + 1));
+
+                a + (b - a) * fraction
+            }
+            Interpolation::CubicHermite => {
+                let (y0, y1, y2, y3) = (self.at(i - 1), self.at(i), self.at(i + 1), self.at(i + 2));
+
+                let c1 = 0.5 * (y2 - y0);
+                let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+                let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+
+                ((c3 * fraction + c2) * fraction + c1) * fraction + y1
+            }
+            Interpolation::WindowedSinc => {
+                let taps = SINC_TAPS as isize;
+
+                // Dividing by the weights' sum gives the kernel unity gain at
+                // every fraction, where the truncated sinc alone falls short.
+                let (sum, weights) =
+                    (1 - taps..=taps).fold((0.0, 0.0), |(sum, weights), offset| {
+                        let x = offset as f64 - fraction;
+                        let weight = sinc(x) * blackman_harris(x, SINC_TAPS as f64);
+
+                        (sum + self.at(i + offset) * weight, weights + weight)
+                    });
+
+                sum / weights
+            }
         }
     }
 
+    fn at(&self, index: isize) -> f64 {
+        self.samples[index.rem_euclid(self.samples.len() as isize) as usize]
+    }
+
     pub fn len(&self) -> usize {
         self.samples.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+}
+
+impl MipmappedWavetable {
+    pub fn new<W: Waveform>(length: usize, waveform: W) -> Self {
+        let mut table = Wavetable::new(length);
+
+        table.fill(waveform);
+
+        Self::from_wavetable(&table)
+    }
+
+    ///
+    /// Level `n` keeps harmonics `1..=max_harmonic(n)`, halving every octave
+    /// until only the fundamental remains.
+    ///
+    pub fn from_wavetable(wavetable: &Wavetable) -> Self {
+        assert!(wavetable.len() >= 4, "Wavetable too short to band-limit");
+
+        let length = wavetable.len();
+        let harmonics = wavetable.harmonics();
+
+        let mut levels = Vec::new();
+        let mut max_harmonic = length / 2 - 1;
+
+        loop {
+            let harmonics = &harmonics[..=max_harmonic];
+
+            levels.push(if length.is_power_of_two() {
+                Wavetable::from_harmonics_fft(length, harmonics)
+            } else {
+                Wavetable::from_harmonics(length, harmonics)
+            });
+
+            if max_harmonic <= 1 {
+                break;
+            }
+
+            max_harmonic /= 2;
+        }
+
+        Self {
+            levels: levels.into(),
+        }
+    }
+
+    pub fn iter(
+        &self,
+        frequency: f64,
+        sample_rate: f64,
+        interpolation: Interpolation,
+    ) -> WavetableIter {
+        WavetableIter {
+            frequency,
+            position: 0.0,
+            index: 0.0,
+            phase: 0,
+            accumulator: PhaseAccumulator::default(),
+            sample_rate,
+            interpolation,
+            tables: self.levels.clone(),
+            levels: self.levels.len(),
+        }
+    }
+
+    pub fn level(&self, index: usize) -> &Wavetable {
+        &self.levels[index]
+    }
+
+    pub fn levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels[0].is_empty()
+    }
+}
+
+impl WavetableBank {
+    pub fn from_frames(frames: &[Wavetable]) -> Self {
+        assert!(
+            !frames.is_empty(),
+            "Wavetable bank needs at least one frame"
+        );
+        assert!(
+            frames.iter().all(|frame| frame.len() == frames[0].len()),
+            "Wavetable bank frames differ in length"
+        );
+
+        let mut tables = Vec::new();
+        let mut levels = 0;
+
+        for frame in frames {
+            let mipmap = MipmappedWavetable::from_wavetable(frame);
+
+            levels = mipmap.levels();
+            tables.extend(mipmap.levels.iter().cloned());
+        }
+
+        Self {
+            tables: tables.into(),
+            levels,
+        }
+    }
+
+    ///
+    /// Slices consecutive single cycles of `frame_length` samples out of
+    /// `samples`, ignoring any trailing partial cycle.
+    ///
+    pub fn from_samples(samples: &[f64], frame_length: usize) -> Self {
+        assert!(frame_length > 0);
+
+        let frames: Vec<Wavetable> = samples
+            .chunks_exact(frame_length)
+            .map(|cycle| Wavetable {
+                samples: cycle.to_vec(),
+            })
+            .collect();
+
+        Self::from_frames(&frames)
+    }
+
+    ///
+    /// Builds a bank from a (mixed to mono) WAV file. Cycles are sliced at
+    /// `cycle_length` if given, else at the file's `clm ` length, else at a
+    /// detected pitch period resampled to `DETECTED_CYCLE_LENGTH` samples.
+    ///
+    pub fn from_wav(wav: &Wav, cycle_length: Option<usize>) -> Result<Self, String> {
+        let samples = wav.mono();
+
+        if let Some(cycle_length) = cycle_length.or(wav.cycle_length) {
+            if cycle_length < 4 {
+                return Err("Cycle length must be at least 4 samples".to_string());
+            }
+
+            if samples.len() < cycle_length {
+                return Err(format!(
+                    "WAV file shorter than one {cycle_length} sample cycle"
+                ));
+            }
+
+            return Ok(Self::from_samples(&samples, cycle_length));
+        }
+
+        let period = detect_cycle_length(&samples).ok_or("Could not detect a pitch period")?;
+        let cycles = (((samples.len() - 1) as f64 / period) as usize).min(MAX_DETECTED_CYCLES);
+
+        let frames: Vec<Wavetable> = (0..cycles)
+            .map(|cycle| Wavetable {
+                samples: (0..DETECTED_CYCLE_LENGTH)
+                    .map(|i| {
+                        let position =
+                            (cycle as f64 + i as f64 / DETECTED_CYCLE_LENGTH as f64) * period;
+                        let index = position.floor() as usize;
+                        let fraction = position - index as f64;
+
+                        let a = samples[index];
+                        let b = samples[(index + 1).min(samples.len() - 1)];
+
+                        a + (b - a) * fraction
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Ok(Self::from_frames(&frames))
+    }
+
+    pub fn iter(
+        &self,
+        frequency: f64,
+        sample_rate: f64,
+        interpolation: Interpolation,
+    ) -> WavetableIter {
+        WavetableIter {
+            frequency,
+            position: 0.0,
+            index: 0.0,
+            phase: 0,
+            accumulator: PhaseAccumulator::default(),
+            sample_rate,
+            interpolation,
+            tables: self.tables.clone(),
+            levels: self.levels,
+        }
+    }
+
+    pub fn frame(&self, frame: usize, level: usize) -> &Wavetable {
+        &self.tables[frame * self.levels + level]
+    }
+
+    pub fn frames(&self) -> usize {
+        self.tables.len() / self.levels
+    }
+
+    pub fn levels(&self) -> usize {
+        self.levels
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables[0].is_empty()
+    }
 }
 
-impl<'a> Iterator for WavetableIter<'a> {
+impl WavetableIter {
+    ///
+    /// The first (richest) level whose highest harmonic stays below Nyquist.
+    ///
+    pub fn level(&self) -> usize {
+        let nyquist = self.sample_rate / 2.0;
+        let length = self.tables[0].len();
+
+        for level in 0..self.levels {
+            let max_harmonic = ((length / 2).saturating_sub(1) >> level).max(1);
+
+            if max_harmonic as f64 * self.frequency.abs() < nyquist {
+                return level;
+            }
+        }
+
+        self.levels - 1
+    }
+
+    pub fn accumulator(&self) -> PhaseAccumulator {
+        self.accumulator
+    }
+
+    ///
+    /// Switches how the phase is kept, carrying on from the same place.
+    ///
+    pub fn set_accumulator(&mut self, accumulator: PhaseAccumulator) {
+        let phase = self.phase();
+
+        self.accumulator = accumulator;
+        self.sync(phase);
+    }
+
+    ///
+    /// Moves to `position` before producing the next sample, for per-sample
+    /// morph modulation.
+    ///
+    pub fn next_at(&mut self, position: f64) -> f64 {
+        self.position = position;
+
+        self.next().unwrap()
+    }
+
+    ///
+    /// Reads `offset` cycles away from the current phase before moving on at
+    /// the set frequency, for phase modulation.
+    ///
+    pub fn next_offset(&mut self, offset: f64) -> f64 {
+        let level = self.level();
+        let frames = self.tables.len() / self.levels;
+
+        let position = self.position.clamp(0.0, 1.0) * (frames - 1) as f64;
+        let frame = (position.floor() as usize).min(frames - 1);
+        let fraction = position - frame as f64;
+
+        let wavetable = &self.tables[frame * self.levels + level];
+        let length = wavetable.len() as f64;
+        let index = match self.accumulator {
+            PhaseAccumulator::Float => self.index,
+            PhaseAccumulator::Fixed => self.phase as f64 / CYCLE * length,
+        } + offset * length;
+        let mut sample = wavetable.sample(index, self.interpolation);
+
+        if fraction > 0.0 {
+            let next = &self.tables[(frame + 1) * self.levels + level];
+
+            sample += (next.sample(index, self.interpolation) - sample) * fraction;
+        }
+
+        match self.accumulator {
+            PhaseAccumulator::Float => {
+                self.index += self.frequency * length / self.sample_rate;
+                self.index = self.index.rem_euclid(length);
+            }
+            PhaseAccumulator::Fixed => {
+                let increment = (self.frequency / self.sample_rate).rem_euclid(1.0) * CYCLE;
+
+                self.phase = self.phase.wrapping_add(increment as u64);
+            }
+        }
+
+        sample
+    }
+}
+
+impl Iterator for WavetableIter {
     type Item = f64;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let sample = self.wavetable.samples[self.index.floor() as usize];
+        Some(self.next_offset(0.0))
+    }
+}
 
-        self.index += self.frequency * self.wavetable.len() as f64 / self.sample_rate as f64;
-        self.index %= self.wavetable.len() as f64;
+impl Oscillator for WavetableIter {
+    fn frequency(&self) -> f64 {
+        self.frequency
+    }
 
-        Some(sample)
+    fn set_frequency(&mut self, frequency: f64) {
+        self.frequency = frequency;
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate;
+    }
+
+    fn phase(&self) -> f64 {
+        match self.accumulator {
+            PhaseAccumulator::Float => self.index / self.tables[0].len() as f64,
+            PhaseAccumulator::Fixed => self.phase as f64 / CYCLE,
+        }
+    }
+
+    fn sync(&mut self, phase: f64) {
+        let length = self.tables[0].len() as f64;
+
+        self.index = (phase * length).rem_euclid(length);
+        self.phase = (phase.rem_euclid(1.0) * CYCLE) as u64;
+    }
+
+    fn set_position(&mut self, position: f64) {
+        self.position = position;
+    }
+
+    fn next_sample(&mut self) -> f64 {
+        self.next().unwrap()
+    }
+
+    fn clone_box(&self) -> Box<dyn Oscillator + Send> {
+        Box::new(self.clone())
+    }
+}
+
+const DETECTED_CYCLE_LENGTH: usize = 2048;
+const MAX_DETECTED_CYCLES: usize = 256;
+
+///
+/// Estimates the fundamental period of `samples`, in (fractional) samples,
+/// using the YIN difference function over a window from the middle of the
+/// signal so that fades at either end don't skew it.
+///
+pub fn detect_cycle_length(samples: &[f64]) -> Option<f64> {
+    const WINDOW: usize = 4096;
+    const THRESHOLD: f64 = 0.1;
+
+    let window = WINDOW.min(samples.len() / 2);
+
+    if window < 4 {
+        return None;
+    }
+
+    let start = (samples.len() - 2 * window) / 2;
+    let signal = &samples[start..start + 2 * window];
+
+    let difference: Vec<f64> = (0..window)
+        .map(|lag| {
+            (0..window)
+                .map(|i| (signal[i] - signal[i + lag]).powi(2))
+                .sum()
+        })
+        .collect();
+
+    // Cumulative mean normalised difference, so that lag 0 isn't a minimum.
+    let mut normalised = vec![1.0; window];
+    let mut total = 0.0;
+
+    for lag in 1..window {
+        total += difference[lag];
+        normalised[lag] = if total > 0.0 {
+            difference[lag] * lag as f64 / total
+        } else {
+            1.0
+        };
+    }
+
+    let mut lag = 2;
+
+    while lag < window - 1 {
+        if normalised[lag] < THRESHOLD {
+            while lag + 1 < window - 1 && normalised[lag + 1] < normalised[lag] {
+                lag += 1;
+            }
+
+            let (a, b, c) = (normalised[lag - 1], normalised[lag], normalised[lag + 1]);
+            let curvature = a - 2.0 * b + c;
+            let offset = if curvature > 0.0 {
+                0.5 * (a - c) / curvature
+            } else {
+                0.0
+            };
+
+            return Some(lag as f64 + offset);
+        }
+
+        lag += 1;
+    }
+
+    None
+}
+
+///
+/// Half-width of the windowed-sinc kernel, in samples.
+///
+const SINC_TAPS: usize = 8;
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
     }
 }
 
+fn blackman_harris(x: f64, half_width: f64) -> f64 {
+    let n = (x + half_width) / (2.0 * half_width);
+
+    0.35875 - 0.48829 * (2.0 * PI * n).cos() + 0.14128 * (4.0 * PI * n).cos()
+        - 0.01168 * (6.0 * PI * n).cos()
+}
+
 #[cfg(test)]
+#[allow(clippy::legacy_numeric_constants, clippy::unnecessary_cast)]
 mod tests {
     use crate::waveform;
     use std::f64::EPSILON;
@@ -101,7 +758,7 @@ mod tests {
 
         sine_table.fill(waveform::sine);
 
-        let mut iter = sine_table.iter(frequency, sample_rate);
+        let mut iter = sine_table.iter(frequency, sample_rate, Interpolation::None);
 
         let index_increment = frequency * length as f64 / sample_rate;
 
@@ -113,4 +770,363 @@ mod tests {
         assert!(iter.next().unwrap() == sine_table.samples[b]);
         assert!(iter.next().unwrap() == sine_table.samples[c]);
     }
+
+    #[test]
+    fn fixed_accumulator_ignores_table_length() {
+        let frequency = 261.625_565;
+        let phase = |length, accumulator| {
+            let mut iter = MipmappedWavetable::new(length, waveform::sine).iter(
+                frequency,
+                48_000.0,
+                Interpolation::Linear,
+            );
+
+            iter.set_accumulator(accumulator);
+            iter.by_ref().take(100_000).for_each(drop);
+            iter.phase()
+        };
+        let expected = (100_000.0 * frequency / 48_000.0).fract();
+
+        assert!(phase(2048, PhaseAccumulator::Fixed) == phase(1000, PhaseAccumulator::Fixed));
+        assert!((phase(1000, PhaseAccumulator::Fixed) - expected).abs() < 1e-12);
+        assert!((phase(1000, PhaseAccumulator::Float) - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn mipmap_levels_halve_harmonics() {
+        let mipmap = MipmappedWavetable::new(64, waveform::sawtooth);
+
+        assert!(mipmap.levels() == 5);
+
+        for level in 0..mipmap.levels() {
+            let max_harmonic = 31 >> level;
+            let harmonics = mipmap.level(level).harmonics();
+
+            for (harmonic, Harmonic { amplitude, .. }) in harmonics.iter().enumerate() {
+                let magnitude = *amplitude;
+
+                if harmonic > max_harmonic {
+                    assert!(magnitude < 1e-9, "level {level} harmonic {harmonic}");
+                } else if harmonic > 0 {
+                    assert!(magnitude > 1e-3, "level {level} harmonic {harmonic}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mipmap_preserves_sine() {
+        let mut sine_table = Wavetable::new(64);
+
+        sine_table.fill(waveform::sine);
+
+        let mipmap = MipmappedWavetable::from_wavetable(&sine_table);
+
+        for level in 0..mipmap.levels() {
+            for (a, b) in mipmap.level(level).samples.iter().zip(&sine_table.samples) {
+                assert!((a - b).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn mipmap_selects_level_below_nyquist() {
+        let mipmap = MipmappedWavetable::new(2048, waveform::sawtooth);
+        let sample_rate = 44_100.0;
+
+        let mut iter = mipmap.iter(20.0, sample_rate, Interpolation::None);
+        assert!(iter.level() == 0);
+
+        for frequency in [55.0, 220.0, 880.0, 3_520.0, 10_000.0] {
+            iter.frequency = frequency;
+
+            let level = iter.level();
+            let max_harmonic = (1023 >> level).max(1);
+
+            assert!(max_harmonic as f64 * frequency < sample_rate / 2.0);
+            assert!(level == 0 || (max_harmonic * 2 + 1) as f64 * frequency >= sample_rate / 2.0);
+        }
+    }
+
+    fn signal_to_noise(interpolation: Interpolation) -> f64 {
+        let frequency = 441.0;
+        let sample_rate = 44_100.0;
+
+        let mut sine_table = Wavetable::new(64);
+
+        sine_table.fill(waveform::sine);
+
+        let iter = sine_table.iter(frequency, sample_rate, interpolation);
+
+        let (signal, noise) = iter.take(sample_rate as usize).enumerate().fold(
+            (0.0, 0.0),
+            |(signal, noise), (n, sample)| {
+                let phase = 2.0 * PI * frequency * n as f64 / sample_rate;
+                let reference = waveform::sine(phase);
+
+                (
+                    signal + reference * reference,
+                    noise + (sample - reference).powi(2),
+                )
+            },
+        );
+
+        10.0 * (signal / noise).log10()
+    }
+
+    #[test]
+    fn interpolation_signal_to_noise() {
+        let none = signal_to_noise(Interpolation::None);
+        let linear = signal_to_noise(Interpolation::Linear);
+        let cubic = signal_to_noise(Interpolation::CubicHermite);
+        let sinc = signal_to_noise(Interpolation::WindowedSinc);
+
+        assert!(none > 20.0);
+        assert!(linear > 55.0);
+        assert!(cubic > 90.0);
+        assert!(sinc > 110.0);
+        assert!(none < linear && linear < cubic && cubic < sinc);
+    }
+
+    #[test]
+    fn plays_single_sample_tables() {
+        let mut table = Wavetable::new(1);
+
+        table.fill(waveform::sawtooth);
+
+        let mut iter = table.iter(440.0, 48_000.0, Interpolation::Linear);
+
+        assert!(iter.level() == 0);
+        assert!(iter.next().is_some());
+    }
+
+    #[test]
+    fn interpolation_hits_table_points() {
+        let mut sawtooth_table = Wavetable::new(64);
+
+        sawtooth_table.fill(waveform::sawtooth);
+
+        for interpolation in [
+            Interpolation::None,
+            Interpolation::Linear,
+            Interpolation::CubicHermite,
+            Interpolation::WindowedSinc,
+        ] {
+            for i in 0..64 {
+                let sample = sawtooth_table.sample(i as f64, interpolation);
+
+                assert!((sample - sawtooth_table.samples[i]).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn harmonics_round_trip() {
+        let harmonics = [
+            Harmonic {
+                amplitude: 0.25,
+                phase: 0.0,
+            },
+            Harmonic {
+                amplitude: 1.0,
+                phase: 0.0,
+            },
+            Harmonic {
+                amplitude: 0.0,
+                phase: 0.0,
+            },
+            Harmonic {
+                amplitude: 0.5,
+                phase: PI / 3.0,
+            },
+            Harmonic {
+                amplitude: 0.125,
+                phase: -PI / 2.0,
+            },
+        ];
+
+        for length in [32, 48] {
+            let table = Wavetable::from_harmonics(length, &harmonics);
+            let analysed = table.harmonics();
+
+            assert!(analysed.len() == length / 2 + 1);
+
+            for (k, harmonic) in analysed.iter().enumerate() {
+                let expected = harmonics.get(k).copied().unwrap_or_default();
+
+                assert!((harmonic.amplitude - expected.amplitude).abs() < 1e-9);
+
+                if k > 0 && expected.amplitude > 0.0 {
+                    assert!((harmonic.phase - expected.phase).abs() < 1e-9);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn harmonics_fft_matches_additive() {
+        let harmonics: Vec<Harmonic> = (0..40)
+            .map(|k| Harmonic {
+                amplitude: 1.0 / (k + 1) as f64,
+                phase: k as f64 * 0.3,
+            })
+            .collect();
+
+        let additive = Wavetable::from_harmonics(64, &harmonics);
+        let fft = Wavetable::from_harmonics_fft(64, &harmonics);
+
+        for (a, b) in additive.samples.iter().zip(&fft.samples) {
+            assert!((a - b).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn sawtooth_spectrum() {
+        let mut sawtooth_table = Wavetable::new(2048);
+
+        sawtooth_table.fill(waveform::sawtooth);
+
+        let harmonics = sawtooth_table.harmonics();
+
+        assert!(harmonics[0].amplitude.abs() < 1e-3);
+
+        for (k, harmonic) in harmonics.iter().enumerate().take(9).skip(1) {
+            let expected = 2.0 / (PI * k as f64);
+            let sign = if k % 2 == 1 { 1.0 } else { -1.0 };
+
+            assert!((harmonic.amplitude - expected).abs() < 1e-2);
+            assert!((harmonic.phase.cos() - sign).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn bank_morphs_between_frames() {
+        let mut sine_table = Wavetable::new(64);
+        let mut sawtooth_table = Wavetable::new(64);
+
+        sine_table.fill(waveform::sine);
+        sawtooth_table.fill(waveform::sawtooth);
+
+        let sawtooth = MipmappedWavetable::from_wavetable(&sawtooth_table);
+        let bank = WavetableBank::from_frames(&[sine_table, sawtooth_table]);
+
+        assert!(bank.frames() == 2);
+        assert!(bank.levels() == 5);
+
+        // One table step per sample keeps the iterator on exact table points.
+        let mut iter = bank.iter(44_100.0 / 64.0, 44_100.0, Interpolation::None);
+
+        for i in 0..64 {
+            let sine = bank.frame(0, 0).samples[i];
+            let sawtooth = sawtooth.level(0).samples[i];
+
+            let position = i as f64 / 63.0;
+            let expected = sine + (sawtooth - sine) * position;
+
+            assert!((iter.next_at(position) - expected).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn bank_slices_frames_from_samples() {
+        let samples: Vec<f64> = (0..256 * 3 + 17)
+            .map(|i| (i / 256) as f64 + (2.0 * PI * (i % 256) as f64 / 256.0).sin())
+            .collect();
+
+        let bank = WavetableBank::from_samples(&samples, 256);
+
+        assert!(bank.frames() == 3);
+        assert!(bank.len() == 256);
+
+        for frame in 0..3 {
+            let harmonics = bank.frame(frame, 0).harmonics();
+
+            assert!((harmonics[0].amplitude - frame as f64).abs() < 1e-9);
+            assert!((harmonics[1].amplitude - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn bank_clamps_position() {
+        let mut sine_table = Wavetable::new(64);
+
+        sine_table.fill(waveform::sine);
+
+        let bank = WavetableBank::from_frames(&[sine_table]);
+        let mut iter = bank.iter(440.0, 44_100.0, Interpolation::Linear);
+
+        iter.position = 2.0;
+        assert!(iter.next().is_some());
+
+        iter.position = -1.0;
+        assert!(iter.next().is_some());
+    }
+
+    #[test]
+    fn detects_asset_cycle_length() {
+        let wav = Wav::read(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/assets/sine_261hz.wav"
+        ))
+        .unwrap();
+        let period = detect_cycle_length(&wav.mono()).unwrap();
+
+        assert!((wav.sample_rate as f64 / period - 261.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn bank_from_wav_detects_cycles() {
+        let period = 100.5;
+        let samples: Vec<f64> = (0..2_010)
+            .map(|i| 0.5 * waveform::sawtooth(2.0 * PI * i as f64 / period))
+            .collect();
+
+        let wav = Wav {
+            sample_rate: 48_000,
+            channels: 1,
+            format: crate::wav::SampleFormat::Float64,
+            samples,
+            cycle_length: None,
+        };
+
+        let bank = WavetableBank::from_wav(&wav, None).unwrap();
+
+        assert!(bank.frames() == 19);
+        assert!(bank.len() == DETECTED_CYCLE_LENGTH);
+
+        let harmonics = bank.frame(0, 0).harmonics();
+
+        assert!((harmonics[1].amplitude - 1.0 / PI).abs() < 0.02);
+
+        let sliced = WavetableBank::from_wav(&wav, Some(201)).unwrap();
+
+        assert!(sliced.frames() == 10);
+        assert!(sliced.len() == 201);
+    }
+
+    #[test]
+    fn bank_from_wav_rejects_short_cycles() {
+        let clm = b"<!>3 10000000 wavetable";
+        let mut bytes = Wav {
+            sample_rate: 48_000,
+            channels: 1,
+            format: crate::wav::SampleFormat::Float64,
+            samples: vec![0.0; 300],
+            cycle_length: None,
+        }
+        .to_bytes();
+
+        bytes.extend(b"clm ");
+        bytes.extend((clm.len() as u32).to_le_bytes());
+        bytes.extend(clm);
+
+        let wav = Wav::parse(&bytes).unwrap();
+
+        assert!(wav.cycle_length == Some(3));
+
+        assert!(WavetableBank::from_wav(&wav, None).is_err());
+        assert!(WavetableBank::from_wav(&wav, Some(0)).is_err());
+        assert!(WavetableBank::from_wav(&wav, Some(2)).is_err());
+        assert!(WavetableBank::from_wav(&wav, Some(4)).is_ok());
+    }
 }